@@ -0,0 +1,67 @@
+// Interpreter loop throughput on arithmetic-heavy code: `cargo bench`.
+// Prints instructions per second for each kernel, best of a few runs.
+
+use std::time::{Duration, Instant};
+
+use vmma31::{asm, VM};
+
+const RUNS: usize = 5;
+
+// Sum i * 3 ^ i for i below 2,000,000, with the counter and total on the stack
+const ARITHMETIC: &str = "
+    push 0          # total
+    push 0          # i
+loop:
+    dup 0
+    push 3
+    mul
+    dup 4
+    xor
+    dup 8
+    add
+    swap 0 8
+    pop 4
+    push 1
+    add
+    push 2000000
+    iflt next
+    exit 0
+next:
+    pop 4
+    goto loop
+";
+
+// Fixed-point multiply and shifts, counting down from 1,000,000
+const MIXED: &str = "
+    push 1000000
+loop:
+    push 0x10000
+    push 0x18000
+    qmul 0
+    push 3
+    lsl
+    push 1
+    asr
+    pop 4
+    push 1
+    sub
+    ifnz loop
+    exit 0
+";
+
+fn main() {
+    for (name, source) in [("arithmetic", ARITHMETIC), ("mixed", MIXED)] {
+        let bytecode = asm::assemble_program(source).expect("benchmark source assembles");
+        let mut best = Duration::MAX;
+        let mut instructions = 0;
+        for _ in 0..RUNS {
+            let mut vm = VM::from_bytes(&bytecode).expect("benchmark loads");
+            let start = Instant::now();
+            vm.run().expect("benchmark runs without faulting");
+            best = best.min(start.elapsed());
+            instructions = vm.instructions_executed();
+        }
+        let rate = instructions as f64 / best.as_secs_f64() / 1e6;
+        println!("{:<12} {:>11} instructions  {:>8.2?}  {:>7.1} M instructions/s", name, instructions, best, rate);
+    }
+}
@@ -0,0 +1,146 @@
+#ifndef VMMA31_H
+#define VMMA31_H
+
+/* Generated by cbindgen from src/ffi.rs; don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct VM VM;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Create a VM with ram_size bytes of memory, or return NULL if ram_size is
+ * zero, not a multiple of 4 or larger than the VM allows.
+ */
+VM *vmma31_vm_new(uintptr_t ram_size);
+
+/**
+ * Load a program file's contents (magic bytes first). Returns 0 on success
+ * and -1 if the bytes aren't a valid program or don't fit in memory.
+ *
+ * # Safety
+ *
+ * vm must come from vmma31_vm_new, and bytes must point to len readable bytes.
+ */
+int32_t vmma31_load(VM *vm, const uint8_t *bytes, uintptr_t len);
+
+/**
+ * Run the loaded program to completion. Returns its exit code, or 134 if it
+ * faulted, as the CLI does.
+ *
+ * # Safety
+ *
+ * vm must come from vmma31_vm_new.
+ */
+int32_t vmma31_run(VM *vm);
+
+/**
+ * Execute one instruction. Returns 1 while the program is still running, 0
+ * once it has finished (see vmma31_exit_code) and -1 if it faulted.
+ *
+ * # Safety
+ *
+ * vm must come from vmma31_vm_new.
+ */
+int32_t vmma31_step(VM *vm);
+
+/**
+ * Feed input and stinput the lines of text, one per read, instead of
+ * stdin. Reads past the last line get an empty line, as at the end of
+ * stdin. text needn't end in a NUL; invalid UTF-8 is replaced. Returns 0,
+ * or -1 if text is NULL.
+ *
+ * # Safety
+ *
+ * vm must come from vmma31_vm_new, and text must point to len readable bytes.
+ */
+int32_t vmma31_set_input(VM *vm, const uint8_t *text, uintptr_t len);
+
+/**
+ * Keep the program's output from now on, for vmma31_take_output, instead
+ * of writing it to stdout.
+ *
+ * # Safety
+ *
+ * vm must come from vmma31_vm_new.
+ */
+void vmma31_capture_output(VM *vm);
+
+/**
+ * Bytes of captured output not taken yet.
+ *
+ * # Safety
+ *
+ * vm must come from vmma31_vm_new.
+ */
+uintptr_t vmma31_output_len(const VM *vm);
+
+/**
+ * Move up to len bytes of captured output, oldest first, into out. Returns
+ * the number of bytes moved. That stops short of len rather than split a
+ * UTF-8 character, so len should be at least 4.
+ *
+ * # Safety
+ *
+ * vm must come from vmma31_vm_new, and out must point to len writable bytes.
+ */
+uintptr_t vmma31_take_output(VM *vm, uint8_t *out, uintptr_t len);
+
+/**
+ * Exit code of a finished program (0 until it exits).
+ *
+ * # Safety
+ *
+ * vm must come from vmma31_vm_new.
+ */
+int32_t vmma31_exit_code(const VM *vm);
+
+/**
+ * Stack pointer: the address of the top word. The stack runs from here to
+ * the end of memory, and is empty when this equals vmma31_memory_size.
+ *
+ * # Safety
+ *
+ * vm must come from vmma31_vm_new.
+ */
+uintptr_t vmma31_sp(const VM *vm);
+
+/**
+ * Size of the VM's memory in bytes.
+ *
+ * # Safety
+ *
+ * vm must come from vmma31_vm_new.
+ */
+uintptr_t vmma31_memory_size(const VM *vm);
+
+/**
+ * Copy up to len bytes of VM memory starting at addr into out. Returns the
+ * number of bytes copied, which is less than len past the end of memory.
+ *
+ * # Safety
+ *
+ * vm must come from vmma31_vm_new, and out must point to len writable bytes.
+ */
+uintptr_t vmma31_read_memory(const VM *vm, uintptr_t addr, uint8_t *out, uintptr_t len);
+
+/**
+ * Destroy a VM. Passing NULL does nothing.
+ *
+ * # Safety
+ *
+ * vm must come from vmma31_vm_new and not be used again afterwards.
+ */
+void vmma31_free(VM *vm);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* VMMA31_H */
@@ -0,0 +1,156 @@
+"""Python bindings for the VMMA31 interpreter.
+
+Wraps the C ABI in the shared library built with the ffi feature:
+
+    cargo build --release --features ffi
+
+The library is looked up in $VMMA31_LIB, then in ../target/release next to
+this file. Guest input and output use the process's stdin and stdout unless
+run is given input lines, or set_input and capture_output are called.
+
+    from vmma31 import Vmma31
+    vm = Vmma31()
+    vm.load(open("add.v", "rb").read())
+    exit_code, output = vm.run(input=["5", "7"])
+    print(output, vm.stack(), vm.memory[0x10])
+"""
+
+import ctypes
+import os
+import sys
+
+FAULT_EXIT_CODE = 134
+DEFAULT_RAM_SIZE = 4096
+
+
+def _library_path():
+    if "VMMA31_LIB" in os.environ:
+        return os.environ["VMMA31_LIB"]
+    name = {"win32": "vmma31.dll", "darwin": "libvmma31.dylib"}.get(sys.platform, "libvmma31.so")
+    return os.path.join(os.path.dirname(os.path.abspath(__file__)), "..", "target", "release", name)
+
+
+_lib = ctypes.CDLL(_library_path())
+_VM = ctypes.c_void_p
+for _name, _args, _result in [
+    ("vmma31_vm_new", [ctypes.c_size_t], _VM),
+    ("vmma31_load", [_VM, ctypes.c_char_p, ctypes.c_size_t], ctypes.c_int32),
+    ("vmma31_run", [_VM], ctypes.c_int32),
+    ("vmma31_step", [_VM], ctypes.c_int32),
+    ("vmma31_set_input", [_VM, ctypes.c_char_p, ctypes.c_size_t], ctypes.c_int32),
+    ("vmma31_capture_output", [_VM], None),
+    ("vmma31_output_len", [_VM], ctypes.c_size_t),
+    ("vmma31_take_output", [_VM, ctypes.c_void_p, ctypes.c_size_t], ctypes.c_size_t),
+    ("vmma31_exit_code", [_VM], ctypes.c_int32),
+    ("vmma31_sp", [_VM], ctypes.c_size_t),
+    ("vmma31_memory_size", [_VM], ctypes.c_size_t),
+    ("vmma31_read_memory", [_VM, ctypes.c_size_t, ctypes.c_void_p, ctypes.c_size_t], ctypes.c_size_t),
+    ("vmma31_free", [_VM], None),
+]:
+    _function = getattr(_lib, _name)
+    _function.argtypes = _args
+    _function.restype = _result
+
+
+class VmFault(Exception):
+    """The program faulted; stepping it again faults again."""
+
+
+class Memory:
+    """Read-only view of VM memory: memory[addr] is a byte, memory[a:b] bytes."""
+
+    def __init__(self, vm):
+        self._vm = vm
+
+    def __len__(self):
+        return _lib.vmma31_memory_size(self._vm._handle)
+
+    def __getitem__(self, key):
+        if isinstance(key, slice):
+            start, stop, step = key.indices(len(self))
+            data = self._read(start, max(stop - start, 0))
+            return data[::step]
+        if key < 0:
+            key += len(self)
+        if not 0 <= key < len(self):
+            raise IndexError("memory address out of range")
+        return self._read(key, 1)[0]
+
+    def _read(self, addr, length):
+        buffer = ctypes.create_string_buffer(length)
+        count = _lib.vmma31_read_memory(self._vm._handle, addr, buffer, length)
+        return buffer.raw[:count]
+
+    def word(self, addr):
+        """The little-endian 32-bit word at addr."""
+        return int.from_bytes(self[addr:addr + 4], "little")
+
+
+class Vmma31:
+    """A VM with ram_size bytes of memory."""
+
+    def __init__(self, ram_size=DEFAULT_RAM_SIZE):
+        self._handle = _lib.vmma31_vm_new(ram_size)
+        if not self._handle:
+            raise ValueError("invalid memory size: {}".format(ram_size))
+        self.memory = Memory(self)
+
+    def __del__(self):
+        if getattr(self, "_handle", None):
+            _lib.vmma31_free(self._handle)
+            self._handle = None
+
+    def load(self, data):
+        """Load a program file's contents, magic bytes first."""
+        if _lib.vmma31_load(self._handle, bytes(data), len(data)) != 0:
+            raise ValueError("not a valid program, or too large for memory")
+
+    def run(self, input=None):
+        """Run to completion and return the exit code; 134 if it faulted.
+
+        Given input, a list of lines without their newlines, the program
+        reads those instead of stdin, its output is captured, and run
+        returns (exit_code, output)."""
+        if input is None:
+            return _lib.vmma31_run(self._handle)
+        self.set_input(input)
+        self.capture_output()
+        exit_code = _lib.vmma31_run(self._handle)
+        return exit_code, self.take_output()
+
+    def set_input(self, lines):
+        """Read these lines instead of stdin; past the last one the program
+        reads empty lines, as at the end of stdin."""
+        text = "".join(line + "\n" for line in lines).encode()
+        _lib.vmma31_set_input(self._handle, text, len(text))
+
+    def capture_output(self):
+        """Keep the program's output for take_output instead of printing it."""
+        _lib.vmma31_capture_output(self._handle)
+
+    def take_output(self):
+        """Output captured since the last call, as a string."""
+        length = _lib.vmma31_output_len(self._handle)
+        buffer = ctypes.create_string_buffer(max(length, 1))
+        count = _lib.vmma31_take_output(self._handle, buffer, length)
+        return buffer.raw[:count].decode()
+
+    def step(self):
+        """Execute one instruction. Returns True while the program is still
+        running and False once it has finished; raises VmFault on a fault."""
+        result = _lib.vmma31_step(self._handle)
+        if result < 0:
+            raise VmFault("program faulted")
+        return result == 1
+
+    @property
+    def exit_code(self):
+        return _lib.vmma31_exit_code(self._handle)
+
+    @property
+    def sp(self):
+        return _lib.vmma31_sp(self._handle)
+
+    def stack(self):
+        """Words on the stack, top first."""
+        return [self.memory.word(addr) for addr in range(self.sp, len(self.memory), 4)]
@@ -0,0 +1,20 @@
+// Character operations on stack words. A word is either a single character
+// or a packed string chunk as produced by stinput: up to three characters in
+// bytes 0-2 with the continuation flag in byte 3. Only the character bytes
+// are inspected or changed, so both forms are handled the same way.
+
+pub fn map_chars(word: u32, f: fn(&u8) -> u8) -> u32 {
+    let mut bytes = word.to_le_bytes();
+    for byte in bytes[..3].iter_mut() {
+        *byte = f(byte);
+    }
+    u32::from_le_bytes(bytes)
+}
+
+// 1 if the word holds at least one character and every character before the
+// first NUL satisfies the predicate, otherwise 0
+pub fn all_chars(word: u32, f: fn(&u8) -> bool) -> u32 {
+    let bytes = word.to_le_bytes();
+    let chars: Vec<u8> = bytes[..3].iter().copied().take_while(|&b| b != 0).collect();
+    (!chars.is_empty() && chars.iter().all(f)) as u32
+}
@@ -0,0 +1,565 @@
+// Two-pass assembler behind `vmma31 asm`. Each line holds one instruction
+// written the way disassemble prints it, with a few additions:
+//
+//   loop:            a label names the address of the next word
+//   goto loop        labels can stand in for any operand
+//   .word 0x1234     emits a raw 32-bit word
+//   push64 1 << 40   pushes a 64-bit value, high word first, in as many
+//                    instructions as it takes; no labels defined later
+//   .entry main      execution starts at main instead of the first word
+//   .return_stack    calls keep return addresses on a separate stack
+//   push 1  # note   `#` or `;` starts a comment
+//   .macro name args ... .endm   see preprocess.rs
+//   .include "lib.s"             likewise
+//
+// Branch operands (call, goto and the ifs) given as labels become offsets
+// from the branching instruction; anywhere else a label is its address.
+//
+// Operands can be expressions of numbers, labels and constants (see
+// expr.rs), such as `push BUF_SIZE*4+2` or `goto end - start`; only a
+// label alone becomes a branch offset.
+//
+//   .equ BUF_SIZE, 64   a constant, usable anywhere in the file
+//
+// Constants and globals go in blocks after the code, at addresses of the
+// program's choosing, which the loader fills in:
+//
+//   .data 0x400      initialized data from 0x400 on, until the next block
+//   msg: .string "hi\n"   the bytes, NUL-terminated (escapes \n \t \0 \\ \")
+//   .byte 1, 2, 255  single bytes
+//   .word 7, msg     32-bit words, as in code
+//   .space 8         zero bytes
+//   .bss 0x800       zeroed data from 0x800 on, which only .space fills
+//   .code            back to instructions
+//
+// Globals that gload and gstore reach by number, wherever the code is,
+// live in one region of words, also zeroed on load:
+//
+//   .globals 0xa00, 16   the region: 16 words from 0xa00 (the count
+//                        defaults to however many .var names)
+//   .var total           names the next global, so `gload total` reads it
+//   .var table, 8        names the next 8, with table the first
+//
+// Built with --object (assemble_object), a file can use labels it doesn't
+// define, which `vmma31 link` finds in the other files:
+//
+//   .global square   lets other files use square; other labels stay local
+//   call print_all   a label from another file
+
+use std::collections::HashMap;
+
+use crate::expr;
+use crate::isa::{self, InstrSpec};
+use crate::link::{Object, Relocation};
+use crate::preprocess::{self, Location, SourceLine};
+use crate::program::{join_program, Sections};
+
+enum Statement<'a> {
+    Word(&'a str),
+    Encoded(u32), // Already encoded, from push64
+    Instruction(&'static InstrSpec, Vec<&'a str>),
+}
+
+// Where pass 1 puts what it reads
+#[derive(Clone, Copy)]
+enum Segment {
+    Code,
+    Data(usize), // Index into Sections::data
+    Bss(usize),  // Index into Sections::bss
+}
+
+// What assemble_words makes of a source file
+struct Assembled {
+    words: Vec<u32>,
+    lines: Vec<Location>, // Where each word came from
+    sections: Sections, // Entry point, labels as symbols, and blocks
+    globals: Vec<String>,
+    relocations: Vec<Relocation>, // Only for objects
+}
+
+// Assemble source text into magic-prefixed bytecode, ready for load_file.
+// Files it includes are found relative to the current directory.
+pub fn assemble_program(source: &str) -> Result<Vec<u8>, String> {
+    assemble_file(source, "")
+}
+
+// Like assemble_program, for source read from the file named file, so
+// files it includes are found next to it
+pub fn assemble_file(source: &str, file: &str) -> Result<Vec<u8>, String> {
+    assemble_with_lines(source, file).map(|(bytecode, _)| bytecode)
+}
+
+// Like assemble_file, but every label is also written to the file's
+// symbol section, so the disassembler and debugger can show it
+pub fn assemble_with_symbols(source: &str, file: &str) -> Result<Vec<u8>, String> {
+    let assembled = assemble_words(source, file, false)?;
+    Ok(join(&assembled.words, assembled.sections))
+}
+
+// Like assemble_with_symbols, plus a debug section giving the file and line
+// each instruction came from
+pub fn assemble_with_debug_info(source: &str, file: &str) -> Result<Vec<u8>, String> {
+    let mut assembled = assemble_words(source, file, false)?;
+    add_lines(&mut assembled, file);
+    Ok(join(&assembled.words, assembled.sections))
+}
+
+// Assemble source, read from the file named file, into an object file for
+// `vmma31 link`, with symbols and source lines
+pub fn assemble_object(source: &str, file: &str) -> Result<Vec<u8>, String> {
+    let mut assembled = assemble_words(source, file, true)?;
+    add_lines(&mut assembled, file);
+    let object = Object {
+        code: assembled.words.iter().flat_map(|word| word.to_le_bytes()).collect(),
+        sections: assembled.sections,
+        globals: assembled.globals,
+        relocations: assembled.relocations,
+    };
+    Ok(object.to_bytes())
+}
+
+fn add_lines(assembled: &mut Assembled, file: &str) {
+    for (index, at) in assembled.lines.iter().enumerate() {
+        let (included, line) = at.source_line();
+        assembled.sections.lines.insert(index * 4, included.unwrap_or(file), line as u32);
+    }
+}
+
+fn join(words: &[u32], sections: Sections) -> Vec<u8> {
+    let code: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    join_program(&sections, &code)
+}
+
+// Like assemble_file, but also returns the line (counting from 1) in source
+// of each word, for mapping addresses back to it; words from an included
+// file get the line of the .include
+pub fn assemble_with_lines(source: &str, file: &str) -> Result<(Vec<u8>, Vec<usize>), String> {
+    let Assembled { words, lines, sections, .. } = assemble_words(source, file, false)?;
+    // Symbols and source lines are left out; the entry point and blocks are needed to run
+    let Sections { entry, data, bss, globals, return_stack, .. } = sections;
+    let lines = lines.iter().map(|at| at.line).collect();
+    Ok((join(&words, Sections { entry, data, bss, globals, return_stack, ..Sections::default() }), lines))
+}
+
+// Assemble a program, or with object set an object, whose labels can come
+// from other files. source was read from the file named file.
+fn assemble_words(source: &str, file: &str, object: bool) -> Result<Assembled, String> {
+    let lines = preprocess::expand(source, file)?;
+
+    // Constants first, so they can be used before they're defined, then the
+    // numbers of named globals
+    let mut names = Names::default();
+    let mut vars = Vec::new();
+    for SourceLine { text, at } in &lines {
+        let mut rest = text.as_str();
+        while let Some((_, after)) = split_label(rest) {
+            rest = after;
+        }
+        let (mnemonic, operands) = split_mnemonic(rest);
+        if mnemonic == ".equ" {
+            let (name, expression) = match expr::split_operands(operands)[..] {
+                [name, expression] if is_label(name) => (name, expression),
+                _ => return Err(format!("{}: .equ takes a name and a value", at)),
+            };
+            if let Some((_, other)) = names.constants.insert(name, (expression, at)) {
+                return Err(format!("{}: constant {} is already defined at {}", at, name, other));
+            }
+        }
+        if mnemonic == ".var" {
+            match expr::split_operands(operands)[..] {
+                [name] if is_label(name) => vars.push((name, None, at)),
+                [name, count] if is_label(name) => vars.push((name, Some(count), at)),
+                _ => return Err(format!("{}: .var takes a name and optionally a count of words", at)),
+            }
+        }
+    }
+    let mut var_count = 0;
+    for (name, count, at) in vars {
+        if let Some((_, defined_at)) = names.constants.get(name) {
+            return Err(format!("{}: {} is already a constant, defined at {}", at, name, defined_at));
+        }
+        if names.vars.insert(name, var_count).is_some() {
+            return Err(format!("{}: global {} is already defined", at, name));
+        }
+        var_count += match count {
+            Some(text) => space(text, &names, at)?,
+            None => 1,
+        };
+    }
+
+    // Pass 1: parse every line and give each label the address of the next word
+    let mut sections = Sections::default();
+    let mut entry = None;
+    let mut region = None; // .globals address and count, and where it's given
+    let mut globals = Vec::new();
+    let mut statements = Vec::new();
+    let mut segment = Segment::Code;
+    let mut data_values = Vec::new(); // (line, block, offset, width, value) for .word and .byte in data
+    for SourceLine { text, at } in &lines {
+        let mut rest = text.as_str();
+        while let Some((label, after)) = split_label(rest) {
+            let (addr, in_code) = match segment {
+                Segment::Code => (statements.len() * 4, true),
+                Segment::Data(block) => (sections.data[block].0 + sections.data[block].1.len(), false),
+                Segment::Bss(block) => (sections.bss[block].0 + sections.bss[block].1, false),
+            };
+            if names.labels.insert(label, (addr, in_code)).is_some() {
+                return Err(format!("{}: duplicate label {:?}", at, label));
+            }
+            if let Some((_, defined_at)) = names.constants.get(label) {
+                return Err(format!("{}: {} is already a constant, defined at {}", at, label, defined_at));
+            }
+            if names.vars.contains_key(label) {
+                return Err(format!("{}: {} is already a global", at, label));
+            }
+            sections.symbols.insert(label, addr);
+            rest = after;
+        }
+        if rest.is_empty() {
+            continue;
+        }
+        let (mnemonic, operands) = split_mnemonic(rest);
+        let operands = expr::split_operands(operands);
+        if mnemonic == ".equ" || mnemonic == ".var" {
+            continue;
+        }
+        if mnemonic == ".globals" {
+            let (addr, count) = match operands[..] {
+                [addr] => (addr, None),
+                [addr, count] => (addr, Some(count)),
+                _ => return Err(format!("{}: .globals takes an address and optionally a count of words", at)),
+            };
+            if region.is_some() {
+                return Err(format!("{}: the globals region is already set", at));
+            }
+            let count = count.map(|count| space(count, &names, at)).transpose()?;
+            let addr = names
+                .value(addr, 0)
+                .and_then(|addr| usize::try_from(addr).map_err(|_| format!("invalid address {}", addr)))
+                .map_err(|e| format!("{}: {}", at, e))?;
+            region = Some((addr, count, at));
+            continue;
+        }
+        if mnemonic == ".return_stack" {
+            if !operands.is_empty() {
+                return Err(format!("{}: .return_stack takes no operands", at));
+            }
+            sections.return_stack = true;
+            continue;
+        }
+        if mnemonic == ".entry" {
+            match (&operands[..], entry) {
+                (&[target], None) => entry = Some((at, target)),
+                (&[_], Some(_)) => return Err(format!("{}: the entry point is already set", at)),
+                _ => return Err(format!("{}: .entry takes one address", at)),
+            }
+            continue;
+        }
+        if mnemonic == ".global" {
+            match operands[..] {
+                [name] if is_label(name) => globals.push((at, name)),
+                _ => return Err(format!("{}: .global takes one label", at)),
+            }
+            continue;
+        }
+        // Block directives, and everything inside data and bss blocks
+        let handled = match (mnemonic, &operands[..]) {
+            (".code", []) => {
+                segment = Segment::Code;
+                true
+            }
+            (".data" | ".bss", &[text]) => {
+                let addr = names
+                    .value(text, 0)
+                    .and_then(|addr| usize::try_from(addr).map_err(|_| format!("invalid address {}", addr)))
+                    .map_err(|e| format!("{}: {}", at, e))?;
+                segment = if mnemonic == ".data" {
+                    sections.data.push((addr, Vec::new()));
+                    Segment::Data(sections.data.len() - 1)
+                } else {
+                    sections.bss.push((addr, 0));
+                    Segment::Bss(sections.bss.len() - 1)
+                };
+                true
+            }
+            (".code" | ".data" | ".bss", _) => return Err(format!("{}: invalid {} directive", at, mnemonic)),
+            _ => match segment {
+                Segment::Code => false,
+                Segment::Data(block) => {
+                    let bytes = &mut sections.data[block].1;
+                    match (mnemonic, &operands[..]) {
+                        (".word" | ".byte", values) if !values.is_empty() => {
+                            let width = if mnemonic == ".word" { 4 } else { 1 };
+                            for text in values {
+                                data_values.push((at, block, bytes.len(), width, *text));
+                                bytes.resize(bytes.len() + width, 0);
+                            }
+                        }
+                        (".string", _) => {
+                            let text = rest[".string".len()..].trim();
+                            bytes.extend(parse_string(text).map_err(|e| format!("{}: {}", at, e))?);
+                        }
+                        (".space", &[text]) => bytes.resize(bytes.len() + space(text, &names, at)?, 0),
+                        _ => return Err(format!("{}: {} can't go in a .data block", at, mnemonic)),
+                    }
+                    true
+                }
+                Segment::Bss(block) => match (mnemonic, &operands[..]) {
+                    (".space", &[text]) => {
+                        sections.bss[block].1 += space(text, &names, at)?;
+                        true
+                    }
+                    _ => return Err(format!("{}: only .space can go in a .bss block", at)),
+                },
+            },
+        };
+        if handled {
+            continue;
+        }
+        if mnemonic == "push64" {
+            let value = match operands[..] {
+                [text] => names.fixed_value(text).map_err(|e| format!("{}: {}", at, e))?,
+                _ => return Err(format!("{}: push64 takes one value", at)),
+            };
+            statements.extend(push64(value).into_iter().map(|word| (at, Statement::Encoded(word))));
+            continue;
+        }
+        let statement = if mnemonic == ".word" {
+            match operands[..] {
+                [value] => Statement::Word(value),
+                _ => return Err(format!("{}: .word takes one value", at)),
+            }
+        } else {
+            let op: isa::Op = mnemonic.parse().map_err(|e| format!("{}: {}", at, e))?;
+            let spec = op.spec();
+            if operands.len() != spec.operands.len() {
+                return Err(format!(
+                    "{}: {} takes {} operand(s), got {}",
+                    at,
+                    mnemonic,
+                    spec.operands.len(),
+                    operands.len()
+                ));
+            }
+            Statement::Instruction(spec, operands)
+        };
+        statements.push((at, statement));
+    }
+
+    sections.globals = match region {
+        Some((_, Some(count), at)) if count < var_count => {
+            return Err(format!("{}: .var names {} globals, more than the region's {}", at, var_count, count));
+        }
+        Some((addr, count, _)) => Some((addr, count.unwrap_or(var_count))),
+        None if var_count > 0 => return Err(".var needs a .globals region to put the globals in".to_string()),
+        None => None,
+    };
+    sections.check_blocks(statements.len() * 4, u32::MAX as usize)?;
+    if let Some((at, text)) = entry {
+        let addr = names.value(text, 0).map_err(|e| format!("{}: {}", at, e))?;
+        if addr % 4 != 0 || !(0..statements.len() as i64 * 4).contains(&addr) {
+            return Err(format!("{}: entry point {} is not an instruction", at, text));
+        }
+        sections.entry = addr as usize;
+    }
+    for &(at, name) in &globals {
+        if !names.labels.contains_key(name) {
+            return Err(format!("{}: .global {} isn't defined here", at, name));
+        }
+    }
+
+    // The value of an operand at addr in operand (None for a whole word).
+    // In an object, uses of labels are noted for the linker, labels from
+    // other files are 0 until linked, and an expression can't depend on
+    // where the code is placed, as the linker only moves labels used alone.
+    let mut relocations = Vec::new();
+    let mut reference = |text: &str, addr: usize, operand: Option<usize>| -> Result<i64, String> {
+        if !object {
+            return names.value(text, 0);
+        }
+        let label = names.labels.contains_key(text);
+        let external = !label && !names.constants.contains_key(text) && !names.vars.contains_key(text) && is_label(text);
+        if label || external {
+            relocations.push(Relocation { addr, operand, name: text.to_string() });
+            return if external { Ok(0) } else { names.value(text, 0) };
+        }
+        names.fixed_value(text)
+    };
+
+    // Pass 2: resolve labels and encode
+    let mut words = Vec::with_capacity(statements.len());
+    for (index, (at, statement)) in statements.iter().enumerate() {
+        let addr = index * 4;
+        let word = match statement {
+            Statement::Word(text) => reference(text, addr, None)
+                .and_then(|value| word_value(text, value))
+                .map_err(|e| format!("{}: {}", at, e))?,
+            Statement::Encoded(word) => *word,
+            Statement::Instruction(spec, operands) => {
+                let mut values = Vec::with_capacity(operands.len());
+                for (index, (text, field)) in operands.iter().zip(spec.operands).enumerate() {
+                    let mut value = reference(text, addr, Some(index)).map_err(|e| format!("{}: {}", at, e))?;
+                    if field.branch && names.labels.contains_key(text) {
+                        value -= addr as i64;
+                    }
+                    values.push(value);
+                }
+                spec.encode_checked(&values).map_err(|e| format!("{}: {}", at, e))?
+            }
+        };
+        words.push(word);
+    }
+    for (at, block, offset, width, text) in data_values {
+        if width == 1 {
+            let value = names.fixed_value(text).map_err(|e| format!("{}: {}", at, e))?;
+            if !(-128..=255).contains(&value) {
+                return Err(format!("{}: {} does not fit in a byte", at, text));
+            }
+            sections.data[block].1[offset] = value as u8;
+            continue;
+        }
+        let addr = sections.data[block].0 + offset;
+        let value = reference(text, addr, None)
+            .and_then(|value| word_value(text, value))
+            .map_err(|e| format!("{}: {}", at, e))?;
+        sections.data[block].1[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    Ok(Assembled {
+        words,
+        lines: statements.iter().map(|(at, _)| (*at).clone()).collect(),
+        sections,
+        globals: globals.into_iter().map(|(_, name)| name.to_string()).collect(),
+        relocations,
+    })
+}
+
+// The value of a .word, given as text
+fn word_value(text: &str, value: i64) -> Result<u32, String> {
+    if !(i32::MIN as i64..=u32::MAX as i64).contains(&value) {
+        return Err(format!("{} does not fit in 32 bits", text));
+    }
+    Ok(value as u32)
+}
+
+// The words for push64: each half is one push if it fits, or else its top
+// and bottom 16 bits put together with lsl and or
+fn push64(value: i64) -> Vec<u32> {
+    let push = |value: i32| isa::Op::Push.encode_with(&[value]).expect("16-bit values fit in push");
+    let mut words = Vec::new();
+    for half in [(value >> 32) as i32, value as i32] {
+        match isa::Op::Push.encode_with(&[half]) {
+            Some(word) => words.push(word),
+            None => words.extend([push(half >> 16), push(16), isa::Op::Lsl.encode(), push(half & 0xFFFF), isa::Op::Or.encode()]),
+        }
+    }
+    words
+}
+
+// The byte count of a .space
+fn space(text: &str, names: &Names, at: &Location) -> Result<usize, String> {
+    names
+        .value(text, 0)
+        .and_then(|len| usize::try_from(len).map_err(|_| format!("invalid size {}", len)))
+        .map_err(|e| format!("{}: {}", at, e))
+}
+
+// The mnemonic at the start of a line, and the operands after it
+fn split_mnemonic(text: &str) -> (&str, &str) {
+    text.split_once(char::is_whitespace).unwrap_or((text, ""))
+}
+
+// Labels and constants, by name, for working out operands
+#[derive(Default)]
+struct Names<'a> {
+    labels: HashMap<&'a str, (usize, bool)>, // Address, and whether it's in the code
+    constants: HashMap<&'a str, (&'a str, &'a Location)>, // Expression, and where it's defined
+    vars: HashMap<&'a str, usize>,                         // Number of each named global
+}
+
+impl Names<'_> {
+    // The value of an expression, with code labels moved on by shift bytes
+    fn value(&self, text: &str, shift: usize) -> Result<i64, String> {
+        self.value_within(text, shift, &mut Vec::new())
+    }
+
+    // Like value, inside the constants named in evaluating, which can't be
+    // used again
+    fn value_within<'e>(&'e self, text: &str, shift: usize, evaluating: &mut Vec<&'e str>) -> Result<i64, String> {
+        expr::evaluate(text, &mut |name| {
+            if let Some(&(addr, in_code)) = self.labels.get(name) {
+                return Ok((addr + if in_code { shift } else { 0 }) as i64);
+            }
+            if let Some(&index) = self.vars.get(name) {
+                return Ok(index as i64);
+            }
+            let Some((&name, &(expression, at))) = self.constants.get_key_value(name) else {
+                return Err(format!("unknown label or constant {:?}", name));
+            };
+            if evaluating.contains(&name) {
+                return Err(format!("{} is defined in terms of itself", name));
+            }
+            evaluating.push(name);
+            let value = self.value_within(expression, shift, evaluating).map_err(|e| format!("{} (in .equ {} at {})", e, name, at));
+            evaluating.pop();
+            value
+        })
+    }
+
+    // The value of an expression in an object file, which must not change
+    // when the linker moves the code
+    fn fixed_value(&self, text: &str) -> Result<i64, String> {
+        let value = self.value(text, 0)?;
+        if self.value(text, 0x1000)? != value {
+            return Err(format!("{} depends on where the code is placed; in an object file, use a code label alone", text));
+        }
+        Ok(value)
+    }
+}
+
+// The part of a line before any comment; `#` and `;` in a string don't count
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let (mut quoted, mut escaped) = (false, false);
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' | ';' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+// The bytes of a quoted .string, with the NUL terminator added
+fn parse_string(text: &str) -> Result<Vec<u8>, String> {
+    let inner = text.strip_prefix('"').and_then(|text| text.strip_suffix('"')).ok_or(".string takes a quoted string")?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '"')) => c,
+                other => return Err(format!("unknown escape \\{}", other.unwrap_or(' '))),
+            },
+            c => c,
+        };
+        bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+    bytes.push(0);
+    Ok(bytes)
+}
+
+// Split `name:` off the front of a line, if it starts with a label
+pub(crate) fn split_label(line: &str) -> Option<(&str, &str)> {
+    let (label, rest) = line.split_once(':')?;
+    is_label(label).then(|| (label, rest.trim_start()))
+}
+
+pub(crate) fn is_label(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
@@ -0,0 +1,52 @@
+// Call chain for faults, and for `dump` with --dump-backtrace. It's rebuilt
+// from the guest stack rather than the shadow stack, so it works with
+// --no-shadow-stack and shows what a return would actually find: every
+// stacked word that points just past a call instruction is taken to be that
+// call's return address. A data word that happens to look like one shows up
+// as an extra frame. With a separate return stack, that's where a return
+// looks, so the chain comes from there instead.
+//
+//   #0 0x0054 factorial+0x8 (fact.s:14)
+//   #1 0x0028 factorial+0x14 (fact.s:20)
+//   #2 0x0004 main+0x4 (fact.s:3)
+//
+// Frames are named from the symbol section (the nearest symbol at or before
+// the address) and placed with the debug section, when the file has them.
+
+use std::fmt::Write;
+
+use crate::isa::{self, Op};
+use crate::VM;
+
+impl VM {
+    // The PC, then the address of each call still waiting to return,
+    // innermost first
+    pub fn backtrace(&self) -> Vec<usize> {
+        if self.return_stack {
+            let calls = self.shadow_stack.iter().rev().map(|&return_addr| (return_addr as usize).wrapping_sub(4));
+            return std::iter::once(self.pc).chain(calls).collect();
+        }
+        let calls = (self.sp..self.memory.len().saturating_sub(3)).step_by(4).filter_map(|addr| {
+            let return_addr = self.read_u32(addr) as usize;
+            let call = return_addr.checked_sub(4).filter(|call| call % 4 == 0 && *call < self.code_size)?;
+            (isa::decode(self.read_u32(call)) == Some(Op::Call)).then_some(call)
+        });
+        std::iter::once(self.pc).chain(calls).collect()
+    }
+
+    // The backtrace as text, one frame per line
+    pub fn format_backtrace(&self) -> String {
+        let mut text = String::new();
+        for (index, addr) in self.backtrace().into_iter().enumerate() {
+            let _ = write!(text, "#{} 0x{:04x}", index, addr);
+            if let Some((start, name)) = self.sections.symbols.containing(addr) {
+                let _ = write!(text, " {}+0x{:x}", name, addr - start);
+            }
+            if let Some((file, line)) = self.sections.lines.at(addr) {
+                let _ = write!(text, " ({}:{})", file, line);
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
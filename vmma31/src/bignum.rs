@@ -0,0 +1,124 @@
+// Host bignum operations exposed through the syscall instruction. Every
+// operand is an array of `limbs` little-endian 32-bit words at a byte
+// address in VM memory, so guest programs can work with numbers far wider
+// than a single stack word.
+
+use num_bigint::BigUint;
+
+use crate::VM;
+
+pub const BIGNUM_ADD: u32 = 0x10;
+pub const BIGNUM_SUB: u32 = 0x11;
+pub const BIGNUM_MUL: u32 = 0x12;
+pub const BIGNUM_DIV: u32 = 0x13;
+pub const BIGNUM_MOD: u32 = 0x14;
+pub const BIGNUM_POW: u32 = 0x15;
+
+// Status word pushed after every bignum syscall
+const STATUS_OK: u32 = 0;
+const STATUS_OVERFLOW: u32 = 1; // Result did not fit in `limbs` words and was truncated
+const STATUS_DIVIDE_BY_ZERO: u32 = 2;
+const STATUS_BAD_ADDRESS: u32 = 3;
+
+impl VM {
+    // Stack layout, top first:
+    //   add/sub/mul/div/mod: [limbs] [b] [a] [dst]      dst = a op b
+    //   pow:                 [limbs] [m] [b] [a] [dst]  dst = a ^ b (mod m, unless m is zero)
+    // All of a, b, m and dst are `limbs` words long. A status word is pushed afterwards.
+    pub(crate) fn exec_bignum(&mut self, number: u32) {
+        let limbs = self.pop() as usize;
+        let modulus_addr = if number == BIGNUM_POW { Some(self.pop() as usize) } else { None };
+        let b_addr = self.pop() as usize;
+        let a_addr = self.pop() as usize;
+        let dst = self.pop() as usize;
+
+        let status = self.bignum_operation(number, limbs, dst, a_addr, b_addr, modulus_addr);
+        self.push(status);
+    }
+
+    fn bignum_operation(
+        &mut self,
+        number: u32,
+        limbs: usize,
+        dst: usize,
+        a_addr: usize,
+        b_addr: usize,
+        modulus_addr: Option<usize>,
+    ) -> u32 {
+        if !self.limbs_in_bounds(dst, limbs) {
+            return STATUS_BAD_ADDRESS;
+        }
+        let (a, b) = match (self.read_bignum(a_addr, limbs), self.read_bignum(b_addr, limbs)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return STATUS_BAD_ADDRESS,
+        };
+        let width = BigUint::from(1u32) << (32 * limbs);
+
+        let (result, mut status) = match number {
+            BIGNUM_ADD => (a + b, STATUS_OK),
+            BIGNUM_SUB if a >= b => (a - b, STATUS_OK),
+            BIGNUM_SUB => (&width - (b - a), STATUS_OVERFLOW), // Wrap like two's complement
+            BIGNUM_MUL => (a * b, STATUS_OK),
+            BIGNUM_DIV | BIGNUM_MOD if b.bits() == 0 => return STATUS_DIVIDE_BY_ZERO,
+            BIGNUM_DIV => (a / b, STATUS_OK),
+            BIGNUM_MOD => (a % b, STATUS_OK),
+            BIGNUM_POW => {
+                let modulus = match self.read_bignum(modulus_addr.unwrap_or(0), limbs) {
+                    Some(m) => m,
+                    None => return STATUS_BAD_ADDRESS,
+                };
+                if modulus.bits() != 0 {
+                    (a.modpow(&b, &modulus), STATUS_OK)
+                } else if b.bits() == 0 {
+                    (BigUint::from(1u32), STATUS_OK)
+                } else if a.bits() <= 1 {
+                    (a, STATUS_OK) // 0 and 1 are fixed points
+                } else if b >= BigUint::from(32 * limbs)
+                    || (a.bits() - 1).saturating_mul(exponent(&b)) >= 32 * limbs as u64
+                {
+                    // a >= 2^(bits - 1), so a^b has at least (bits - 1) * b bits
+                    // and cannot fit. Reduce as we go rather than build the
+                    // whole power, which could take minutes in one instruction.
+                    (a.modpow(&b, &width), STATUS_OVERFLOW)
+                } else {
+                    // Here a^b < 2^(bits * b), at most twice the bit width
+                    (a.pow(exponent(&b) as u32), STATUS_OK)
+                }
+            }
+            _ => return STATUS_OK,
+        };
+
+        if result >= width {
+            status = STATUS_OVERFLOW;
+        }
+        self.write_bignum(dst, limbs, &(result % width));
+        status
+    }
+
+    fn limbs_in_bounds(&self, addr: usize, limbs: usize) -> bool {
+        limbs
+            .checked_mul(4)
+            .and_then(|len| addr.checked_add(len))
+            .is_some_and(|end| end <= self.memory.len())
+    }
+
+    fn read_bignum(&self, addr: usize, limbs: usize) -> Option<BigUint> {
+        if !self.limbs_in_bounds(addr, limbs) {
+            return None;
+        }
+        let words: Vec<u32> = (0..limbs).map(|i| self.read_u32(addr + i * 4)).collect();
+        Some(BigUint::from_slice(&words))
+    }
+
+    fn write_bignum(&mut self, addr: usize, limbs: usize, value: &BigUint) {
+        let mut digits = value.iter_u32_digits();
+        for i in 0..limbs {
+            self.write_u32(addr + i * 4, digits.next().unwrap_or(0));
+        }
+    }
+}
+
+// Low 64 bits of an exponent already known to be below the bit width
+fn exponent(b: &BigUint) -> u64 {
+    b.iter_u64_digits().next().unwrap_or(0)
+}
@@ -0,0 +1,72 @@
+// The cpuid instruction: lets a guest ask which optional parts of the ISA
+// this interpreter has. Interpreters that predate cpuid ignore it, so a
+// guest can push 0 first and treat a 0 on top afterwards as "no cpuid".
+
+use crate::VM;
+
+// Feature bits pushed by cpuid
+pub const HAS_VECTOR: u32 = 1 << 0; // vadd/vsub/vmul
+pub const HAS_FIXED_POINT: u32 = 1 << 1; // qmul/qdiv
+pub const HAS_CHAR_OPS: u32 = 1 << 2; // toupper/tolower/isdigit/isalpha
+pub const HAS_CODEC: u32 = 1 << 3; // b64enc/b64dec/hexenc/hexdec
+pub const HAS_PROCESSES: u32 = 1 << 4; // fork/wait
+pub const HAS_MATH: u32 = 1 << 5; // Math syscalls
+pub const HAS_SORT: u32 = 1 << 6; // Sort syscall
+pub const HAS_BIGNUM: u32 = 1 << 7; // Bignum syscalls (compiled in)
+pub const HAS_REGEX: u32 = 1 << 8; // Regex syscalls (compiled in and enabled with --allow-regex)
+pub const HAS_COUNTERS: u32 = 1 << 9; // counter
+pub const HAS_MEMORY: u32 = 1 << 10; // load/store and their byte and immediate forms
+pub const HAS_GLOBALS: u32 = 1 << 11; // gload/gstore
+pub const HAS_FRAMES: u32 = 1 << 12; // enter/leave/lload/lstore
+pub const HAS_RETURN_STACK: u32 = 1 << 13; // Separate return stack mode
+pub const HAS_HEAP: u32 = 1 << 14; // alloc/free
+pub const HAS_BLOCK_MEMORY: u32 = 1 << 15; // memcpy/memset/memcmp
+pub const HAS_PRINTF: u32 = 1 << 16; // printf
+pub const HAS_WIDE_ARITHMETIC: u32 = 1 << 17; // add64/sub64/mul64/cmp64
+pub const HAS_BIT_COUNTS: u32 = 1 << 18; // popcount/clz/ctz
+pub const HAS_MIN_MAX: u32 = 1 << 19; // min/max/abs
+pub const HAS_MULH: u32 = 1 << 20; // mulh/mulhu
+pub const HAS_UNSIGNED_IFS: u32 = 1 << 21; // ifltu/ifgtu/ifleu/ifgeu
+pub const HAS_FLAGS: u32 = 1 << 22; // cmp and the flag branches
+pub const HAS_OVERFLOW_TRAP: u32 = 1 << 23; // trapv
+
+impl VM {
+    // Pushes [version] then [features], so features ends up on top. The
+    // version is major << 16 | minor << 8 | patch.
+    pub(crate) fn exec_cpuid(&mut self) {
+        self.push(version());
+        self.push(self.features());
+    }
+
+    fn features(&self) -> u32 {
+        let mut features = HAS_VECTOR | HAS_FIXED_POINT | HAS_CHAR_OPS | HAS_CODEC | HAS_PROCESSES | HAS_MATH | HAS_SORT
+            | HAS_COUNTERS | HAS_MEMORY | HAS_GLOBALS | HAS_FRAMES
+            | HAS_RETURN_STACK | HAS_HEAP | HAS_BLOCK_MEMORY | HAS_PRINTF
+            | HAS_WIDE_ARITHMETIC | HAS_BIT_COUNTS | HAS_MIN_MAX | HAS_MULH
+            | HAS_UNSIGNED_IFS | HAS_FLAGS | HAS_OVERFLOW_TRAP;
+        if cfg!(feature = "bignum") {
+            features |= HAS_BIGNUM;
+        }
+        if self.regex_enabled() {
+            features |= HAS_REGEX;
+        }
+        features
+    }
+
+    #[cfg(feature = "regex")]
+    fn regex_enabled(&self) -> bool {
+        self.allow_regex
+    }
+
+    #[cfg(not(feature = "regex"))]
+    fn regex_enabled(&self) -> bool {
+        false
+    }
+}
+
+fn version() -> u32 {
+    let part = |text: &str| text.parse::<u32>().unwrap_or(0) & 0xFF;
+    part(env!("CARGO_PKG_VERSION_MAJOR")) << 16
+        | part(env!("CARGO_PKG_VERSION_MINOR")) << 8
+        | part(env!("CARGO_PKG_VERSION_PATCH"))
+}
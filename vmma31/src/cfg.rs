@@ -0,0 +1,186 @@
+// Control-flow graph of a program: its code split into basic blocks (runs
+// of instructions entered only at the top and left only at the bottom) and
+// the edges between them, for passes that reason about control flow.
+//
+// Edges come from the static branches: goto goes to its target, the ifs to
+// their target or the next instruction, and call to its target with a
+// second edge to the instruction after it, where the callee's return lands.
+// A branch to its own address leaves the PC unchanged, so the VM moves on
+// to the next instruction as if it hadn't branched. return's destination is
+// only known at run time; it gets just the edge to the next instruction,
+// where it goes when the stack is empty. exit, undefined words and branches
+// that leave the code have no edges.
+
+use crate::isa::{self, Op};
+
+pub type BlockId = usize; // Index into Cfg::blocks, in address order
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    Fallthrough, // On to the next instruction, including past an untaken if
+    Branch,      // A goto, or an if that's taken
+    Call,        // Into a called function
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub from: BlockId,
+    pub to: BlockId,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize, // Address of the first instruction
+    pub end: usize,   // Address just past the last one
+}
+
+impl BasicBlock {
+    // Address of the last instruction, the one that decides where control goes
+    pub fn last(&self) -> usize {
+        self.end - 4
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    entry: Option<BlockId>, // Where execution starts; None for empty code
+    blocks: Vec<BasicBlock>,
+    successors: Vec<Vec<Edge>>,
+    predecessors: Vec<Vec<Edge>>,
+}
+
+impl Cfg {
+    // Build the graph for code words as decode_program returns them
+    pub fn build(words: &[u32]) -> Cfg {
+        Cfg::build_with_entry(words, 0)
+    }
+
+    // Like build, for a program whose entry point (see Sections::entry) is at
+    // entry rather than the first word
+    pub fn build_with_entry(words: &[u32], entry: usize) -> Cfg {
+        let code_size = words.len() * 4;
+        let target = |addr: usize, word: u32| -> Option<usize> {
+            let spec = isa::lookup(word)?;
+            let field = spec.operands.iter().find(|field| field.branch)?;
+            let target = usize::try_from(addr as i64 + field.extract(word) as i64).ok();
+            target.filter(|&target| target < code_size && target != addr)
+        };
+
+        // A block starts at the entry, at every branch target and after every
+        // instruction that doesn't simply fall through
+        let mut leaders = vec![false; words.len()];
+        for (index, &word) in words.iter().enumerate() {
+            let addr = index * 4;
+            if index == 0 || addr == entry {
+                leaders[index] = true;
+            }
+            if let Some(target) = target(addr, word) {
+                leaders[target / 4] = true;
+            }
+            if !falls_through_only(word) && index + 1 < words.len() {
+                leaders[index + 1] = true;
+            }
+        }
+        let mut blocks = Vec::new();
+        for (index, &leader) in leaders.iter().enumerate() {
+            if leader {
+                blocks.push(BasicBlock { start: index * 4, end: index * 4 + 4 });
+            } else if let Some(block) = blocks.last_mut() {
+                block.end += 4;
+            }
+        }
+
+        let block_of = |addr: usize| blocks.partition_point(|block| block.start <= addr) - 1;
+        let mut successors = vec![Vec::new(); blocks.len()];
+        let mut predecessors = vec![Vec::new(); blocks.len()];
+        for (from, block) in blocks.iter().enumerate() {
+            let word = words[block.last() / 4];
+            let mut edges = Vec::new();
+            let next = (block.end < code_size).then(|| from + 1);
+            let static_target = target(block.last(), word);
+            let self_branch = branch_to_self(word);
+            match isa::decode(word) {
+                _ if self_branch => edges.extend(next.map(|next| (next, EdgeKind::Fallthrough))),
+                Some(Op::Goto) => edges.extend(static_target.map(|t| (block_of(t), EdgeKind::Branch))),
+                Some(Op::Call) => {
+                    edges.extend(static_target.map(|t| (block_of(t), EdgeKind::Call)));
+                    edges.extend(next.map(|next| (next, EdgeKind::Fallthrough)));
+                }
+                Some(op) if is_conditional(op) => {
+                    edges.extend(static_target.map(|t| (block_of(t), EdgeKind::Branch)));
+                    edges.extend(next.map(|next| (next, EdgeKind::Fallthrough)));
+                }
+                Some(Op::Exit) | None => {}
+                Some(_) => edges.extend(next.map(|next| (next, EdgeKind::Fallthrough))),
+            }
+            for (to, kind) in edges {
+                let edge = Edge { from, to, kind };
+                successors[from].push(edge);
+                predecessors[to].push(edge);
+            }
+        }
+        let entry = (entry < code_size).then(|| block_of(entry));
+        Cfg { entry, blocks, successors, predecessors }
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    pub fn block(&self, id: BlockId) -> &BasicBlock {
+        &self.blocks[id]
+    }
+
+    // The block holding the instruction at addr
+    pub fn block_at(&self, addr: usize) -> Option<BlockId> {
+        let id = self.blocks.partition_point(|block| block.start <= addr).checked_sub(1)?;
+        (addr < self.blocks[id].end).then_some(id)
+    }
+
+    // Edges out of a block, the taken branch before the fallthrough
+    pub fn successors(&self, id: BlockId) -> &[Edge] {
+        &self.successors[id]
+    }
+
+    // Edges into a block, in the order of the blocks they come from
+    pub fn predecessors(&self, id: BlockId) -> &[Edge] {
+        &self.predecessors[id]
+    }
+
+    // Blocks reachable from the entry by following edges, in address order
+    pub fn reachable(&self) -> Vec<BlockId> {
+        let mut seen = vec![false; self.blocks.len()];
+        let mut pending: Vec<BlockId> = self.entry.into_iter().collect();
+        while let Some(id) = pending.pop() {
+            if !std::mem::replace(&mut seen[id], true) {
+                pending.extend(self.successors[id].iter().map(|edge| edge.to));
+            }
+        }
+        (0..self.blocks.len()).filter(|&id| seen[id]).collect()
+    }
+}
+
+// A branch with offset 0, which continues with the next instruction
+fn branch_to_self(word: u32) -> bool {
+    let field = isa::lookup(word).and_then(|spec| spec.operands.iter().find(|field| field.branch));
+    field.is_some_and(|field| field.extract(word) == 0)
+}
+
+fn is_conditional(op: Op) -> bool {
+    matches!(
+        op,
+        Op::Ifeq | Op::Ifne | Op::Iflt | Op::Ifgt | Op::Ifle | Op::Ifge | Op::Ifltu | Op::Ifgtu | Op::Ifleu | Op::Ifgeu
+            | Op::Ifez | Op::Ifnz | Op::Ifmi | Op::Ifpl
+            | Op::Beq | Op::Bne | Op::Bhs | Op::Blo | Op::Bmi | Op::Bpl | Op::Bvs | Op::Bvc
+            | Op::Bhi | Op::Bls | Op::Bge | Op::Blt | Op::Bgt | Op::Ble
+    )
+}
+
+// The instruction always continues with the next one
+fn falls_through_only(word: u32) -> bool {
+    match isa::decode(word) {
+        Some(op) => !matches!(op, Op::Goto | Op::Call | Op::Return | Op::Exit) && !is_conditional(op),
+        None => false,
+    }
+}
@@ -0,0 +1,98 @@
+// Base64 and hex conversion of memory regions (opcode 11). Each instruction
+// pops [len] [src] [dst] (top first), converts the len bytes at src and
+// writes the result at dst, which may overlap src. The output length is
+// pushed, or -1 if the input is malformed or a region is out of bounds.
+
+use crate::isa::Op;
+use crate::VM;
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+impl VM {
+    pub(crate) fn exec_codec(&mut self, op: Op) {
+        let len = self.pop() as usize;
+        let src = self.pop() as usize;
+        let dst = self.pop() as usize;
+
+        let input = match src.checked_add(len).and_then(|end| self.memory.get(src..end)) {
+            Some(input) => input,
+            None => return self.push(-1i32 as u32),
+        };
+        let output = match op {
+            Op::B64enc => Some(base64_encode(input)),
+            Op::B64dec => base64_decode(input),
+            Op::Hexenc => Some(hex_encode(input)),
+            Op::Hexdec => hex_decode(input),
+            _ => None
+        };
+
+        match output {
+            Some(output) if dst.checked_add(output.len()).is_some_and(|end| end <= self.memory.len()) => {
+                self.memory[dst..dst + output.len()].copy_from_slice(&output);
+                self.invalidate_code(dst, output.len());
+                self.trace_write(dst, &output);
+                self.push(output.len() as u32);
+            }
+            _ => self.push(-1i32 as u32),
+        }
+    }
+}
+
+fn base64_encode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |acc, (i, &b)| acc | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize]);
+            } else {
+                output.push(b'=');
+            }
+        }
+    }
+    output
+}
+
+fn base64_decode(input: &[u8]) -> Option<Vec<u8>> {
+    if !input.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut output = Vec::with_capacity(input.len() / 4 * 3);
+    for (index, chunk) in input.chunks(4).enumerate() {
+        let last = index == input.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut bits = 0u32;
+        for &c in &chunk[..4 - padding] {
+            let value = BASE64_ALPHABET.iter().position(|&a| a == c)? as u32;
+            bits = bits << 6 | value;
+        }
+        bits <<= 6 * padding;
+        output.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+    }
+    Some(output)
+}
+
+fn hex_encode(input: &[u8]) -> Vec<u8> {
+    input
+        .iter()
+        .flat_map(|&b| [HEX_DIGITS[(b >> 4) as usize], HEX_DIGITS[(b & 0xF) as usize]])
+        .collect()
+}
+
+fn hex_decode(input: &[u8]) -> Option<Vec<u8>> {
+    if !input.len().is_multiple_of(2) {
+        return None;
+    }
+    input
+        .chunks(2)
+        .map(|pair| {
+            let high = (pair[0] as char).to_digit(16)?;
+            let low = (pair[1] as char).to_digit(16)?;
+            Some((high << 4 | low) as u8)
+        })
+        .collect()
+}
@@ -0,0 +1,856 @@
+// Ahead-of-time translation to C behind `vmma31 compile`. The program becomes
+// one C function with a label per instruction: branches with a fixed target
+// are plain gotos, and return, whose target is only known at run time, goes
+// through a switch on the address. The runtime below reproduces what the VM
+// does by default (no strict mode, shadow stack on), including fault
+// messages and exit statuses, so the compiled program behaves like
+// `vmma31 program.v`.
+//
+// Translation happens once, so code the program writes over at run time
+// keeps its original meaning. Instructions that need the host
+// (syscall, fork, wait, cpuid, counter and the codec group) are rejected.
+// Memory is 4096 bytes unless the C file is built with -DRAM_SIZE=<bytes>.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::flags;
+use crate::isa::{self, Op};
+use crate::program::Sections;
+use crate::{split_program, DEFAULT_RAM_SIZE, FAULT_EXIT_CODE};
+
+// Translate magic-prefixed bytecode into a standalone C program
+pub fn compile_c(bytes: &[u8]) -> Result<String, String> {
+    let (sections, code) = split_program(bytes)?;
+    sections.check_entry(code.len())?;
+    sections.check_blocks(code.len(), u32::MAX as usize)?;
+    let mut instructions = Vec::new();
+    let mut labels = Labels::default();
+    if sections.entry != 0 {
+        labels.targets.insert(sections.entry);
+    }
+    for pc in (0..code.len()).step_by(4) {
+        // The VM reads past a short last word as zeros
+        let mut bytes = [0u8; 4];
+        let end = (pc + 4).min(code.len());
+        bytes[..end - pc].copy_from_slice(&code[pc..end]);
+        let word = u32::from_le_bytes(bytes);
+        let statement = match isa::lookup(word) {
+            Some(spec) => translate(spec.op, pc, word, code.len(), &sections, &mut labels)
+                .ok_or_else(|| format!("{} at 0x{:04x} can't be compiled", spec.mnemonic, pc))?,
+            None => format!("invalid(0x{:08x}u);", word),
+        };
+        instructions.push((pc, word, statement));
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "/* Generated by vmma31 compile. */");
+    let _ = writeln!(out, "#ifndef RAM_SIZE\n#define RAM_SIZE {}\n#endif", DEFAULT_RAM_SIZE);
+    let _ = writeln!(out, "#define CODE_SIZE {}", code.len());
+    let _ = writeln!(out, "#define FAULT_EXIT_CODE {}", FAULT_EXIT_CODE);
+    let _ = writeln!(out, "#define HEAP_START {}", sections.heap_start(code.len()));
+    out.push_str(RUNTIME_HEADER);
+    // Memory starts as the code followed by the data blocks
+    let mut image = code.to_vec();
+    for (addr, data) in &sections.data {
+        image.resize(image.len().max(addr + data.len()), 0);
+        image[*addr..addr + data.len()].copy_from_slice(data);
+    }
+    let init: Vec<String> = image.iter().map(|b| format!("0x{:02x}", b)).collect();
+    let init = if init.is_empty() { "0".to_string() } else { init.join(",") };
+    let _ = writeln!(out, "static uint8_t mem[RAM_SIZE] = {{{}}};", init);
+    out.push_str(RUNTIME);
+
+    out.push_str("\nint main(void)\n{\n");
+    if sections.entry != 0 {
+        let _ = writeln!(out, "    goto a{:04x};", sections.entry);
+    }
+    for (pc, word, statement) in &instructions {
+        if labels.dispatch || labels.targets.contains(pc) {
+            let _ = writeln!(out, "a{:04x}:", pc);
+        }
+        let _ = writeln!(out, "    /* {:04x}: {:08x}  {} */", pc, word, isa::disassemble(*word));
+        let _ = writeln!(out, "    pc = 0x{:x};", pc);
+        if !statement.is_empty() {
+            let _ = writeln!(out, "    {}", statement);
+        }
+    }
+    // Running off the end of the code finishes with status 0, as does a
+    // branch outside it
+    if labels.done || labels.dispatch {
+        out.push_str("done:\n");
+    }
+    out.push_str("    finish(0);\n");
+    if labels.dispatch {
+        out.push_str("dispatch:\n    switch (pc) {\n");
+        for (pc, _, _) in &instructions {
+            let _ = writeln!(out, "    case 0x{:x}: goto a{:04x};", pc, pc);
+        }
+        // The shadow stack only lets return go to the word after a call
+        out.push_str("    default:\n        goto done;\n    }\n");
+    }
+    out.push_str("    return 0;\n}\n");
+    Ok(out)
+}
+
+// Labels the generated code jumps to, so a C compiler building with -Wall
+// doesn't see unused ones. With a dispatch switch every instruction needs one.
+#[derive(Default)]
+struct Labels {
+    targets: HashSet<usize>, // Instructions that fixed branches go to
+    dispatch: bool,          // Jump to an address only known at run time
+    done: bool,              // Branch out of the code
+}
+
+// C statements for one instruction; None if it can't be compiled. sections
+// are the program's, for its globals region and return stack mode.
+fn translate(op: Op, pc: usize, word: u32, code_size: usize, sections: &Sections, labels: &mut Labels) -> Option<String> {
+    let spec = op.spec();
+    let globals = sections.globals.unwrap_or_default();
+    let [a, b] = spec.operands(word);
+    let jump = match spec.operands.first() {
+        Some(field) if field.branch => jump_to(pc as i64 + a as i64, pc, code_size, labels),
+        _ => String::new(),
+    };
+    let binary = |expr: &str| format!("{{ uint32_t r = pop(), l = pop(); push({}); }}", expr);
+    let unary = |expr: &str| format!("{{ uint32_t v = pop(); push({}); }}", expr);
+    let statement = match op {
+        Op::Exit => format!("finish({});", a),
+        Op::Swap => format!("swap({}, {});", a, b),
+        Op::Nop | Op::Debug => String::new(),
+        Op::Input => "input();".to_string(),
+        Op::Stinput => format!("stinput({}u);", a),
+        Op::Trapv => format!("trap_overflow = {};", (a != 0) as i32),
+        Op::Pop => format!("pop_bytes({});", a),
+        Op::Add => binary("checked((int64_t)(int32_t)l + (int32_t)r)"),
+        Op::Sub => binary("checked((int64_t)(int32_t)l - (int32_t)r)"),
+        Op::Mul => binary("checked((int64_t)(int32_t)l * (int32_t)r)"),
+        Op::Div => binary("div32(l, r)"),
+        Op::Rem => binary("rem32(l, r)"),
+        Op::And => binary("l & r"),
+        Op::Or => binary("l | r"),
+        Op::Xor => binary("l ^ r"),
+        Op::Lsl => binary("l << (r & 31)"),
+        Op::Lsr => binary("l >> (r & 31)"),
+        Op::Asr => binary("(uint32_t)((int32_t)l >> (r & 31))"),
+        Op::Min => binary("(int32_t)l < (int32_t)r ? l : r"),
+        Op::Max => binary("(int32_t)l > (int32_t)r ? l : r"),
+        Op::Mulh => binary("(uint32_t)((int64_t)(int32_t)l * (int32_t)r >> 32)"),
+        Op::Mulhu => binary("(uint32_t)((uint64_t)l * r >> 32)"),
+        Op::Qmul => binary(&format!("qmul(l, r, {})", a)),
+        Op::Qdiv => binary(&format!("qdiv(l, r, {})", a)),
+        Op::Neg => unary("0u - v"),
+        Op::Not => unary("~v"),
+        Op::Toupper => unary("map_chars(v, 'a', 'z', -32)"),
+        Op::Tolower => unary("map_chars(v, 'A', 'Z', 32)"),
+        Op::Isdigit => unary("all_chars(v, is_digit)"),
+        Op::Isalpha => unary("all_chars(v, is_alpha)"),
+        Op::Popcount => unary("popcount(v)"),
+        Op::Clz => unary("clz(v)"),
+        Op::Ctz => unary("ctz(v)"),
+        Op::Abs => unary("(int32_t)v < 0 ? 0u - v : v"),
+        Op::Stprint => format!("stprint({});", a),
+        Op::Call if sections.return_stack => format!("rcall(0x{:x}); {}", pc + 4, jump).trim_end().to_string(),
+        Op::Call => format!("push(0x{0:x}); shadow_call(0x{0:x}); {1}", pc + 4, jump).trim_end().to_string(),
+        Op::Return => {
+            labels.dispatch = true;
+            let ret = if sections.return_stack { "rret" } else { "ret" };
+            format!("if ({}({})) goto dispatch;", ret, a)
+        }
+        Op::Goto => jump,
+        Op::Ifeq | Op::Ifne | Op::Iflt | Op::Ifgt | Op::Ifle | Op::Ifge => {
+            let condition = match op {
+                Op::Ifeq => "==",
+                Op::Ifne => "!=",
+                Op::Iflt => "<",
+                Op::Ifgt => ">",
+                Op::Ifle => "<=",
+                _ => ">=",
+            };
+            conditional(&format!("(int32_t)peek(4) {} (int32_t)peek(0)", condition), jump)
+        }
+        Op::Ifltu | Op::Ifgtu | Op::Ifleu | Op::Ifgeu => {
+            let condition = match op {
+                Op::Ifltu => "<",
+                Op::Ifgtu => ">",
+                Op::Ifleu => "<=",
+                _ => ">=",
+            };
+            conditional(&format!("peek(4) {} peek(0)", condition), jump)
+        }
+        Op::Ifez | Op::Ifnz | Op::Ifmi | Op::Ifpl => {
+            let condition = match op {
+                Op::Ifez => "== 0",
+                Op::Ifnz => "!= 0",
+                Op::Ifmi => "< 0",
+                _ => ">= 0",
+            };
+            conditional(&format!("(int32_t)peek(0) {}", condition), jump)
+        }
+        Op::Vadd => "vector(0);".to_string(),
+        Op::Vsub => "vector(1);".to_string(),
+        Op::Vmul => "vector(2);".to_string(),
+        Op::Load => "push(rd(pop()));".to_string(),
+        Op::Loadi => format!("push(rd({}));", a),
+        Op::Loadb => "push(rdb(pop()));".to_string(),
+        Op::Loadbi => format!("push(rdb({}));", a),
+        Op::Store => "{ uint32_t addr = pop(), v = pop(); wr(addr, v); }".to_string(),
+        Op::Storei => format!("wr({}, pop());", a),
+        Op::Storeb => "{ uint32_t addr = pop(), v = pop(); wrb(addr, v); }".to_string(),
+        Op::Storebi => format!("wrb({}, pop());", a),
+        // Globals past the region read 0 and aren't written
+        Op::Gload if (a as usize) < globals.1 => format!("push(rd({}));", globals.0 + a as usize * 4),
+        Op::Gload => "push(0);".to_string(),
+        Op::Gstore if (a as usize) < globals.1 => format!("wr({}, pop());", globals.0 + a as usize * 4),
+        Op::Gstore => "pop();".to_string(),
+        Op::Enter => format!("enter({});", a as i64 * 4),
+        Op::Leave => "leave();".to_string(),
+        Op::Lload => format!("push(rd(fp + {}));", a as i64 * 4),
+        Op::Lstore => format!("{{ uint32_t v = pop(); wr(fp + {}, v); }}", a as i64 * 4),
+        Op::Alloc => "push(heap_alloc(pop()));".to_string(),
+        Op::Free => "{ uint32_t addr = pop(); if (addr != 0) heap_free(addr); }".to_string(),
+        Op::Memcpy => "{ uint32_t len = pop(), src = pop(), dst = pop(); block_copy(dst, src, len); }".to_string(),
+        Op::Memset => "{ uint32_t len = pop(), v = pop(), dst = pop(); block_fill(dst, v, len); }".to_string(),
+        Op::Memcmp => "{ uint32_t len = pop(), b = pop(), a = pop(); push(block_compare(a, b, len)); }".to_string(),
+        Op::Add64 => "{ uint64_t r = pop_wide(), l = pop_wide(); push_wide(l + r); }".to_string(),
+        Op::Sub64 => "{ uint64_t r = pop_wide(), l = pop_wide(); push_wide(l - r); }".to_string(),
+        Op::Mul64 => "{ uint64_t r = pop_wide(), l = pop_wide(); push_wide(l * r); }".to_string(),
+        Op::Cmp64 => "{ int64_t r = (int64_t)pop_wide(), l = (int64_t)pop_wide(); push((l > r) - (l < r)); }".to_string(),
+        Op::Cmp => "{ uint32_t r = pop(), l = pop(); compare(l, r); }".to_string(),
+        Op::Beq | Op::Bne | Op::Bhs | Op::Blo | Op::Bmi | Op::Bpl | Op::Bvs | Op::Bvc
+        | Op::Bhi | Op::Bls | Op::Bge | Op::Blt | Op::Bgt | Op::Ble => {
+            conditional(&format!("condition({})", flags::condition_code(op)), jump)
+        }
+        Op::Dup => format!("push(peek({}));", a),
+        Op::Print => format!("print_word(peek({}), 0);", a),
+        Op::Printh => format!("print_word(peek({}), 1);", a),
+        Op::Printb => format!("print_word(peek({}), 2);", a),
+        Op::Printo => format!("print_word(peek({}), 3);", a),
+        Op::Printf => format!("print_word(peek({}), 0x{:x});", a, b),
+        Op::Dump => "dump();".to_string(),
+        Op::Push => format!("push(0x{:08x}u);", a as u32),
+        Op::Syscall | Op::Fork | Op::Wait | Op::Cpuid | Op::Counter => return None,
+        Op::B64enc | Op::B64dec | Op::Hexenc | Op::Hexdec => return None,
+    };
+    Some(statement)
+}
+
+// Branch to a fixed target. A branch to itself leaves the PC unchanged, so
+// the VM moves on to the next instruction.
+fn jump_to(target: i64, pc: usize, code_size: usize, labels: &mut Labels) -> String {
+    if target == pc as i64 {
+        String::new()
+    } else if target < 0 || target >= code_size as i64 {
+        labels.done = true;
+        "goto done;".to_string()
+    } else {
+        labels.targets.insert(target as usize);
+        format!("goto a{:04x};", target)
+    }
+}
+
+// Conditions only peek, so a branch that goes nowhere has no effect at all
+fn conditional(condition: &str, jump: String) -> String {
+    if jump.is_empty() {
+        String::new()
+    } else {
+        format!("if ({}) {}", condition, jump)
+    }
+}
+
+const RUNTIME_HEADER: &str = r#"#if RAM_SIZE < CODE_SIZE || RAM_SIZE % 4 != 0
+#error "RAM_SIZE must be a multiple of 4 large enough for the code"
+#endif
+
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+"#;
+
+// Helpers are static inline so the C compiler doesn't warn about the ones a
+// program never uses
+const RUNTIME: &str = r#"
+static int64_t sp = RAM_SIZE;
+static int64_t fp = RAM_SIZE;
+static int64_t heap_top; /* End of the last heap block; 0 while there are none */
+static uint32_t heap[RAM_SIZE / 4]; /* Size of the heap block at each word, | 1 while in use */
+static uint32_t flags; /* N, Z, C and V as cmp last set them, in bits 3 to 0 */
+static int trap_overflow; /* Set by trapv: add, sub, mul, qmul and qdiv fault on signed overflow */
+static uint32_t pc;
+static uint32_t *shadow;
+static size_t shadow_len, shadow_cap;
+static char *line;
+static size_t line_cap;
+
+static inline void finish(int code)
+{
+    fflush(stdout);
+    exit(code);
+}
+
+static inline void fault(const char *what)
+{
+    fflush(stdout);
+    fprintf(stderr, "Error: %s at pc 0x%" PRIx32 "\n", what, pc);
+    exit(FAULT_EXIT_CODE);
+}
+
+static inline void invalid(uint32_t word)
+{
+    fflush(stdout);
+    fprintf(stderr, "Error: invalid instruction 0x%08" PRIx32 " at pc 0x%" PRIx32 "\n", word, pc);
+    exit(FAULT_EXIT_CODE);
+}
+
+static inline int in_bounds(int64_t addr, int64_t len)
+{
+    return addr >= 0 && addr + len <= RAM_SIZE;
+}
+
+static inline uint32_t rd(int64_t addr)
+{
+    if (!in_bounds(addr, 4))
+        return 0;
+    return mem[addr] | (uint32_t)mem[addr + 1] << 8 | (uint32_t)mem[addr + 2] << 16 | (uint32_t)mem[addr + 3] << 24;
+}
+
+static inline void wr(int64_t addr, uint32_t value)
+{
+    if (!in_bounds(addr, 4))
+        return;
+    for (int i = 0; i < 4; i++)
+        mem[addr + i] = (uint8_t)(value >> 8 * i);
+}
+
+static inline uint32_t rdb(int64_t addr)
+{
+    return in_bounds(addr, 1) ? mem[addr] : 0;
+}
+
+static inline void wrb(int64_t addr, uint32_t value)
+{
+    if (in_bounds(addr, 1))
+        mem[addr] = (uint8_t)value;
+}
+
+/* How many of the len bytes at addr are inside memory */
+static inline int64_t bytes_inside(int64_t addr, int64_t len)
+{
+    if (addr >= RAM_SIZE)
+        return 0;
+    return len < RAM_SIZE - addr ? len : RAM_SIZE - addr;
+}
+
+/* memcpy, memset and memcmp act like byte loops of loadb and storeb:
+   outside memory bytes read as 0 and writes are dropped. The copy goes
+   as if through a buffer, so the ranges may overlap. */
+static inline void block_copy(int64_t dst, int64_t src, int64_t len)
+{
+    int64_t n = bytes_inside(dst, len);
+    if (dst < src) {
+        for (int64_t i = 0; i < n; i++)
+            mem[dst + i] = (uint8_t)rdb(src + i);
+    } else {
+        for (int64_t i = n - 1; i >= 0; i--)
+            mem[dst + i] = (uint8_t)rdb(src + i);
+    }
+}
+
+static inline void block_fill(int64_t dst, uint32_t value, int64_t len)
+{
+    int64_t n = bytes_inside(dst, len);
+    if (n > 0)
+        memset(mem + dst, (uint8_t)value, (size_t)n);
+}
+
+static inline uint32_t block_compare(int64_t a, int64_t b, int64_t len)
+{
+    int64_t n = bytes_inside(a < b ? a : b, len);
+    for (int64_t i = 0; i < n; i++) {
+        uint32_t x = rdb(a + i), y = rdb(b + i);
+        if (x != y)
+            return x < y ? (uint32_t)-1 : 1;
+    }
+    return 0;
+}
+
+static inline void push(uint32_t value)
+{
+    if (sp < heap_top + 4)
+        fault("stack overflow");
+    sp -= 4;
+    wr(sp, value);
+}
+
+static inline uint32_t pop(void)
+{
+    if (sp + 4 > RAM_SIZE)
+        fault("stack underflow");
+    uint32_t value = rd(sp);
+    sp += 4;
+    return value;
+}
+
+static inline void enter(int64_t bytes)
+{
+    push((uint32_t)fp);
+    fp = sp;
+    if (sp < heap_top + bytes)
+        fault("stack overflow");
+    sp -= bytes;
+}
+
+static inline void leave(void)
+{
+    sp = fp < RAM_SIZE ? fp : RAM_SIZE;
+    fp = pop();
+}
+
+/* First fit among freed blocks, splitting a bigger one, or else a new block
+   on top of the heap as long as it stays below the stack */
+static inline uint32_t heap_alloc(uint32_t request)
+{
+    int64_t size = request == 0 ? 4 : ((int64_t)request + 3) / 4 * 4;
+    int64_t addr = HEAP_START, end = heap_top ? heap_top : HEAP_START;
+    for (; addr < end; addr += heap[addr / 4] & ~1u) {
+        uint32_t block = heap[addr / 4];
+        if (!(block & 1) && block >= size) {
+            if (block > size)
+                heap[(addr + size) / 4] = block - (uint32_t)size;
+            heap[addr / 4] = (uint32_t)size | 1;
+            return (uint32_t)addr;
+        }
+    }
+    if (addr + size > sp) {
+        fflush(stdout);
+        fprintf(stderr, "Error: out of memory allocating %" PRIu32 " bytes at pc 0x%" PRIx32 "\n", request, pc);
+        exit(FAULT_EXIT_CODE);
+    }
+    heap[addr / 4] = (uint32_t)size | 1;
+    heap_top = addr + size;
+    return (uint32_t)addr;
+}
+
+/* Merge the block with free neighbours; a free block on top goes back to
+   the stack. Addresses alloc didn't return are ignored. */
+static inline void heap_free(uint32_t addr)
+{
+    int64_t at = HEAP_START, prev = -1;
+    while (at < heap_top && at != addr) {
+        prev = at;
+        at += heap[at / 4] & ~1u;
+    }
+    if (at >= heap_top || !(heap[at / 4] & 1))
+        return;
+    uint32_t size = heap[at / 4] & ~1u;
+    if (at + size < heap_top && !(heap[(at + size) / 4] & 1))
+        size += heap[(at + size) / 4];
+    if (prev >= 0 && !(heap[prev / 4] & 1)) {
+        size += heap[prev / 4];
+        at = prev;
+    }
+    if (at + size == heap_top)
+        heap_top = at == HEAP_START ? 0 : at;
+    else
+        heap[at / 4] = size;
+}
+
+static inline uint32_t peek(int32_t offset)
+{
+    return rd(sp + offset);
+}
+
+static inline void pop_bytes(int64_t bytes)
+{
+    sp = sp + bytes <= RAM_SIZE ? sp + bytes : RAM_SIZE;
+}
+
+static inline void swap(int32_t from, int32_t to)
+{
+    if (in_bounds(sp + from, 4) && in_bounds(sp + to, 4)) {
+        uint32_t first = peek(from), second = peek(to);
+        wr(sp + from, second);
+        wr(sp + to, first);
+    }
+}
+
+/* A result computed in 64 bits as a word, which faults if it doesn't fit in
+   one while trapv is on */
+static inline uint32_t checked(int64_t result)
+{
+    if (trap_overflow && result != (int32_t)result)
+        fault("arithmetic overflow");
+    return (uint32_t)result;
+}
+
+/* Division by -1 of the smallest word wraps instead of trapping */
+static inline uint32_t div32(uint32_t l, uint32_t r)
+{
+    if (r == 0)
+        fault("divide by zero");
+    if (l == 0x80000000u && r == 0xffffffffu)
+        return l;
+    return (uint32_t)((int32_t)l / (int32_t)r);
+}
+
+static inline uint32_t rem32(uint32_t l, uint32_t r)
+{
+    if (r == 0)
+        fault("divide by zero");
+    if (l == 0x80000000u && r == 0xffffffffu)
+        return 0;
+    return (uint32_t)((int32_t)l % (int32_t)r);
+}
+
+/* Q16.16 division of n by a non-zero d with the instruction's rounding mode */
+static inline int64_t fixed_divide(int64_t n, int64_t d, int rounding)
+{
+    int64_t quotient = n / d, remainder = n % d;
+    int negative = (n < 0) != (d < 0);
+    if (remainder != 0) {
+        int64_t twice = remainder < 0 ? -2 * remainder : 2 * remainder;
+        if ((rounding == 1 && twice >= (d < 0 ? -d : d)) || (rounding == 2 && negative) || (rounding == 3 && !negative))
+            quotient += negative ? -1 : 1;
+    }
+    return quotient;
+}
+
+static inline uint32_t qmul(uint32_t l, uint32_t r, int rounding)
+{
+    return checked(fixed_divide((int64_t)(int32_t)l * (int32_t)r, 65536, rounding));
+}
+
+static inline uint32_t qdiv(uint32_t l, uint32_t r, int rounding)
+{
+    if (r == 0)
+        fault("divide by zero");
+    return checked(fixed_divide((int64_t)(int32_t)l * 65536, (int32_t)r, rounding));
+}
+
+static inline uint32_t map_chars(uint32_t word, int low, int high, int delta)
+{
+    for (int i = 0; i < 3; i++) {
+        int c = word >> 8 * i & 0xff;
+        if (c >= low && c <= high)
+            word = (word & ~(0xffu << 8 * i)) | (uint32_t)(c + delta) << 8 * i;
+    }
+    return word;
+}
+
+static inline int is_digit(int c)
+{
+    return c >= '0' && c <= '9';
+}
+
+static inline int is_alpha(int c)
+{
+    return (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z');
+}
+
+static inline uint32_t all_chars(uint32_t word, int (*test)(int))
+{
+    int i;
+    for (i = 0; i < 3 && (word >> 8 * i & 0xff) != 0; i++) {
+        if (!test(word >> 8 * i & 0xff))
+            return 0;
+    }
+    return i > 0;
+}
+
+/* Bit counts, 32 for clz and ctz of 0 */
+static inline uint32_t popcount(uint32_t v)
+{
+    uint32_t n = 0;
+    for (; v != 0; v &= v - 1)
+        n++;
+    return n;
+}
+
+static inline uint32_t clz(uint32_t v)
+{
+    uint32_t n = 0;
+    for (; n < 32 && !(v & 0x80000000u); v <<= 1)
+        n++;
+    return n;
+}
+
+static inline uint32_t ctz(uint32_t v)
+{
+    uint32_t n = 0;
+    for (; n < 32 && !(v & 1); v >>= 1)
+        n++;
+    return n;
+}
+
+static inline void vector(int op)
+{
+    uint32_t right[4], left[4];
+    for (int i = 0; i < 4; i++)
+        right[i] = pop();
+    for (int i = 0; i < 4; i++)
+        left[i] = pop();
+    for (int i = 3; i >= 0; i--)
+        push(op == 0 ? left[i] + right[i] : op == 1 ? left[i] - right[i] : left[i] * right[i]);
+}
+
+/* A 64-bit value is two words, the low one on top */
+static inline uint64_t pop_wide(void)
+{
+    uint64_t low = pop();
+    return (uint64_t)pop() << 32 | low;
+}
+
+static inline void push_wide(uint64_t value)
+{
+    push((uint32_t)(value >> 32));
+    push((uint32_t)value);
+}
+
+static inline void compare(uint32_t l, uint32_t r)
+{
+    uint32_t d = l - r;
+    flags = (d >> 31) << 3 | (d == 0) << 2 | (l >= r) << 1 | ((l ^ r) & (l ^ d)) >> 31;
+}
+
+/* Each even condition code tests the flags, and the odd one after it is the opposite */
+static inline int condition(int code)
+{
+    int n = flags >> 3 & 1, z = flags >> 2 & 1, c = flags >> 1 & 1, v = flags & 1;
+    int tests[] = { z, c, n, v, c && !z, n == v, !z && n == v };
+    return tests[code >> 1] != (code & 1);
+}
+
+/* Bytes above 0x7f are printed as the Unicode characters U+0080 to U+00FF */
+static inline void put_byte(int c)
+{
+    if (c < 0x80) {
+        putchar(c);
+    } else {
+        putchar(0xc0 | c >> 6);
+        putchar(0x80 | (c & 0x3f));
+    }
+}
+
+static inline void stprint(int32_t offset)
+{
+    for (int64_t addr = sp + offset; addr >= 0 && addr < RAM_SIZE && mem[addr] != 0; addr++) {
+        if (mem[addr] != 1)
+            put_byte(mem[addr]);
+    }
+}
+
+/* Format bits 2:0: 0 decimal, 1 hex, 2 binary, 3 octal, 4 the low byte as a
+   character. Bit 3 leaves off the newline, bit 4 pads with zeros after the
+   sign or prefix instead of spaces before, and bits 11:5 are the width. */
+static inline void print_word(uint32_t value, uint32_t format)
+{
+    char digits[33];
+    const char *prefix = "";
+    int len = 0;
+    switch (format & 7) {
+    case 1:
+        prefix = "0x";
+        len = sprintf(digits, "%" PRIx32, value);
+        break;
+    case 2: {
+        int bit = 31;
+        while (bit > 0 && !(value >> bit & 1))
+            bit--;
+        prefix = "0b";
+        for (; bit >= 0; bit--)
+            digits[len++] = (char)('0' + (value >> bit & 1));
+        break;
+    }
+    case 3:
+        prefix = "0o";
+        len = sprintf(digits, "%" PRIo32, value);
+        break;
+    case 4:
+        len = 1;
+        break;
+    default:
+        if ((int32_t)value < 0) {
+            prefix = "-";
+            value = 0u - value;
+        }
+        len = sprintf(digits, "%" PRIu32, value);
+    }
+    int pad = (int)(format >> 5) - (int)strlen(prefix) - len;
+    for (; !(format & 0x10) && pad > 0; pad--)
+        putchar(' ');
+    fputs(prefix, stdout);
+    for (; pad > 0; pad--)
+        putchar('0');
+    if ((format & 7) == 4)
+        put_byte(value & 0xff);
+    else
+        fwrite(digits, 1, (size_t)len, stdout);
+    if (!(format & 8))
+        putchar('\n');
+}
+
+static inline void dump(void)
+{
+    for (int64_t addr = sp; addr < RAM_SIZE; addr += 4)
+        printf("%04" PRIx64 ": %08" PRIx32 "\n", (uint64_t)(addr - sp), rd(addr));
+}
+
+static inline void shadow_call(uint32_t return_addr)
+{
+    if (shadow_len == shadow_cap) {
+        shadow_cap = shadow_cap ? 2 * shadow_cap : 64;
+        shadow = realloc(shadow, shadow_cap * sizeof *shadow);
+        if (!shadow)
+            abort();
+    }
+    shadow[shadow_len++] = return_addr;
+}
+
+/* With a separate return stack the shadow stack is the only one, as deep
+   as memory has words */
+static inline void rcall(uint32_t return_addr)
+{
+    if (shadow_len >= RAM_SIZE / 4)
+        fault("stack overflow");
+    shadow_call(return_addr);
+}
+
+static inline int rret(int32_t offset)
+{
+    uint32_t here = pc;
+    if (offset != 0 && sp + offset >= 0 && sp + offset <= RAM_SIZE)
+        sp += offset;
+    if (shadow_len == 0)
+        return 0;
+    pc = shadow[--shadow_len];
+    return pc != here;
+}
+
+/* Pop the return address into pc; returns 0 when the stack is empty and
+   execution carries on with the next instruction */
+static inline int ret(int32_t offset)
+{
+    uint32_t here = pc;
+    if (offset != 0 && sp + offset >= 0 && sp + offset <= RAM_SIZE)
+        sp += offset;
+    if (sp >= RAM_SIZE)
+        return 0;
+    uint32_t addr = pop();
+    if (shadow_len == 0 || shadow[shadow_len - 1] != addr) {
+        fflush(stdout);
+        if (shadow_len == 0)
+            fprintf(stderr, "Error: shadow stack mismatch (no matching call) at pc 0x%" PRIx32 "\n", here);
+        else
+            fprintf(stderr, "Error: shadow stack mismatch (popped 0x%" PRIx32 ", expected 0x%" PRIx32 ") at pc 0x%" PRIx32 "\n",
+                    addr, shadow[shadow_len - 1], here);
+        exit(FAULT_EXIT_CODE);
+    }
+    shadow_len--;
+    pc = addr;
+    return addr != here;
+}
+
+/* Read one line including its newline, after showing any pending output */
+static inline size_t read_line(void)
+{
+    size_t len = 0;
+    int c;
+    fflush(stdout);
+    while ((c = getchar()) != EOF) {
+        if (len + 1 >= line_cap) {
+            line_cap = line_cap ? 2 * line_cap : 128;
+            line = realloc(line, line_cap);
+            if (!line)
+                abort();
+        }
+        line[len++] = (char)c;
+        if (c == '\n')
+            break;
+    }
+    return len;
+}
+
+static inline int is_space(int c)
+{
+    return c == ' ' || (c >= '\t' && c <= '\r');
+}
+
+/* The line without surrounding whitespace, as start and length */
+static inline size_t read_trimmed(size_t *start)
+{
+    size_t end = read_line();
+    *start = 0;
+    while (*start < end && is_space(line[*start]))
+        (*start)++;
+    while (end > *start && is_space(line[end - 1]))
+        end--;
+    return end - *start;
+}
+
+/* Optional sign and at least one digit; anything else, or a value that
+   doesn't fit a word, reads as 0 */
+static inline int32_t parse_int(const char *text, size_t len, int radix)
+{
+    size_t i = 0;
+    int negative = 0;
+    int64_t value = 0;
+    if (len > 0 && (text[0] == '+' || text[0] == '-')) {
+        negative = text[0] == '-';
+        i = 1;
+    }
+    if (i == len)
+        return 0;
+    for (; i < len; i++) {
+        int c = text[i], digit = -1;
+        if (c >= '0' && c <= '9')
+            digit = c - '0';
+        else if (c >= 'a' && c <= 'z')
+            digit = c - 'a' + 10;
+        else if (c >= 'A' && c <= 'Z')
+            digit = c - 'A' + 10;
+        if (digit < 0 || digit >= radix)
+            return 0;
+        value = value * radix + digit;
+        if (value > (negative ? 2147483648LL : 2147483647LL))
+            return 0;
+    }
+    return (int32_t)(negative ? -value : value);
+}
+
+static inline void input(void)
+{
+    size_t start, len = read_trimmed(&start);
+    const char *text = line + start;
+    int32_t value;
+    if (len >= 2 && text[0] == '0' && (text[1] == 'x' || text[1] == 'X'))
+        value = parse_int(text + 2, len - 2, 16);
+    else if (len >= 2 && text[0] == '0' && (text[1] == 'b' || text[1] == 'B'))
+        value = parse_int(text + 2, len - 2, 2);
+    else
+        value = parse_int(text, len, 10);
+    push((uint32_t)value);
+}
+
+/* Pack the line three characters to a word, with byte 3 flagging that more
+   follow, and push the words so the first chunk ends up on top */
+static inline void stinput(uint32_t max_chars)
+{
+    size_t start, len = read_trimmed(&start);
+    if (len == 0) {
+        push(0);
+        return;
+    }
+    if (max_chars != 0xffffff && len > max_chars)
+        len = max_chars;
+    for (size_t chunk = (len + 2) / 3; chunk-- > 0;) {
+        uint32_t word = 0;
+        for (size_t i = 0; i < 3 && chunk * 3 + i < len; i++)
+            word |= (uint32_t)(uint8_t)line[start + chunk * 3 + i] << 8 * i;
+        if (chunk * 3 + 3 < len)
+            word |= 0x01000000u;
+        push(word);
+    }
+}
+"#;
@@ -0,0 +1,208 @@
+// Conformance runner for `vmma31 conformance <dir>`. Each .json file in the
+// directory holds one spec case or an array of them: encoded instruction
+// words, an optional starting stack and input, and the expected final state
+// and output. Results are grouped by opcode so other implementations of the
+// ISA can see exactly which instructions disagree with this one.
+//
+//   {
+//     "name": "add two numbers",
+//     "opcode": "add",
+//     "program": ["0x20000000"],
+//     "stack": [2, 3],
+//     "input": [],
+//     "strict": false,
+//     "trap_overflow": false,
+//     "return_stack": false,
+//     "ram_size": 4096,
+//     "expect": { "stack": [5], "pc": 4, "exit_code": 0, "output": "" }
+//   }
+//
+// Stacks are listed bottom first. Words may be numbers or "0x" strings, and
+// program words may also be written as assembly, e.g. "push 5" or "goto -8".
+// Every field of "expect" is optional; only the ones given are checked,
+// except that a case faulting (see VmError) fails unless "error" names the
+// fault kind it expects.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{isa, FuelStop, DEFAULT_RAM_SIZE, VM};
+
+const STEP_LIMIT: u64 = 100_000; // Cases that don't finish within this fail
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CaseFile {
+    One(Box<Case>),
+    Many(Vec<Case>),
+}
+
+#[derive(Deserialize)]
+struct Case {
+    name: Option<String>,
+    opcode: Option<String>, // Defaults to the file name
+    program: Vec<Word>,
+    #[serde(default)]
+    stack: Vec<Word>,
+    #[serde(default)]
+    input: Vec<String>,
+    #[serde(default)]
+    strict: bool, // Run with set_strict
+    #[serde(default)]
+    trap_overflow: bool, // Run with set_trap_overflow
+    #[serde(default)]
+    return_stack: bool, // Run with set_return_stack
+    ram_size: Option<usize>, // Defaults to DEFAULT_RAM_SIZE
+    expect: Expect,
+}
+
+#[derive(Deserialize)]
+struct Expect {
+    stack: Option<Vec<Word>>,
+    pc: Option<usize>,
+    sp: Option<usize>,
+    fp: Option<usize>,
+    flags: Option<u32>, // Of flags::NEGATIVE and the rest
+    exit_code: Option<i32>,
+    output: Option<String>,
+    error: Option<String>, // Fault kind, e.g. "stack_underflow"; unset means no fault
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Word {
+    Number(i64),
+    Text(String),
+}
+
+impl Word {
+    fn value(&self) -> Result<u32, String> {
+        match self {
+            Word::Number(n) if (i32::MIN as i64..=u32::MAX as i64).contains(n) => Ok(*n as u32),
+            Word::Number(n) => Err(format!("word {} does not fit in 32 bits", n)),
+            Word::Text(text) => match text.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16).map_err(|_| format!("invalid word {:?}", text)),
+                None => isa::assemble(text),
+            },
+        }
+    }
+}
+
+fn words(list: &[Word]) -> Result<Vec<u32>, String> {
+    list.iter().map(Word::value).collect()
+}
+
+// Run every case under dir and print a per-opcode report. Returns whether all passed.
+pub fn run_suite(dir: &str) -> Result<bool, String> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read {}: {}", dir, e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    let mut results: BTreeMap<String, (usize, usize)> = BTreeMap::new(); // opcode -> (passed, total)
+    let mut failures = Vec::new();
+    for path in &paths {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let cases = match serde_json::from_str(&text) {
+            Ok(CaseFile::One(case)) => vec![*case],
+            Ok(CaseFile::Many(cases)) => cases,
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        for (index, case) in cases.iter().enumerate() {
+            let opcode = case.opcode.clone().unwrap_or_else(|| stem.clone());
+            let counts = results.entry(opcode).or_default();
+            counts.1 += 1;
+            match run_case(case) {
+                Ok(()) => counts.0 += 1,
+                Err(problem) => failures.push(format!("{}", CaseId { path, index, case, problem })),
+            }
+        }
+    }
+
+    let (mut passed, mut total) = (0, 0);
+    for (opcode, (ok, count)) in &results {
+        println!("{:<12} {}/{} {}", opcode, ok, count, if ok == count { "pass" } else { "FAIL" });
+        passed += ok;
+        total += count;
+    }
+    for failure in &failures {
+        println!("{}", failure);
+    }
+    println!("{}/{} cases passed", passed, total);
+    Ok(passed == total)
+}
+
+struct CaseId<'a> {
+    path: &'a Path,
+    index: usize,
+    case: &'a Case,
+    problem: String,
+}
+
+impl std::fmt::Display for CaseId<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "  FAIL {}#{}", self.path.display(), self.index)?;
+        if let Some(name) = &self.case.name {
+            write!(f, " ({})", name)?;
+        }
+        write!(f, ": {}", self.problem)
+    }
+}
+
+fn run_case(case: &Case) -> Result<(), String> {
+    let code: Vec<u8> = words(&case.program)?.iter().flat_map(|w| w.to_le_bytes()).collect();
+    let mut vm = VM::new(case.ram_size.unwrap_or(DEFAULT_RAM_SIZE));
+    vm.load_code(&code)?;
+    for value in words(&case.stack)? {
+        vm.push(value);
+    }
+    vm.capture_output();
+    vm.set_input(case.input.iter().cloned());
+    vm.set_strict(case.strict);
+    vm.set_trap_overflow(case.trap_overflow);
+    vm.set_return_stack(case.return_stack);
+
+    let fault = match vm.run_with_fuel(STEP_LIMIT).stop {
+        FuelStop::Exited(_) => None,
+        FuelStop::Trapped(error) => Some(error),
+        FuelStop::OutOfFuel => return Err(format!("did not finish within {} steps", STEP_LIMIT)),
+    };
+
+    let expect = &case.expect;
+    match (expect.error.as_deref(), &fault) {
+        (None, Some(error)) => return Err(format!("faulted: {}", error)),
+        (Some(kind), None) => return Err(format!("error: expected {}, but no fault", kind)),
+        (Some(kind), Some(error)) if kind != error.kind() => {
+            return Err(format!("error: expected {}, got {}", kind, error.kind()));
+        }
+        _ => {}
+    }
+    if let Some(stack) = &expect.stack {
+        let expected = words(stack)?;
+        let actual: Vec<u32> = vm.stack().into_iter().rev().collect();
+        if actual != expected {
+            return Err(format!("stack: expected {:x?}, got {:x?}", expected, actual));
+        }
+    }
+    check("pc", expect.pc, vm.pc)?;
+    check("sp", expect.sp, vm.sp)?;
+    check("fp", expect.fp, vm.fp)?;
+    check("flags", expect.flags, vm.flags)?;
+    check("exit code", expect.exit_code, vm.exit_code)?;
+    check("output", expect.output.as_deref(), vm.captured_output().unwrap_or(""))
+}
+
+fn check<T: PartialEq + std::fmt::Debug>(what: &str, expected: Option<T>, actual: T) -> Result<(), String> {
+    match expected {
+        Some(expected) if expected != actual => {
+            Err(format!("{}: expected {:?}, got {:?}", what, expected, actual))
+        }
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,221 @@
+// Calling-convention checker, enabled with --check-calls. Each call records
+// the SP just after the return address is pushed. A function must not pop
+// past that slot, must leave the return address intact, and must be back at
+// exactly that SP once `return` has discarded its locals. With a separate
+// return stack nothing is pushed, so the same goes for the SP at the call,
+// less the return address. Violations are recorded on the VM, for the host
+// to read back with call_violations, and execution continues. Functions are
+// named from the program's symbols when it has them.
+//
+// lint_calls makes the same check without running the program: it follows
+// each called function's control-flow graph, tracking SP relative to where
+// it was on entry, and reports paths that pop past that or reach return
+// somewhere else. It assumes the functions it calls keep to the convention
+// themselves. stinput and syscall move SP by amounts only known at run
+// time, so a path isn't followed past them, nor into a block that other
+// paths reach with a different SP.
+
+use std::fmt;
+
+use crate::cfg::{Cfg, EdgeKind};
+use crate::isa::{self, Op};
+use crate::{Symbols, VM};
+
+const MAX_VIOLATIONS: usize = 1000; // Later ones are dropped, so a loop can't use up host memory
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallViolation {
+    pub pc: usize,               // The offending instruction
+    pub entry: Option<usize>,    // Address of the function; None for a return without a call
+    pub call_pc: Option<usize>,  // Where the function was called from
+    pub name: Option<String>,    // The function's symbol, if the program has one
+    pub problem: String,
+}
+
+impl fmt::Display for CallViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(entry) = self.entry else {
+            return write!(f, "{} at pc 0x{:x}", self.problem, self.pc);
+        };
+        match &self.name {
+            Some(name) => write!(f, "function {}", name)?,
+            None => write!(f, "function 0x{:x}", entry)?,
+        }
+        if let Some(call_pc) = self.call_pc {
+            write!(f, " (called from 0x{:x})", call_pc)?;
+        }
+        write!(f, " {} at pc 0x{:x}", self.problem, self.pc)
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct CallFrame {
+    call_pc: usize, // Address of the call instruction
+    entry: usize,   // Address of the called function
+    sp: usize,      // SP after the return address was pushed
+    reported: bool, // Only report a runaway SP once per frame
+}
+
+impl VM {
+    pub(crate) fn track_call(&mut self, call_pc: usize) {
+        self.call_frames.push(CallFrame { call_pc, entry: self.pc, sp: self.sp, reported: false });
+    }
+
+    // Run after every instruction while checking is enabled
+    pub(crate) fn check_frame(&mut self, pc: usize) {
+        let sp = self.sp;
+        if let Some(frame) = self.call_frames.last_mut() {
+            if sp > frame.sp && !frame.reported {
+                frame.reported = true;
+                let past = if self.return_stack { "the stack it was called with" } else { "its return address" };
+                let (entry, call_pc) = (frame.entry, frame.call_pc);
+                self.report(Some((entry, call_pc)), pc, format!("popped past {} (sp 0x{:x})", past, sp));
+            }
+        }
+    }
+
+    // Called by `return` after the locals are discarded, before the return address is popped
+    pub(crate) fn check_return(&mut self, pc: usize) {
+        let frame = match self.call_frames.pop() {
+            Some(frame) => frame,
+            None => return self.report(None, pc, "return without a matching call".to_string()),
+        };
+        let function = Some((frame.entry, frame.call_pc));
+        if self.sp != frame.sp {
+            let problem = format!("returned with sp 0x{:x}, expected 0x{:x}", self.sp, frame.sp);
+            self.report(function, pc, problem);
+        } else if !self.return_stack && self.read_u32(frame.sp) as usize != frame.call_pc + 4 {
+            self.report(function, pc, "overwrote its return address".to_string());
+        }
+    }
+
+    // function is the entry and call site of the active call, if any
+    fn report(&mut self, function: Option<(usize, usize)>, pc: usize, problem: String) {
+        if self.call_violations.len() < MAX_VIOLATIONS {
+            let name = function.and_then(|(entry, _)| self.sections.symbols.name_at(entry)).map(String::from);
+            let (entry, call_pc) = (function.map(|(entry, _)| entry), function.map(|(_, call_pc)| call_pc));
+            self.call_violations.push(CallViolation { pc, entry, call_pc, name, problem });
+        }
+    }
+
+    // Violations --check-calls found so far, oldest first
+    pub fn call_violations(&self) -> &[CallViolation] {
+        &self.call_violations
+    }
+}
+
+// SP relative to a function's entry, in bytes (above it is positive), and
+// where it was at each enter that hasn't been left yet
+#[derive(Clone, PartialEq)]
+struct Depth {
+    sp: i64,
+    frames: Vec<i64>,
+}
+
+// Calling-convention violations found statically in code words as
+// decode_program returns them, by function and then address
+pub fn lint_calls(words: &[u32], symbols: &Symbols) -> Vec<CallViolation> {
+    let cfg = Cfg::build(words);
+    let mut entries: Vec<usize> = words
+        .iter()
+        .enumerate()
+        .filter(|(_, &word)| isa::decode(word) == Some(Op::Call))
+        .filter_map(|(index, &word)| {
+            let target = index as i64 * 4 + isa::lookup(word)?.operands(word)[0] as i64;
+            usize::try_from(target).ok().filter(|&target| target < words.len() * 4 && target % 4 == 0)
+        })
+        .collect();
+    entries.sort_unstable();
+    entries.dedup();
+    entries.into_iter().flat_map(|entry| lint_function(&cfg, words, symbols, entry)).collect()
+}
+
+fn lint_function(cfg: &Cfg, words: &[u32], symbols: &Symbols, entry: usize) -> Vec<CallViolation> {
+    let mut violations = Vec::new();
+    let mut report = |pc: usize, problem: String| {
+        let name = symbols.name_at(entry).map(String::from);
+        violations.push(CallViolation { pc, entry: Some(entry), call_pc: None, name, problem });
+    };
+    let mut depths: Vec<Option<Depth>> = vec![None; cfg.blocks().len()];
+    let mut unknown = vec![false; cfg.blocks().len()];
+    let mut popped_past = false;
+    let start = cfg.block_at(entry).expect("call targets are in the code");
+    depths[start] = Some(Depth { sp: 0, frames: Vec::new() });
+    let mut pending = vec![start];
+    while let Some(id) = pending.pop() {
+        let mut depth = depths[id].clone().expect("pending blocks have a depth");
+        let block = cfg.block(id);
+        let mut follow = true;
+        for pc in (block.start..block.end).step_by(4) {
+            let word = words[pc / 4];
+            let Some(spec) = isa::lookup(word) else {
+                follow = false;
+                break;
+            };
+            let [operand, _] = spec.operands(word);
+            match spec.op {
+                Op::Return => {
+                    let sp = depth.sp + operand as i64;
+                    if sp != 0 {
+                        report(pc, format!("can return with sp {:+} from its entry", sp));
+                    }
+                    follow = false;
+                }
+                Op::Exit | Op::Stinput | Op::Syscall => follow = false,
+                Op::Enter => {
+                    depth.frames.push(depth.sp - 4);
+                    depth.sp -= 4 + operand as i64 * 4;
+                }
+                Op::Leave => match depth.frames.pop() {
+                    Some(fp) => depth.sp = fp + 4,
+                    None => follow = false, // The caller's frame, wherever that is
+                },
+                op => depth.sp += popped_bytes(op, operand),
+            }
+            if depth.sp > 0 && !popped_past {
+                popped_past = true;
+                report(pc, format!("can pop past its return address (sp {:+} from its entry)", depth.sp));
+            }
+            if !follow {
+                break;
+            }
+        }
+        if !follow {
+            continue;
+        }
+        // The callee's return brings control back to the next instruction
+        for edge in cfg.successors(id).iter().filter(|edge| edge.kind != EdgeKind::Call) {
+            let to = edge.to;
+            match &depths[to] {
+                _ if unknown[to] => {}
+                None => {
+                    depths[to] = Some(depth.clone());
+                    pending.push(to);
+                }
+                Some(known) if *known != depth => unknown[to] = true,
+                Some(_) => {}
+            }
+        }
+    }
+    violations.sort_by_key(|violation| violation.pc);
+    violations
+}
+
+// Bytes an instruction moves SP up by, for those whose effect doesn't
+// depend on anything but its operand (negative when it pushes)
+fn popped_bytes(op: Op, operand: i32) -> i64 {
+    match op {
+        Op::Pop => operand as i64,
+        Op::Input | Op::Fork | Op::Counter | Op::Loadi | Op::Loadbi | Op::Gload | Op::Lload | Op::Dup | Op::Push => -4,
+        Op::Wait | Op::Cpuid => -8,
+        Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Rem | Op::And | Op::Or | Op::Xor | Op::Lsl | Op::Lsr
+            | Op::Asr | Op::Min | Op::Max | Op::Mulh | Op::Mulhu | Op::Qmul | Op::Qdiv => 4,
+        Op::Storei | Op::Storebi | Op::Gstore | Op::Lstore | Op::Free => 4,
+        Op::Store | Op::Storeb | Op::Memcmp | Op::Cmp | Op::Add64 | Op::Sub64 | Op::Mul64 => 8,
+        Op::B64enc | Op::B64dec | Op::Hexenc | Op::Hexdec => 8,
+        Op::Memcpy | Op::Memset | Op::Cmp64 => 12,
+        Op::Vadd | Op::Vsub | Op::Vmul => 16,
+        _ => 0, // Unary arithmetic, load, alloc, branches, printing and the like
+    }
+}
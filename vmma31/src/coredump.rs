@@ -0,0 +1,157 @@
+// Core dumps: the state of a program that faulted, written with --core-dump
+// for post-mortem inspection. The file is little-endian binary:
+//
+//   "CORE"                magic
+//   u32                   format version, CORE_VERSION
+//   u32 pc, u32 sp        registers when the fault was raised
+//   u32 fp, u32 flags     the frame pointer and cmp's flags, from version 2 on
+//   u32                   code size in bytes
+//   u64                   instructions executed
+//   u8 kind, u32 pc, u32 a, u32 b   the fault; kind is 1 invalid_instruction
+//                         (a = word), 2 stack_underflow, 3 stack_overflow,
+//                         4 divide_by_zero, 5 out_of_bounds (a = sp, b = addr),
+//                         6 out_of_memory (a = size), 7 overflow,
+//                         8 shadow_stack_mismatch (a = popped, b = expected)
+//                         and 9 the same with no matching call (a = popped)
+//   u32 length, bytes     all of memory
+//   u32 length, bytes     the program file's sections: MAGIC, then any symbol
+//                         and debug sections, without the code
+//
+// Unused fault fields are 0. The code is the start of memory, so the program
+// can be disassembled from the dump alone. Version 1 dumps still load, with
+// fp at the top of memory as it is before any enter and the flags clear.
+
+use std::fs;
+
+use crate::program::{join_program, split_program, Reader, Sections};
+use crate::{VmError, VM};
+
+pub const CORE_MAGIC: [u8; 4] = *b"CORE";
+pub const CORE_VERSION: u32 = 2;
+const FP_VERSION: u32 = 2; // The first version with fp and the flags
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreDump {
+    pub pc: usize,
+    pub sp: usize,
+    pub fp: usize,
+    pub flags: u32,
+    pub code_size: usize,
+    pub instructions_executed: u64,
+    pub fault: VmError,
+    pub memory: Vec<u8>,
+    pub sections: Sections,
+}
+
+impl VM {
+    // A VM in the state a core dump recorded, faulted as it was; it can be
+    // examined but not run (stepping returns the fault again)
+    pub fn from_core(core: &CoreDump) -> VM {
+        let mut vm = VM::new(core.memory.len());
+        vm.memory.copy_from_slice(&core.memory);
+        vm.pc = core.pc;
+        vm.sp = core.sp;
+        vm.fp = core.fp;
+        vm.flags = core.flags;
+        vm.code_size = core.code_size;
+        vm.predecode();
+        vm.instructions_executed = core.instructions_executed;
+        vm.fault = Some(core.fault.clone());
+        vm.sections = core.sections.clone();
+        vm
+    }
+
+    // The VM's state as a core dump, if it has faulted
+    pub fn core_dump(&self) -> Option<CoreDump> {
+        Some(CoreDump {
+            pc: self.pc,
+            sp: self.sp,
+            fp: self.fp,
+            flags: self.flags,
+            code_size: self.code_size,
+            instructions_executed: self.instructions_executed,
+            fault: self.fault.clone()?,
+            memory: self.memory.clone(),
+            sections: self.sections.clone(),
+        })
+    }
+}
+
+impl CoreDump {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (kind, pc, a, b) = match self.fault {
+            VmError::InvalidInstruction { pc, word } => (1, pc, word as usize, 0),
+            VmError::StackUnderflow { pc } => (2, pc, 0, 0),
+            VmError::StackOverflow { pc } => (3, pc, 0, 0),
+            VmError::DivideByZero { pc } => (4, pc, 0, 0),
+            VmError::OutOfBounds { pc, sp, addr } => (5, pc, sp, addr),
+            VmError::OutOfMemory { pc, size } => (6, pc, size, 0),
+            VmError::Overflow { pc } => (7, pc, 0, 0),
+            VmError::ShadowStackMismatch { pc, popped, expected: Some(expected) } => {
+                (8, pc, popped as usize, expected as usize)
+            }
+            VmError::ShadowStackMismatch { pc, popped, expected: None } => (9, pc, popped as usize, 0),
+        };
+        let mut bytes = CORE_MAGIC.to_vec();
+        for value in [CORE_VERSION as usize, self.pc, self.sp, self.fp, self.flags as usize, self.code_size] {
+            bytes.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.instructions_executed.to_le_bytes());
+        bytes.push(kind);
+        for value in [pc, a, b] {
+            bytes.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        for blob in [&self.memory, &join_program(&self.sections, &[])] {
+            bytes.extend_from_slice(&(blob.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(blob);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<CoreDump, String> {
+        let mut reader = Reader { bytes, what: "core dump" };
+        if reader.take(4)? != CORE_MAGIC {
+            return Err("Not a core dump".to_string());
+        }
+        let version = reader.u32()?;
+        if version == 0 || version > CORE_VERSION {
+            return Err(format!("Unsupported core dump version {}", version));
+        }
+        let (pc, sp) = (reader.u32()? as usize, reader.u32()? as usize);
+        let (fp, flags) = if version >= FP_VERSION { (Some(reader.u32()? as usize), reader.u32()?) } else { (None, 0) };
+        let code_size = reader.u32()? as usize;
+        let instructions_executed = reader.u64()?;
+        let kind = reader.u8()?;
+        let (fault_pc, a, b) = (reader.u32()? as usize, reader.u32()?, reader.u32()? as usize);
+        let fault = match kind {
+            1 => VmError::InvalidInstruction { pc: fault_pc, word: a },
+            2 => VmError::StackUnderflow { pc: fault_pc },
+            3 => VmError::StackOverflow { pc: fault_pc },
+            4 => VmError::DivideByZero { pc: fault_pc },
+            5 => VmError::OutOfBounds { pc: fault_pc, sp: a as usize, addr: b },
+            6 => VmError::OutOfMemory { pc: fault_pc, size: a as usize },
+            7 => VmError::Overflow { pc: fault_pc },
+            8 => VmError::ShadowStackMismatch { pc: fault_pc, popped: a, expected: Some(b as u32) },
+            9 => VmError::ShadowStackMismatch { pc: fault_pc, popped: a, expected: None },
+            _ => return Err(format!("Invalid core dump: unknown fault kind {}", kind)),
+        };
+        let len = reader.u32()? as usize;
+        let memory = reader.take(len)?.to_vec();
+        let len = reader.u32()? as usize;
+        let (sections, _) = split_program(reader.take(len)?)?;
+        if code_size > memory.len() || sp > memory.len() {
+            return Err("Invalid core dump: registers outside memory".to_string());
+        }
+        let fp = fp.unwrap_or(memory.len());
+        Ok(CoreDump { pc, sp, fp, flags, code_size, instructions_executed, fault, memory, sections })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_bytes()).map_err(|e| format!("Failed to write core dump: {}", e))
+    }
+
+    pub fn load(path: &str) -> Result<CoreDump, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Failed to read core dump: {}", e))?;
+        CoreDump::from_bytes(&bytes).map_err(|e| format!("{}: {}", path, e))
+    }
+}
@@ -0,0 +1,23 @@
+// Performance counters a guest can read with `counter [id]`, so programs can
+// time their own algorithms without host-side profiling. Counters are
+// deterministic: the same program and input always give the same values.
+
+use crate::VM;
+
+pub const COUNTER_INSTRUCTIONS: u32 = 0; // Instructions retired, including this one
+pub const COUNTER_BRANCHES: u32 = 1; // Taken branches, jumps, calls and returns
+pub const COUNTER_CYCLES: u32 = 2; // Virtual cycles: one per instruction plus one per taken branch
+
+impl VM {
+    // Pushes the low 32 bits of the counter; unknown ids push 0. Counters
+    // cover the whole run, across every guest process.
+    pub(crate) fn exec_counter(&mut self, id: u32) {
+        let value = match id {
+            COUNTER_INSTRUCTIONS => self.instructions_executed,
+            COUNTER_BRANCHES => self.branches_taken,
+            COUNTER_CYCLES => self.instructions_executed + self.branches_taken,
+            _ => 0,
+        };
+        self.push(value as u32);
+    }
+}
@@ -0,0 +1,333 @@
+// Debug Adapter Protocol server behind `vmma31 dap`, so editors such as VS
+// Code can launch and debug programs. Protocol messages travel over stdin
+// and stdout, so guest output is sent as output events and guest input comes
+// from the launch configuration:
+//
+//   { "program": "prog.s", "input": ["5", "7"], "stopOnEntry": true }
+//
+// "program" is either bytecode or assembly source, which is assembled on
+// launch. Source breakpoints and source positions in the call stack need
+// line information, which source has and bytecode only has when assembled
+// with --debug-info; other bytecode can still be stepped instruction by
+// instruction and its stack inspected. Step over and step out
+// measure call depth with the shadow stack.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::{asm, MAGIC, VM};
+
+const THREAD_ID: u64 = 1; // The VM runs one guest process at a time, shown as one thread
+const STACK_REF: u64 = 1; // variablesReference of the stack scope
+const REGISTERS_REF: u64 = 2;
+const POLL_INTERVAL: usize = 1024; // Instructions between checks for a pause request
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Resume {
+    Continue,
+    StepIn,
+    StepOver,
+    StepOut,
+}
+
+struct Session {
+    requests: Receiver<Value>,
+    pending: VecDeque<Value>, // Requests that arrived while the program was running
+    seq: u64,
+    vm: Option<VM>,
+    source: Option<String>,       // Path of the assembly source, when launched from one
+    lines: Vec<usize>,            // Source line of each code word
+    breakpoints: BTreeSet<usize>, // Addresses
+    stop_on_entry: bool,
+}
+
+// Serve one debug session on stdin and stdout, until the client disconnects
+pub fn run() {
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        while let Ok(Some(message)) = read_message(&mut stdin) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut session = Session {
+        requests,
+        pending: VecDeque::new(),
+        seq: 1,
+        vm: None,
+        source: None,
+        lines: Vec::new(),
+        breakpoints: BTreeSet::new(),
+        stop_on_entry: false,
+    };
+    while let Some(request) = session.next_request() {
+        if !session.handle(&request) {
+            break;
+        }
+    }
+}
+
+// Read one `Content-Length`-framed message; None at end of input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+impl Session {
+    fn next_request(&mut self) -> Option<Value> {
+        self.pending.pop_front().or_else(|| self.requests.recv().ok())
+    }
+
+    fn send(&mut self, mut message: Value) {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let body = message.to_string();
+        let mut stdout = io::stdout().lock();
+        let _ = write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = stdout.flush();
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    // Handle one request; returns false once the session is over
+    fn handle(&mut self, request: &Value) -> bool {
+        let args = &request["arguments"];
+        let result = match request["command"].as_str().unwrap_or("") {
+            "initialize" => Ok(json!({ "supportsConfigurationDoneRequest": true })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "configurationDone" => match self.vm {
+                Some(_) => Ok(Value::Null),
+                None => Err("no program launched".to_string()),
+            },
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => self.vm().map(|vm| self.stack_trace(vm)),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Stack", "variablesReference": STACK_REF, "expensive": false },
+                { "name": "Registers", "variablesReference": REGISTERS_REF, "expensive": false },
+            ] })),
+            "variables" => self.vm().map(|vm| variables(vm, args["variablesReference"].as_u64())),
+            "continue" => Ok(json!({ "allThreadsContinued": true })),
+            "next" | "stepIn" | "stepOut" | "pause" => Ok(Value::Null),
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(Value::Null));
+                return false;
+            }
+            command => Err(format!("unsupported request {:?}", command)),
+        };
+        let ok = result.is_ok();
+        self.respond(request, result);
+
+        // Events that follow the response
+        match request["command"].as_str().unwrap_or("") {
+            "launch" if ok => self.event("initialized", json!({})),
+            "configurationDone" if ok && self.stop_on_entry => self.stopped("entry", None),
+            "configurationDone" if ok => self.resume(Resume::Continue),
+            "continue" => self.resume(Resume::Continue),
+            "next" => self.resume(Resume::StepOver),
+            "stepIn" => self.resume(Resume::StepIn),
+            "stepOut" => self.resume(Resume::StepOut),
+            "pause" => self.stopped("pause", None),
+            _ => {}
+        }
+        true
+    }
+
+    fn vm(&self) -> Result<&VM, String> {
+        self.vm.as_ref().ok_or_else(|| "no program launched".to_string())
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["program"].as_str().ok_or("launch needs a \"program\"")?;
+        let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let bytes = if bytes.starts_with(&MAGIC) {
+            bytes
+        } else {
+            let text = String::from_utf8(bytes).map_err(|_| format!("{} is neither bytecode nor source", path))?;
+            let (bytecode, lines) = asm::assemble_with_lines(&text, path)?;
+            self.source = Some(path.to_string());
+            self.lines = lines;
+            bytecode
+        };
+        let mut vm = VM::from_bytes(&bytes)?;
+        if let Some((_, file, _)) = vm.source_lines().iter().next().filter(|_| self.source.is_none()) {
+            let file = file.to_string();
+            let line_of = |addr| vm.source_lines().at(addr).filter(|(f, _)| *f == file).map_or(0, |(_, line)| line as usize);
+            self.lines = (0..vm.code().len()).step_by(4).map(line_of).collect();
+            self.source = Some(file);
+        }
+        vm.capture_output();
+        let input = args["input"].as_array().map(Vec::as_slice).unwrap_or_default();
+        vm.set_input(input.iter().map(|line| line.as_str().unwrap_or_default().to_string()));
+        self.vm = Some(vm);
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(Value::Null)
+    }
+
+    // Replace the breakpoints, placing each on the first instruction at or
+    // after its line
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        self.breakpoints.clear();
+        let requested = args["breakpoints"].as_array().map(Vec::as_slice).unwrap_or_default();
+        let mut placed = Vec::new();
+        for breakpoint in requested {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+            match self.lines.iter().position(|&l| l >= line) {
+                Some(index) if self.source.is_some() => {
+                    self.breakpoints.insert(index * 4);
+                    placed.push(json!({ "verified": true, "line": self.lines[index] }));
+                }
+                _ => placed.push(json!({ "verified": false, "line": line, "message": "no code at or after this line" })),
+            }
+        }
+        json!({ "breakpoints": placed })
+    }
+
+    fn line_at(&self, addr: usize) -> Option<usize> {
+        self.lines.get(addr / 4).copied()
+    }
+
+    // The current instruction, then each active call site, innermost first
+    fn stack_trace(&self, vm: &VM) -> Value {
+        let calls = vm.shadow_stack.iter().rev().map(|&return_addr| (return_addr as usize).saturating_sub(4));
+        let frames: Vec<Value> = std::iter::once(vm.pc)
+            .chain(calls)
+            .enumerate()
+            .map(|(id, addr)| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": format!("0x{:04x}", addr),
+                    "line": self.line_at(addr).unwrap_or(0),
+                    "column": 1,
+                    "instructionPointerReference": format!("0x{:x}", addr),
+                });
+                if let Some(path) = &self.source {
+                    frame["source"] = json!({ "path": path });
+                }
+                frame
+            })
+            .collect();
+        json!({ "totalFrames": frames.len(), "stackFrames": frames })
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) {
+        self.event("stopped", json!({ "reason": reason, "text": text, "threadId": THREAD_ID, "allThreadsStopped": true }));
+    }
+
+    // Run until a breakpoint, the end of the step, an exit or a fault, or
+    // until a pause or disconnect request arrives
+    fn resume(&mut self, mode: Resume) {
+        let Some(mut vm) = self.vm.take() else {
+            return;
+        };
+        let depth = vm.shadow_stack.len();
+        let line = self.line_at(vm.pc);
+        let mut steps = 0;
+        loop {
+            let result = vm.step();
+            steps += 1;
+            self.forward_output(&mut vm);
+            if let Err(error) = result {
+                self.stopped("exception", Some(error.to_string()));
+                break;
+            }
+            if vm.finished() {
+                self.event("exited", json!({ "exitCode": vm.exit_code }));
+                self.event("terminated", json!({}));
+                break;
+            }
+            let new_line = self.source.is_none() || self.line_at(vm.pc) != line;
+            let reason = match mode {
+                _ if self.breakpoints.contains(&vm.pc) => Some("breakpoint"),
+                Resume::StepIn => new_line.then_some("step"),
+                Resume::StepOver => (new_line && vm.shadow_stack.len() <= depth).then_some("step"),
+                Resume::StepOut => (vm.shadow_stack.len() < depth).then_some("step"),
+                Resume::Continue => None,
+            };
+            if let Some(reason) = reason {
+                self.stopped(reason, None);
+                break;
+            }
+            if steps % POLL_INTERVAL == 0 && self.interrupted() {
+                break; // handle reports the pause once it gets to the request
+            }
+        }
+        self.vm = Some(vm);
+    }
+
+    // Queue requests that arrived while running; true if one should stop it
+    fn interrupted(&mut self) -> bool {
+        while let Ok(request) = self.requests.try_recv() {
+            self.pending.push_back(request);
+        }
+        self.pending
+            .iter()
+            .any(|request| matches!(request["command"].as_str(), Some("pause" | "disconnect" | "terminate")))
+    }
+
+    fn forward_output(&mut self, vm: &mut VM) {
+        if let Some(text) = vm.captured_output.as_mut().filter(|text| !text.is_empty()) {
+            let output = mem::take(text);
+            self.event("output", json!({ "category": "stdout", "output": output }));
+        }
+    }
+}
+
+fn variables(vm: &VM, reference: Option<u64>) -> Value {
+    let show = |value: u32| format!("{} (0x{:08x})", value as i32, value);
+    let variables: Vec<Value> = match reference {
+        Some(STACK_REF) => vm
+            .stack()
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| json!({ "name": format!("sp+{}", index * 4), "value": show(value), "variablesReference": 0 }))
+            .collect(),
+        Some(REGISTERS_REF) => vec![
+            json!({ "name": "pc", "value": format!("0x{:04x}", vm.pc), "variablesReference": 0 }),
+            json!({ "name": "sp", "value": format!("0x{:04x}", vm.sp), "variablesReference": 0 }),
+        ],
+        _ => Vec::new(),
+    };
+    json!({ "variables": variables })
+}
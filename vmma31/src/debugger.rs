@@ -0,0 +1,218 @@
+// Interactive debugger behind `vmma31 debug <file>`. Commands are read from
+// stdin, one per line; the guest's own input and stinput read from stdin too,
+// so answer its prompts between commands as usual.
+//
+//   break <addr>     stop before the instruction at addr (no address lists them)
+//   delete <addr>    remove a breakpoint
+//   step [n]         execute n instructions (default 1)
+//   continue         run until a breakpoint, exit or fault
+//   stack            show the stack, top first
+//   mem <addr> [n]   show n words of memory from addr (default 4)
+//   list [n]         disassemble n instructions from the PC (default 5)
+//   regs             show PC, SP, FP and the flags
+//   backtrace        show the chain of calls that led to the PC
+//   quit
+//
+// Commands can be shortened to b, d, s, c, st, m, l, r, bt and q, and an empty
+// line repeats the last one. Addresses are decimal or 0x hex, or a name from
+// the program's symbol section, which list also shows in place of targets.
+// For programs assembled with --debug-info, the source line of the next
+// instruction is shown before it, read from the source file if it's there.
+//
+// `vmma31 core <file>` opens a core dump the same way, after printing the
+// fault and backtrace. Nothing can run, but the stack, memory and code can be
+// examined as they were; list starts two instructions before the PC there,
+// to show how the program reached the fault.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use crate::flags;
+use crate::isa::{self, parse_number};
+use crate::{Symbols, VM};
+
+const HELP: &str =
+    "commands: break [addr], delete <addr>, step [n], continue, stack, mem <addr> [n], list [n], regs, backtrace, quit";
+
+// Run an interactive session on a loaded VM until the user quits or stdin closes
+pub fn run(vm: &mut VM) {
+    session(vm, false);
+}
+
+// Inspect a VM restored from a core dump (see VM::from_core), without running it
+pub fn inspect_core(vm: &mut VM) {
+    if let Some(fault) = &vm.fault {
+        println!("Error: {}", fault);
+    }
+    print!("{}", vm.format_backtrace());
+    session(vm, true);
+}
+
+fn session(vm: &mut VM, post_mortem: bool) {
+    let mut breakpoints = BTreeSet::new();
+    let mut last = String::new();
+    println!("{}", HELP);
+    show_next(vm);
+    loop {
+        vm.flush_output();
+        print!("(vmma31) ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line).unwrap_or(0) == 0 {
+            println!();
+            return;
+        }
+        let line = match line.trim() {
+            "" => last.clone(),
+            text => text.to_string(),
+        };
+        last = line.clone();
+
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+        let result = match command {
+            "b" | "break" => set_breakpoint(&mut breakpoints, &args, vm.symbols()),
+            "d" | "delete" => delete_breakpoint(&mut breakpoints, &args, vm.symbols()),
+            "s" | "step" | "c" | "continue" if post_mortem => Err("a core dump can't be run".to_string()),
+            "s" | "step" => count(&args, 1).map(|n| run_to(vm, &breakpoints, Some(n))),
+            "c" | "continue" => {
+                run_to(vm, &breakpoints, None);
+                Ok(())
+            }
+            "st" | "stack" => {
+                show_stack(vm);
+                Ok(())
+            }
+            "m" | "mem" => show_memory(vm, &args),
+            "l" | "list" => count(&args, 5).map(|n| list(vm, n, if post_mortem { 2 } else { 0 })),
+            "r" | "regs" => {
+                println!("pc 0x{:04x}  sp 0x{:04x}  fp 0x{:04x}  flags {}", vm.pc, vm.sp, vm.fp, flags::describe(vm.flags));
+                Ok(())
+            }
+            "bt" | "backtrace" => {
+                print!("{}", vm.format_backtrace());
+                Ok(())
+            }
+            "q" | "quit" => return,
+            "" => Ok(()),
+            _ => Err(HELP.to_string()),
+        };
+        if let Err(e) = result {
+            println!("{}", e);
+        }
+    }
+}
+
+// Execute `steps` instructions, or until a breakpoint when None. Stops early
+// when the program finishes or faults.
+fn run_to(vm: &mut VM, breakpoints: &BTreeSet<usize>, steps: Option<usize>) {
+    let mut taken = 0;
+    loop {
+        let result = vm.step();
+        vm.flush_output();
+        if let Err(error) = result {
+            println!("Error: {}", error);
+            return;
+        }
+        taken += 1;
+        if vm.finished() {
+            println!("program exited with code {}", vm.exit_code);
+            return;
+        }
+        if steps == Some(taken) {
+            break;
+        }
+        if steps.is_none() && breakpoints.contains(&vm.pc) {
+            println!("breakpoint at 0x{:04x}", vm.pc);
+            break;
+        }
+    }
+    show_next(vm);
+}
+
+fn set_breakpoint(breakpoints: &mut BTreeSet<usize>, args: &[&str], symbols: &Symbols) -> Result<(), String> {
+    match args {
+        [] if breakpoints.is_empty() => println!("no breakpoints"),
+        [] => breakpoints.iter().for_each(|addr| println!("0x{:04x}", addr)),
+        [addr] => {
+            breakpoints.insert(address(addr, symbols)?);
+        }
+        _ => return Err("usage: break [addr]".to_string()),
+    }
+    Ok(())
+}
+
+fn delete_breakpoint(breakpoints: &mut BTreeSet<usize>, args: &[&str], symbols: &Symbols) -> Result<(), String> {
+    let [addr] = args else {
+        return Err("usage: delete <addr>".to_string());
+    };
+    let addr = address(addr, symbols)?;
+    if !breakpoints.remove(&addr) {
+        return Err(format!("no breakpoint at 0x{:04x}", addr));
+    }
+    Ok(())
+}
+
+fn show_stack(vm: &VM) {
+    let stack = vm.stack();
+    if stack.is_empty() {
+        println!("stack empty");
+    }
+    for (index, value) in stack.iter().enumerate() {
+        println!("{:04x}: {:08x}  {}", index * 4, value, *value as i32);
+    }
+}
+
+fn show_memory(vm: &VM, args: &[&str]) -> Result<(), String> {
+    let (addr, words) = match args {
+        [addr] => (address(addr, vm.symbols())?, 4),
+        [addr, n] => (address(addr, vm.symbols())?, count(&[n], 4)?),
+        _ => return Err("usage: mem <addr> [words]".to_string()),
+    };
+    for addr in (addr..).step_by(4).take(words).take_while(|&a| a + 4 <= vm.memory.len()) {
+        println!("{:04x}: {:08x}", addr, vm.read_u32(addr));
+    }
+    Ok(())
+}
+
+// Starting `before` instructions ahead of the PC
+fn list(vm: &VM, instructions: usize, before: usize) {
+    let start = vm.pc.saturating_sub(before * 4);
+    for addr in (start..vm.code_size).step_by(4).take(instructions) {
+        let word = vm.read_u32(addr);
+        let marker = if addr == vm.pc { "=>" } else { "  " };
+        println!("{} {:04x}: {:08x}  {}", marker, addr, word, isa::disassemble_named(word, addr, vm.symbols()));
+    }
+}
+
+// The instruction about to run
+fn show_next(vm: &VM) {
+    if vm.finished() {
+        println!("program has finished");
+    } else {
+        if let Some((file, line)) = vm.source_lines().at(vm.pc) {
+            let source = fs::read_to_string(file).unwrap_or_default();
+            let text = source.lines().nth((line as usize).saturating_sub(1)).unwrap_or("");
+            println!("{}:{}  {}", file, line, text.trim());
+        }
+        let word = vm.read_u32(vm.pc);
+        println!("=> {:04x}: {:08x}  {}", vm.pc, word, isa::disassemble_named(word, vm.pc, vm.symbols()));
+    }
+}
+
+fn address(text: &str, symbols: &Symbols) -> Result<usize, String> {
+    parse_number(text)
+        .and_then(|n| usize::try_from(n).ok())
+        .or_else(|| symbols.address_of(text))
+        .ok_or_else(|| format!("invalid address {:?}", text))
+}
+
+fn count(args: &[&str], default: usize) -> Result<usize, String> {
+    match args {
+        [] => Ok(default),
+        [n] => n.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("invalid count {:?}", n)),
+        _ => Err("too many arguments".to_string()),
+    }
+}
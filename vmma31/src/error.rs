@@ -0,0 +1,83 @@
+// Faults that stop a program. An instruction that hits one records it on the
+// VM and step() hands it back, so run() and the other run methods can tell a
+// clean exit from a crash. A faulted VM stays faulted: stepping it again
+// returns the same error.
+
+use std::error::Error;
+use std::fmt;
+
+// Exit status the CLI uses for a program that faulted
+pub const FAULT_EXIT_CODE: i32 = 134;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub enum VmError {
+    InvalidInstruction { pc: usize, word: u32 }, // Word matches no instruction in the table
+    StackUnderflow { pc: usize },                // Popped with the stack empty
+    StackOverflow { pc: usize },                 // Pushed with no room left below SP
+    DivideByZero { pc: usize },                  // div or rem with a zero divisor
+    OutOfBounds { pc: usize, sp: usize, addr: usize }, // Memory access outside RAM, in strict mode
+    OutOfMemory { pc: usize, size: usize },      // alloc with no room left between the heap and the stack
+    Overflow { pc: usize },                      // add, sub, mul, qmul or qdiv overflowed, with the overflow trap on
+    ShadowStackMismatch { pc: usize, popped: u32, expected: Option<u32> }, // return to an address no call left; None with no call
+}
+
+impl VmError {
+    // Short name used by conformance cases and the manifest
+    pub fn kind(&self) -> &'static str {
+        match self {
+            VmError::InvalidInstruction { .. } => "invalid_instruction",
+            VmError::StackUnderflow { .. } => "stack_underflow",
+            VmError::StackOverflow { .. } => "stack_overflow",
+            VmError::DivideByZero { .. } => "divide_by_zero",
+            VmError::OutOfBounds { .. } => "out_of_bounds",
+            VmError::OutOfMemory { .. } => "out_of_memory",
+            VmError::Overflow { .. } => "overflow",
+            VmError::ShadowStackMismatch { .. } => "shadow_stack_mismatch",
+        }
+    }
+
+    // Address of the instruction that faulted
+    pub fn pc(&self) -> usize {
+        match *self {
+            VmError::InvalidInstruction { pc, .. }
+            | VmError::StackUnderflow { pc }
+            | VmError::StackOverflow { pc }
+            | VmError::DivideByZero { pc }
+            | VmError::OutOfBounds { pc, .. }
+            | VmError::OutOfMemory { pc, .. }
+            | VmError::Overflow { pc }
+            | VmError::ShadowStackMismatch { pc, .. } => pc,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::InvalidInstruction { word, .. } => write!(f, "invalid instruction 0x{:08x}", word)?,
+            VmError::StackUnderflow { .. } => write!(f, "stack underflow")?,
+            VmError::StackOverflow { .. } => write!(f, "stack overflow")?,
+            VmError::DivideByZero { .. } => write!(f, "divide by zero")?,
+            VmError::OutOfBounds { sp, addr, .. } => {
+                write!(f, "out-of-bounds access to 0x{:x} (sp 0x{:x})", addr, sp)?
+            }
+            VmError::OutOfMemory { size, .. } => write!(f, "out of memory allocating {} bytes", size)?,
+            VmError::Overflow { .. } => write!(f, "arithmetic overflow")?,
+            VmError::ShadowStackMismatch { popped, expected: Some(expected), .. } => {
+                write!(f, "shadow stack mismatch (popped 0x{:x}, expected 0x{:x})", popped, expected)?
+            }
+            VmError::ShadowStackMismatch { .. } => write!(f, "shadow stack mismatch (no matching call)")?,
+        }
+        write!(f, " at pc 0x{:x}", self.pc())
+    }
+}
+
+impl Error for VmError {}
+
+impl crate::VM {
+    // Record a fault; the first one wins and ends the run after this instruction
+    pub(crate) fn raise(&mut self, error: VmError) {
+        self.fault.get_or_insert(error);
+    }
+}
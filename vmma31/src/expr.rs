@@ -0,0 +1,205 @@
+// Assembler operand expressions: numbers and names combined with
+// parentheses and, from tightest to loosest binding,
+//
+//   - ~            negation and bitwise not (unary)
+//   * / %          multiply, divide, remainder
+//   + -            add, subtract
+//   << >>          shifts (>> is arithmetic)
+//   &  ^  |        bitwise and, xor, or
+//
+// as in C. Arithmetic is on 64-bit signed values; overflow and division by
+// zero are errors rather than wrapping.
+
+use crate::isa;
+
+// The value of text, with lookup giving the value of each name in it
+pub(crate) fn evaluate(text: &str, lookup: &mut dyn FnMut(&str) -> Result<i64, String>) -> Result<i64, String> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser { tokens: &tokens, next: 0, lookup };
+    let value = parser.binary(0)?;
+    match parser.tokens.get(parser.next) {
+        None => Ok(value),
+        Some(token) => Err(format!("unexpected {} in {:?}", describe(token), text)),
+    }
+}
+
+// Operands separated by commas or whitespace. Whitespace next to a binary
+// operator doesn't separate (`end - start` is one operand), but a sign
+// written against a number after a space starts a new one (`swap 4 -8`).
+// Quoted strings are kept whole.
+pub(crate) fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    for part in split_outside_quotes(text, |c| c == ',') {
+        let mut group: Option<(usize, usize)> = None; // Span in part
+        for (start, end) in spans(part) {
+            let word = &part[start..end];
+            let joins = match group {
+                None => false,
+                Some((group_start, group_end)) => {
+                    let before = &part[group_start..group_end];
+                    before.ends_with(['+', '-', '*', '/', '%', '&', '|', '^', '<', '>', '~', '('])
+                        || word.starts_with(['*', '/', '%', '&', '|', '^', '<', '>', ')'])
+                        || word == "+"
+                        || word == "-"
+                }
+            };
+            group = match group {
+                Some((group_start, _)) if joins => Some((group_start, end)),
+                _ => {
+                    operands.extend(group.map(|(start, end)| &part[start..end]));
+                    Some((start, end))
+                }
+            };
+        }
+        operands.extend(group.map(|(start, end)| &part[start..end]));
+    }
+    operands
+}
+
+// Pieces of text between separators that aren't inside quotes
+fn split_outside_quotes(text: &str, separator: impl Fn(char) -> bool) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if !quoted && separator(c) => {
+                pieces.push(&text[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    pieces.push(&text[start..]);
+    pieces
+}
+
+// Start and end of each whitespace-separated word of text
+fn spans(text: &str) -> Vec<(usize, usize)> {
+    let words = split_outside_quotes(text, char::is_whitespace).into_iter().filter(|word| !word.is_empty());
+    words
+        .map(|word| {
+            let start = word.as_ptr() as usize - text.as_ptr() as usize;
+            (start, start + word.len())
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    Number(i64),
+    Name(&'a str),
+    Operator(&'static str),
+}
+
+const OPERATORS: [&str; 13] = ["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")"];
+
+fn tokenize(text: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(operator) = OPERATORS.iter().find(|operator| rest.starts_with(**operator)) {
+            tokens.push(Token::Operator(operator));
+            rest = &rest[operator.len()..];
+        } else {
+            let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+            let word = &rest[..len];
+            if word.starts_with(|c: char| c.is_ascii_digit()) {
+                tokens.push(Token::Number(isa::parse_number(word).ok_or_else(|| format!("invalid number {:?}", word))?));
+            } else if !word.is_empty() {
+                tokens.push(Token::Name(word));
+            } else {
+                return Err(format!("unexpected {:?} in {:?}", &rest[..rest.chars().next().map_or(0, char::len_utf8)], text));
+            }
+            rest = &rest[len..];
+        }
+        rest = rest.trim_start();
+    }
+    if tokens.is_empty() {
+        return Err("missing value".to_string());
+    }
+    Ok(tokens)
+}
+
+struct Parser<'t, 'a, 'l> {
+    tokens: &'t [Token<'a>],
+    next: usize,
+    lookup: &'l mut dyn FnMut(&str) -> Result<i64, String>,
+}
+
+// Binary operators by precedence level, loosest first
+const LEVELS: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+impl Parser<'_, '_, '_> {
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        while let Some(&Token::Operator(operator)) = self.tokens.get(self.next) {
+            if !LEVELS[level].contains(&operator) {
+                break;
+            }
+            self.next += 1;
+            let right = self.binary(level + 1)?;
+            value = apply(operator, value, right)?;
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        match token {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Name(name)) => (self.lookup)(name),
+            Some(Token::Operator("-")) => self.unary()?.checked_neg().ok_or_else(overflow),
+            Some(Token::Operator("+")) => self.unary(),
+            Some(Token::Operator("~")) => Ok(!self.unary()?),
+            Some(Token::Operator("(")) => {
+                let value = self.binary(0)?;
+                match self.tokens.get(self.next) {
+                    Some(Token::Operator(")")) => {
+                        self.next += 1;
+                        Ok(value)
+                    }
+                    _ => Err("missing )".to_string()),
+                }
+            }
+            Some(token) => Err(format!("unexpected {}", describe(&token))),
+            None => Err("expression ends too soon".to_string()),
+        }
+    }
+}
+
+fn apply(operator: &str, left: i64, right: i64) -> Result<i64, String> {
+    let value = match operator {
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" | "%" if right == 0 => return Err("division by zero".to_string()),
+        "/" => left.checked_div(right),
+        "%" => left.checked_rem(right),
+        "<<" | ">>" if !(0..64).contains(&right) => return Err(format!("shift by {} is out of range (0 to 63)", right)),
+        "<<" => left.checked_mul(1 << right),
+        ">>" => Some(left >> right),
+        "&" => Some(left & right),
+        "^" => Some(left ^ right),
+        _ => Some(left | right),
+    };
+    value.ok_or_else(overflow)
+}
+
+fn overflow() -> String {
+    "value overflows 64 bits".to_string()
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(value) => value.to_string(),
+        Token::Name(name) => name.to_string(),
+        Token::Operator(operator) => format!("{:?}", operator),
+    }
+}
@@ -0,0 +1,202 @@
+// C ABI for embedding the VM in C and C++ programs, such as test harnesses.
+// A VM is an opaque pointer from vmma31_vm_new, released with vmma31_free.
+// Guest input and output use the process's stdin and stdout, as in the CLI,
+// unless vmma31_set_input and vmma31_capture_output say otherwise.
+// include/vmma31.h declares these functions; regenerate it with
+//
+//   cbindgen --config cbindgen.toml --output include/vmma31.h
+//
+// after changing anything here.
+
+use std::slice;
+
+use crate::{StepStop, FAULT_EXIT_CODE, MAX_RAM_SIZE, VM};
+
+/// Create a VM with ram_size bytes of memory, or return NULL if ram_size is
+/// zero, not a multiple of 4 or larger than the VM allows.
+#[no_mangle]
+pub extern "C" fn vmma31_vm_new(ram_size: usize) -> *mut VM {
+    if ram_size == 0 || !ram_size.is_multiple_of(4) || ram_size > MAX_RAM_SIZE {
+        return std::ptr::null_mut();
+    }
+    Box::into_raw(Box::new(VM::new(ram_size)))
+}
+
+/// Load a program file's contents (magic bytes first). Returns 0 on success
+/// and -1 if the bytes aren't a valid program or don't fit in memory.
+///
+/// # Safety
+///
+/// vm must come from vmma31_vm_new, and bytes must point to len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn vmma31_load(vm: *mut VM, bytes: *const u8, len: usize) -> i32 {
+    let (Some(vm), false) = (vm.as_mut(), bytes.is_null()) else {
+        return -1;
+    };
+    match vm.load_bytes(slice::from_raw_parts(bytes, len)) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Run the loaded program to completion. Returns its exit code, or 134 if it
+/// faulted, as the CLI does.
+///
+/// # Safety
+///
+/// vm must come from vmma31_vm_new.
+#[no_mangle]
+pub unsafe extern "C" fn vmma31_run(vm: *mut VM) -> i32 {
+    let Some(vm) = vm.as_mut() else {
+        return FAULT_EXIT_CODE;
+    };
+    let result = vm.run();
+    vm.flush_output();
+    result.unwrap_or(FAULT_EXIT_CODE)
+}
+
+/// Execute one instruction. Returns 1 while the program is still running, 0
+/// once it has finished (see vmma31_exit_code) and -1 if it faulted.
+///
+/// # Safety
+///
+/// vm must come from vmma31_vm_new.
+#[no_mangle]
+pub unsafe extern "C" fn vmma31_step(vm: *mut VM) -> i32 {
+    let Some(vm) = vm.as_mut() else {
+        return -1;
+    };
+    let result = match vm.run_steps(1) {
+        Ok(outcome) if outcome.stop == StepStop::StepLimit => 1,
+        Ok(_) => 0,
+        Err(_) => -1,
+    };
+    vm.flush_output();
+    result
+}
+
+/// Feed input and stinput the lines of text, one per read, instead of
+/// stdin. Reads past the last line get an empty line, as at the end of
+/// stdin. text needn't end in a NUL; invalid UTF-8 is replaced. Returns 0,
+/// or -1 if text is NULL.
+///
+/// # Safety
+///
+/// vm must come from vmma31_vm_new, and text must point to len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn vmma31_set_input(vm: *mut VM, text: *const u8, len: usize) -> i32 {
+    let (Some(vm), false) = (vm.as_mut(), text.is_null()) else {
+        return -1;
+    };
+    let text = String::from_utf8_lossy(slice::from_raw_parts(text, len));
+    vm.set_input(text.lines().map(String::from).collect::<Vec<_>>());
+    0
+}
+
+/// Keep the program's output from now on, for vmma31_take_output, instead
+/// of writing it to stdout.
+///
+/// # Safety
+///
+/// vm must come from vmma31_vm_new.
+#[no_mangle]
+pub unsafe extern "C" fn vmma31_capture_output(vm: *mut VM) {
+    if let Some(vm) = vm.as_mut() {
+        vm.capture_output();
+    }
+}
+
+/// Bytes of captured output not taken yet.
+///
+/// # Safety
+///
+/// vm must come from vmma31_vm_new.
+#[no_mangle]
+pub unsafe extern "C" fn vmma31_output_len(vm: *const VM) -> usize {
+    vm.as_ref().and_then(VM::captured_output).map_or(0, str::len)
+}
+
+/// Move up to len bytes of captured output, oldest first, into out. Returns
+/// the number of bytes moved. That stops short of len rather than split a
+/// UTF-8 character, so len should be at least 4.
+///
+/// # Safety
+///
+/// vm must come from vmma31_vm_new, and out must point to len writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn vmma31_take_output(vm: *mut VM, out: *mut u8, len: usize) -> usize {
+    let (Some(vm), false) = (vm.as_mut(), out.is_null()) else {
+        return 0;
+    };
+    let Some(output) = vm.captured_output.as_mut() else {
+        return 0;
+    };
+    let mut count = len.min(output.len());
+    while !output.is_char_boundary(count) {
+        count -= 1;
+    }
+    std::ptr::copy_nonoverlapping(output.as_ptr(), out, count);
+    output.drain(..count);
+    count
+}
+
+/// Exit code of a finished program (0 until it exits).
+///
+/// # Safety
+///
+/// vm must come from vmma31_vm_new.
+#[no_mangle]
+pub unsafe extern "C" fn vmma31_exit_code(vm: *const VM) -> i32 {
+    vm.as_ref().map_or(0, VM::exit_code)
+}
+
+/// Stack pointer: the address of the top word. The stack runs from here to
+/// the end of memory, and is empty when this equals vmma31_memory_size.
+///
+/// # Safety
+///
+/// vm must come from vmma31_vm_new.
+#[no_mangle]
+pub unsafe extern "C" fn vmma31_sp(vm: *const VM) -> usize {
+    vm.as_ref().map_or(0, VM::sp)
+}
+
+/// Size of the VM's memory in bytes.
+///
+/// # Safety
+///
+/// vm must come from vmma31_vm_new.
+#[no_mangle]
+pub unsafe extern "C" fn vmma31_memory_size(vm: *const VM) -> usize {
+    vm.as_ref().map_or(0, |vm| vm.memory().len())
+}
+
+/// Copy up to len bytes of VM memory starting at addr into out. Returns the
+/// number of bytes copied, which is less than len past the end of memory.
+///
+/// # Safety
+///
+/// vm must come from vmma31_vm_new, and out must point to len writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn vmma31_read_memory(vm: *const VM, addr: usize, out: *mut u8, len: usize) -> usize {
+    let (Some(vm), false) = (vm.as_ref(), out.is_null()) else {
+        return 0;
+    };
+    let memory = vm.memory();
+    let start = addr.min(memory.len());
+    let count = len.min(memory.len() - start);
+    std::ptr::copy_nonoverlapping(memory[start..].as_ptr(), out, count);
+    count
+}
+
+/// Destroy a VM. Passing NULL does nothing.
+///
+/// # Safety
+///
+/// vm must come from vmma31_vm_new and not be used again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn vmma31_free(vm: *mut VM) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
@@ -0,0 +1,64 @@
+// Q16.16 fixed-point helpers. A word holds a signed value scaled by 2^16,
+// so 0x00018000 is 1.5. Results are computed and rounded in 64 bits; the VM
+// narrows them back to a word, which wraps or, under trapv, faults.
+
+pub const FRACTION_BITS: u32 = 16;
+pub const ONE: i64 = 1 << FRACTION_BITS;
+
+// Rounding mode selected by the low two bits of the instruction
+#[derive(Clone, Copy)]
+pub enum Rounding {
+    Truncate, // Toward zero
+    Nearest,  // Halfway cases away from zero
+    Floor,
+    Ceil,
+}
+
+impl Rounding {
+    pub fn from_bits(bits: u32) -> Rounding {
+        match bits & 0x3 {
+            0 => Rounding::Truncate,
+            1 => Rounding::Nearest,
+            2 => Rounding::Floor,
+            _ => Rounding::Ceil,
+        }
+    }
+}
+
+pub fn mul(left: i32, right: i32, rounding: Rounding) -> i64 {
+    divide(left as i64 * right as i64, ONE, rounding)
+}
+
+// right must be non-zero; qdiv by zero faults before getting here, as div does
+pub fn div(left: i32, right: i32, rounding: Rounding) -> i64 {
+    divide((left as i64) << FRACTION_BITS, right as i64, rounding)
+}
+
+// Divide n by a non-zero d using the given rounding mode
+fn divide(n: i64, d: i64, rounding: Rounding) -> i64 {
+    let quotient = n / d;
+    let remainder = n % d;
+    if remainder == 0 {
+        return quotient;
+    }
+    let negative = (n < 0) != (d < 0);
+    match rounding {
+        Rounding::Truncate => quotient,
+        Rounding::Nearest if 2 * remainder.abs() >= d.abs() => {
+            if negative { quotient - 1 } else { quotient + 1 }
+        }
+        Rounding::Nearest => quotient,
+        Rounding::Floor if negative => quotient - 1,
+        Rounding::Ceil if !negative => quotient + 1,
+        Rounding::Floor | Rounding::Ceil => quotient,
+    }
+}
+
+pub fn to_f64(value: i32) -> f64 {
+    value as f64 / ONE as f64
+}
+
+// Round to the nearest Q16.16 value; saturates on overflow and maps NaN to 0
+pub fn from_f64(value: f64) -> i32 {
+    (value * ONE as f64).round() as i32
+}
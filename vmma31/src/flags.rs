@@ -0,0 +1,83 @@
+// The flags register behind cmp and the flag branches, for programs written
+// as for a machine with condition codes rather than with the ifs, which
+// compare the top of the stack as they branch. cmp pops two words and sets
+// the flags from subtracting the top one from the one below it, as ARM does:
+//
+//   N  the difference is negative
+//   Z  it is 0, so the words are equal
+//   C  there was no borrow: the first is at least the second, unsigned
+//   V  the subtraction overflowed as signed
+//
+// Only cmp changes them. A flag branch holds a condition code in bits 23:20,
+// also as ARM numbers them: each even code is a test of the flags and the
+// odd code after it is the opposite.
+//
+//   0 beq  Z         2 bhs  C         4 bmi  N         6 bvs  V
+//   1 bne  !Z        3 blo  !C        5 bpl  !N        7 bvc  !V
+//   8 bhi  C && !Z   10 bge  N == V   12 bgt  !Z && N == V
+//   9 bls  !C || Z   11 blt  N != V   13 ble  Z || N != V
+
+use crate::isa::Op;
+use crate::VM;
+
+pub(crate) const NEGATIVE: u32 = 1 << 3;
+pub(crate) const ZERO: u32 = 1 << 2;
+pub(crate) const CARRY: u32 = 1 << 1;
+pub(crate) const OVERFLOW: u32 = 1 << 0;
+
+impl VM {
+    // cmp: pop right and left and set the flags from left - right
+    pub(crate) fn exec_cmp(&mut self) {
+        let right = self.pop();
+        let left = self.pop();
+        if self.fault.is_none() {
+            self.flags = compare(left, right);
+        }
+    }
+
+    pub(crate) fn exec_flag_branch(&mut self, op: Op, target: i32) {
+        if condition_met(condition_code(op), self.flags) {
+            self.pc = target as usize;
+        }
+    }
+}
+
+// The flags cmp sets for left - right
+pub(crate) fn compare(left: u32, right: u32) -> u32 {
+    let (difference, borrow) = left.overflowing_sub(right);
+    let overflow = (left as i32).overflowing_sub(right as i32).1;
+    let mut flags = 0;
+    for (flag, set) in [(NEGATIVE, (difference as i32) < 0), (ZERO, difference == 0), (CARRY, !borrow), (OVERFLOW, overflow)] {
+        if set {
+            flags |= flag;
+        }
+    }
+    flags
+}
+
+// A flag branch's condition code
+pub(crate) fn condition_code(op: Op) -> u32 {
+    op.encode() >> 20 & 0xF
+}
+
+pub(crate) fn condition_met(code: u32, flags: u32) -> bool {
+    let [n, z, c, v] = [NEGATIVE, ZERO, CARRY, OVERFLOW].map(|flag| flags & flag != 0);
+    let holds = match code >> 1 {
+        0 => z,
+        1 => c,
+        2 => n,
+        3 => v,
+        4 => c && !z,
+        5 => n == v,
+        _ => !z && n == v,
+    };
+    holds != (code & 1 != 0)
+}
+
+// The flags as the debugger shows them: a capital letter for each one set
+pub(crate) fn describe(flags: u32) -> String {
+    [(NEGATIVE, 'N'), (ZERO, 'Z'), (CARRY, 'C'), (OVERFLOW, 'V')]
+        .iter()
+        .map(|&(flag, letter)| if flags & flag != 0 { letter } else { letter.to_ascii_lowercase() })
+        .collect()
+}
@@ -0,0 +1,197 @@
+// Guest processes for the fork and wait instructions. The VM only ever runs
+// one process; the others are saved contexts (memory, registers, call
+// tracking and shadow stack) waiting in a queue. Scheduling is cooperative:
+// a process keeps running until it exits or blocks in wait, and then the
+// oldest ready process is switched in. When the root process exits the
+// whole program ends, even if children never got to run.
+//
+// Every saved process holds a copy of memory, so there can be at most
+// MAX_PROCESSES besides the running one, and fewer when memory is large:
+// their copies can take up SAVED_MEMORY_LIMIT bytes at most, though one is
+// always allowed. Exited processes count until they're waited for. Past
+// the limit, fork pushes -1 and no child is made.
+
+use std::mem;
+
+use crate::conventions::CallFrame;
+use crate::heap::Heap;
+use crate::VM;
+
+pub(crate) const ROOT_PID: u32 = 1;
+const MAX_PROCESSES: usize = 64;
+const SAVED_MEMORY_LIMIT: usize = 256 << 20;
+
+#[derive(Clone)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Process {
+    id: u32,
+    parent: u32,
+    state: ProcessState,
+    memory: Vec<u8>, // Empty once the process has exited
+    pc: usize,
+    sp: usize,
+    #[cfg_attr(feature = "snapshot", serde(default))]
+    fp: usize, // 0 in snapshots from before enter and leave, which no program could use then
+    #[cfg_attr(feature = "snapshot", serde(default))]
+    flags: u32, // 0 in snapshots from before cmp
+    #[cfg_attr(feature = "snapshot", serde(default))]
+    heap: Heap, // Empty in snapshots from before alloc and free
+    call_frames: Vec<CallFrame>,
+    shadow_stack: Vec<u32>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+enum ProcessState {
+    Ready,
+    Waiting,
+    Delivered(u32, i32), // Woken by a child exit; push (id, code) when resumed
+    Zombie(i32),         // Exited with this code, not yet collected by wait
+}
+
+impl Process {
+    // Whether a process from a snapshot can be switched to in a VM with len
+    // bytes of memory; exited ones keep no memory
+    pub(crate) fn fits(&self, len: usize) -> bool {
+        matches!(self.state, ProcessState::Zombie(_)) || (self.memory.len() == len && self.pc <= len && self.sp <= len)
+    }
+}
+
+impl VM {
+    // fork: push the child id in the parent and 0 in the child. The parent
+    // keeps running; the child resumes after the fork once it is scheduled.
+    // Pushes -1 if there are too many processes already.
+    pub(crate) fn fork(&mut self) {
+        let limit = (SAVED_MEMORY_LIMIT / self.memory.len().max(1)).clamp(1, MAX_PROCESSES);
+        if self.processes.len() >= limit {
+            self.push(-1i32 as u32);
+            return;
+        }
+        let id = self.next_pid;
+        self.next_pid += 1;
+
+        self.push(0);
+        let child = self.save_context(id, self.pid, ProcessState::Ready);
+        self.pop();
+        self.processes.push(child);
+        self.push(id);
+    }
+
+    // wait: push the id and exit code of a finished child, blocking until one
+    // exits. Pushes -1 and 0 if this process has no children left.
+    pub(crate) fn wait(&mut self) {
+        let pid = self.pid;
+        let zombie = self.processes.iter().position(|p| {
+            p.parent == pid && matches!(p.state, ProcessState::Zombie(_))
+        });
+        if let Some(index) = zombie {
+            let child = self.processes.remove(index);
+            if let ProcessState::Zombie(code) = child.state {
+                self.push(child.id);
+                self.push(code as u32);
+            }
+        } else if self.processes.iter().any(|p| p.parent == pid) {
+            let current = self.save_context(pid, self.parent_pid, ProcessState::Waiting);
+            self.processes.push(current);
+            self.switch_to_next();
+        } else {
+            self.push(-1i32 as u32);
+            self.push(0);
+        }
+    }
+
+    // Exit from a process other than the root
+    pub(crate) fn exit_process(&mut self, code: i32) {
+        let pid = self.pid;
+        self.processes.push(Process {
+            id: pid,
+            parent: self.parent_pid,
+            state: ProcessState::Zombie(code),
+            memory: Vec::new(),
+            pc: 0,
+            sp: self.memory.len(),
+            fp: self.memory.len(),
+            flags: 0,
+            heap: Heap::default(),
+            call_frames: Vec::new(),
+            shadow_stack: Vec::new(),
+        });
+        // Orphans are adopted by the root so their exit codes aren't lost
+        for process in self.processes.iter_mut().filter(|p| p.parent == pid) {
+            process.parent = ROOT_PID;
+        }
+        self.switch_to_next();
+    }
+
+    // Snapshot the running process, resuming after the current instruction
+    fn save_context(&self, id: u32, parent: u32, state: ProcessState) -> Process {
+        Process {
+            id,
+            parent,
+            state,
+            memory: self.memory.to_vec(),
+            pc: self.pc + 4,
+            sp: self.sp,
+            fp: self.fp,
+            flags: self.flags,
+            heap: self.heap.clone(),
+            call_frames: self.call_frames.clone(),
+            shadow_stack: self.shadow_stack.clone(),
+        }
+    }
+
+    // Hand each waiting process the exit code of one of its finished children
+    fn wake_waiting(&mut self) {
+        let mut collected = Vec::new();
+        for waiter in 0..self.processes.len() {
+            if self.processes[waiter].state != ProcessState::Waiting {
+                continue;
+            }
+            let id = self.processes[waiter].id;
+            let zombie = self.processes.iter().position(|p| {
+                p.parent == id && matches!(p.state, ProcessState::Zombie(_))
+            });
+            if let Some(zombie) = zombie {
+                if let ProcessState::Zombie(code) = self.processes[zombie].state {
+                    self.processes[waiter].state = ProcessState::Delivered(self.processes[zombie].id, code);
+                    collected.push(zombie);
+                }
+            }
+        }
+        collected.sort_unstable();
+        for index in collected.into_iter().rev() {
+            self.processes.remove(index);
+        }
+    }
+
+    fn switch_to_next(&mut self) {
+        self.wake_waiting();
+        let next = self.processes.iter().position(|p| {
+            matches!(p.state, ProcessState::Ready | ProcessState::Delivered(..))
+        });
+        let mut process = match next {
+            Some(index) => self.processes.remove(index),
+            None => {
+                self.exited = true; // Nothing can make progress
+                return;
+            }
+        };
+
+        self.memory.copy_from_slice(&process.memory);
+        self.predecode();
+        self.pc = process.pc;
+        self.sp = process.sp;
+        self.fp = process.fp;
+        self.flags = process.flags;
+        self.heap = mem::take(&mut process.heap);
+        self.call_frames = mem::take(&mut process.call_frames);
+        self.shadow_stack = mem::take(&mut process.shadow_stack);
+        self.pid = process.id;
+        self.parent_pid = process.parent;
+        self.switched = true;
+        if let ProcessState::Delivered(child, code) = process.state {
+            self.push(child);
+            self.push(code as u32);
+        }
+    }
+}
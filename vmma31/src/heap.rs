@@ -0,0 +1,113 @@
+// The heap behind alloc and free: the memory between the end of the program
+// (its code and blocks) and the stack. A request is served from the first
+// freed block big enough, split if it's bigger, or else from the top of the
+// heap, which moves up towards the stack; when the two would meet, alloc
+// faults with out of memory. Freeing merges a block with free neighbours and
+// gives a free block at the top back to the stack. The block list is kept by
+// the host, so a guest writing past a block corrupts only data.
+
+use crate::error::VmError;
+use crate::VM;
+
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Heap {
+    start: usize,       // First word after the program
+    top: usize,         // End of the last block; 0 while there are none
+    blocks: Vec<Block>, // From start up with no gaps; the last one is in use
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+struct Block {
+    size: usize,
+    used: bool,
+}
+
+impl Heap {
+    pub(crate) fn new(start: usize) -> Heap {
+        Heap { start, top: 0, blocks: Vec::new() }
+    }
+
+    // Lowest address the stack may grow down to
+    pub(crate) fn top(&self) -> usize {
+        self.top
+    }
+
+    // A block of at least size bytes (rounded up to whole words, 0 giving
+    // one), ending at or below limit if it's new; None when nothing fits
+    fn alloc(&mut self, size: usize, limit: usize) -> Option<usize> {
+        let size = size.max(1).checked_next_multiple_of(4)?;
+        let mut addr = self.start;
+        for i in 0..self.blocks.len() {
+            let block = self.blocks[i];
+            if !block.used && block.size >= size {
+                if block.size > size {
+                    self.blocks.insert(i + 1, Block { size: block.size - size, used: false });
+                }
+                self.blocks[i] = Block { size, used: true };
+                return Some(addr);
+            }
+            addr += block.size;
+        }
+        if addr.checked_add(size).is_none_or(|end| end > limit) {
+            return None;
+        }
+        self.blocks.push(Block { size, used: true });
+        self.top = addr + size;
+        Some(addr)
+    }
+
+    // Give back the block alloc returned at addr; false if there is none
+    fn free(&mut self, addr: usize) -> bool {
+        let mut start = self.start;
+        let Some(mut i) = self.blocks.iter().position(|block| {
+            let here = start;
+            start += block.size;
+            here == addr && block.used
+        }) else {
+            return false;
+        };
+        self.blocks[i].used = false;
+        if self.blocks.get(i + 1).is_some_and(|next| !next.used) {
+            self.blocks[i].size += self.blocks.remove(i + 1).size;
+        }
+        if i > 0 && !self.blocks[i - 1].used {
+            self.blocks[i - 1].size += self.blocks.remove(i).size;
+            i -= 1;
+        }
+        if i == self.blocks.len() - 1 {
+            self.top -= self.blocks.pop().map_or(0, |block| block.size);
+            if self.blocks.is_empty() {
+                self.top = 0;
+            }
+        }
+        true
+    }
+}
+
+impl VM {
+    // alloc: pop a size in bytes and push the address of a new block
+    pub(crate) fn exec_alloc(&mut self) {
+        let size = self.pop();
+        if self.fault.is_some() {
+            return;
+        }
+        match self.heap.alloc(size as usize, self.sp) {
+            Some(addr) => {
+                self.telemetry.heap_top = self.telemetry.heap_top.max(self.heap.top);
+                self.push(addr as u32);
+            }
+            None => self.raise(VmError::OutOfMemory { pc: self.pc, size: size as usize }),
+        }
+    }
+
+    // free: pop an address alloc returned and give its block back. 0 is
+    // ignored, as is any other address, unless strict.
+    pub(crate) fn exec_free(&mut self) {
+        let addr = self.pop() as usize;
+        if self.fault.is_none() && addr != 0 && !self.heap.free(addr) && self.strict {
+            self.raise(VmError::OutOfBounds { pc: self.pc, sp: self.sp, addr });
+        }
+    }
+}
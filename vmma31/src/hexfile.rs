@@ -0,0 +1,153 @@
+// Program files written as text, for tooling that passes programs around
+// as hex. Two forms are read besides the binary file itself:
+//
+//   :10000000DEADBEEF...CC   Intel HEX: data (00), end of file (01) and
+//                            extended address (02, 04) records, holding
+//                            the bytes of the binary file
+//   f0000007                 one code word per line in hex, optionally
+//   0xd0000000               with 0x, as disassemble shows them; blank
+//                            lines and `#` or `;` comments are skipped
+//
+// Either can leave out the magic bytes (for hex words, a first line of
+// deadbeef), in which case the contents are taken as bare code, as in a
+// file from before the header.
+
+use crate::{MAGIC, MAX_RAM_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Auto, // Whichever of the others the contents look like
+    Binary,
+    IntelHex,
+    HexWords,
+}
+
+impl InputFormat {
+    // Parse the --format argument: auto, binary, ihex or hex
+    pub fn parse(text: &str) -> Option<InputFormat> {
+        match text {
+            "auto" => Some(InputFormat::Auto),
+            "binary" => Some(InputFormat::Binary),
+            "ihex" => Some(InputFormat::IntelHex),
+            "hex" => Some(InputFormat::HexWords),
+            _ => None,
+        }
+    }
+
+    // The format bytes appear to be in: binary if they start with the magic
+    // bytes, Intel HEX if the first line is a record, hex words if every
+    // line is one, and otherwise binary (which then fails to load)
+    pub fn detect(bytes: &[u8]) -> InputFormat {
+        if bytes.starts_with(&MAGIC) {
+            return InputFormat::Binary;
+        }
+        let Ok(text) = std::str::from_utf8(bytes) else {
+            return InputFormat::Binary;
+        };
+        let mut lines = text.lines().map(without_comment).filter(|line| !line.is_empty());
+        match lines.clone().next() {
+            Some(line) if line.starts_with(':') => InputFormat::IntelHex,
+            Some(_) if lines.all(|line| hex_word_line(line).is_some()) => InputFormat::HexWords,
+            _ => InputFormat::Binary,
+        }
+    }
+}
+
+// The binary program file that bytes hold in the given format
+pub fn decode(bytes: &[u8], format: InputFormat) -> Result<Vec<u8>, String> {
+    let format = match format {
+        InputFormat::Auto => InputFormat::detect(bytes),
+        format => format,
+    };
+    if format == InputFormat::Binary {
+        return Ok(bytes.to_vec());
+    }
+    let text = std::str::from_utf8(bytes).map_err(|_| "Invalid hex file: not text".to_string())?;
+    let image = match format {
+        InputFormat::IntelHex => intel_hex(text)?,
+        _ => hex_words(text)?,
+    };
+    if image.starts_with(&MAGIC) {
+        return Ok(image);
+    }
+    Ok(MAGIC.iter().copied().chain(image).collect())
+}
+
+fn intel_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut image = Vec::new();
+    let mut base = 0usize;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |reason: &str| format!("Invalid Intel HEX file: line {}: {}", index + 1, reason);
+        let digits = line.strip_prefix(':').ok_or_else(|| error("record doesn't start with ':'"))?;
+        let record = hex_bytes(digits).ok_or_else(|| error("not hex digits"))?;
+        if record.len() < 5 || record.len() != record[0] as usize + 5 {
+            return Err(error("wrong record length"));
+        }
+        if record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(error("checksum mismatch"));
+        }
+        let addr = u16::from_be_bytes([record[1], record[2]]) as usize;
+        let data = &record[4..record.len() - 1];
+        match record[3] {
+            0x00 => {
+                let start = base + addr;
+                if start + data.len() > MAX_RAM_SIZE {
+                    return Err(error("data past the largest memory"));
+                }
+                if image.len() < start + data.len() {
+                    image.resize(start + data.len(), 0);
+                }
+                image[start..start + data.len()].copy_from_slice(data);
+            }
+            0x01 => break,
+            0x02 | 0x04 if data.len() == 2 => {
+                let value = u16::from_be_bytes([data[0], data[1]]) as usize;
+                base = if record[3] == 0x02 { value << 4 } else { value << 16 };
+            }
+            0x03 | 0x05 => {} // Start addresses; the program file has its own entry point
+            kind => return Err(error(&format!("unsupported record type {:02x}", kind))),
+        }
+    }
+    Ok(image)
+}
+
+fn hex_words(text: &str) -> Result<Vec<u8>, String> {
+    let mut image = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = without_comment(line);
+        if line.is_empty() {
+            continue;
+        }
+        let word = hex_word_line(line).ok_or_else(|| format!("Invalid hex file: line {}: not a hex word", index + 1))?;
+        if image.is_empty() && word == u32::from_be_bytes(MAGIC) {
+            image.extend_from_slice(&MAGIC);
+        } else {
+            image.extend_from_slice(&word.to_le_bytes());
+        }
+    }
+    Ok(image)
+}
+
+fn without_comment(line: &str) -> &str {
+    line.split(['#', ';']).next().unwrap_or("").trim()
+}
+
+// The word on a line of a hex words file, without its comment
+fn hex_word_line(line: &str) -> Option<u32> {
+    let digits = line.strip_prefix("0x").unwrap_or(line);
+    if digits.is_empty() || digits.len() > 8 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+fn hex_bytes(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..digits.len()).step_by(2).map(|index| u8::from_str_radix(&digits[index..index + 2], 16).ok()).collect()
+}
@@ -0,0 +1,678 @@
+// The instruction set as data. Each entry gives an instruction's mnemonic,
+// the bits that identify it and where its operands live, so decoding (and
+// anything else that needs to know the encoding) reads from this one table
+// instead of keeping its own copy of the bit layout.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::program::{join_program, split_program, Sections, Symbols};
+
+// Operand field: `width` bits starting at `shift`, optionally sign-extended,
+// then multiplied by `scale` (4 for fields counted in words). `branch` marks
+// offsets the VM adds to the PC rather than the SP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub shift: u32,
+    pub width: u32,
+    pub signed: bool,
+    pub scale: i32,
+    pub branch: bool,
+}
+
+impl Field {
+    pub fn extract(&self, word: u32) -> i32 {
+        let raw = (word >> self.shift) & ((1u32 << self.width) - 1);
+        let value = if self.signed {
+            ((raw << (32 - self.width)) as i32) >> (32 - self.width)
+        } else {
+            raw as i32
+        };
+        value.wrapping_mul(self.scale)
+    }
+
+    // An extracted value as disassembly shows it: branch offsets signed, or
+    // when the branch's own address is known, the target's symbol or address
+    fn format(&self, value: i32, addr: Option<usize>, symbols: Option<&Symbols>) -> String {
+        match addr.map(|addr| addr as i64 + value as i64) {
+            Some(target) if self.branch && target >= 0 => {
+                match symbols.and_then(|symbols| symbols.name_at(target as usize)) {
+                    Some(name) => name.to_string(),
+                    None => format!("{:#06x}", target),
+                }
+            }
+            _ if self.branch => format!("{:+}", value),
+            _ => value.to_string(),
+        }
+    }
+
+    // Smallest and largest values extract can give
+    pub fn range(&self) -> (i64, i64) {
+        let (low, high) = if self.signed {
+            (-(1i64 << (self.width - 1)), (1i64 << (self.width - 1)) - 1)
+        } else {
+            (0, (1i64 << self.width) - 1)
+        };
+        (low * self.scale as i64, high * self.scale as i64)
+    }
+
+    // Like insert, but says why a value doesn't fit
+    pub fn insert_checked(&self, value: i64) -> Result<u32, String> {
+        let (low, high) = self.range();
+        if !(low..=high).contains(&value) {
+            return Err(format!("{} is out of range ({} to {})", value, low, high));
+        }
+        if value % self.scale as i64 != 0 {
+            return Err(format!("{} is not a multiple of {}", value, self.scale));
+        }
+        Ok(self.insert(value as i32).expect("value checked against the field"))
+    }
+
+    // Inverse of extract; None if the value isn't a multiple of scale or doesn't fit
+    pub fn insert(&self, value: i32) -> Option<u32> {
+        if value % self.scale != 0 {
+            return None;
+        }
+        let value = value / self.scale;
+        let fits = if self.signed {
+            let limit = 1i64 << (self.width - 1);
+            (-limit..limit).contains(&(value as i64))
+        } else {
+            (0..1i64 << self.width).contains(&(value as i64))
+        };
+        let mask = (1u32 << self.width) - 1;
+        fits.then_some(((value as u32) & mask) << self.shift)
+    }
+}
+
+const NUMBER: Field = Field { shift: 0, width: 12, signed: false, scale: 1, branch: false }; // Exit code, syscall or counter
+const SWAP_FROM: Field = Field { shift: 12, width: 12, signed: true, scale: 4, branch: false };
+const SWAP_TO: Field = Field { shift: 0, width: 12, signed: true, scale: 4, branch: false };
+const MAX_CHARS: Field = Field { shift: 0, width: 24, signed: false, scale: 1, branch: false };
+const POP_BYTES: Field = Field { shift: 2, width: 26, signed: false, scale: 4, branch: false };
+const OFFSET: Field = Field { shift: 2, width: 26, signed: true, scale: 4, branch: false };
+const TARGET: Field = Field { shift: 2, width: 26, signed: true, scale: 4, branch: true };
+const BINARY_IF_TARGET: Field = Field { shift: 2, width: 23, signed: true, scale: 4, branch: true };
+const FLAG_TARGET: Field = Field { shift: 2, width: 18, signed: true, scale: 4, branch: true };
+const UNARY_IF_TARGET: Field = Field { shift: 2, width: 22, signed: true, scale: 4, branch: true };
+const ROUNDING: Field = Field { shift: 0, width: 2, signed: false, scale: 1, branch: false };
+const VALUE: Field = Field { shift: 0, width: 28, signed: true, scale: 1, branch: false };
+const INDEX: Field = Field { shift: 0, width: 24, signed: false, scale: 1, branch: false }; // Into the globals region, in words
+const LOCALS: Field = Field { shift: 0, width: 24, signed: false, scale: 1, branch: false }; // Words enter reserves
+const LOCAL: Field = Field { shift: 0, width: 24, signed: true, scale: 1, branch: false }; // Words from the frame pointer
+const PRINT_FROM: Field = Field { shift: 12, width: 12, signed: true, scale: 4, branch: false };
+const FORMAT: Field = Field { shift: 0, width: 12, signed: false, scale: 1, branch: false }; // printf format bits
+const ADDRESS: Field = Field { shift: 0, width: 23, signed: false, scale: 1, branch: false }; // Absolute, below 8 MiB
+
+// `bits` is the instruction with every operand zero
+#[derive(Debug)]
+pub struct InstrSpec {
+    pub op: Op,
+    pub mnemonic: &'static str,
+    pub bits: u32,
+    pub operands: &'static [Field],
+}
+
+impl InstrSpec {
+    // Operand values in table order; missing operands are 0
+    pub fn operands(&self, word: u32) -> [i32; 2] {
+        let mut values = [0; 2];
+        for (value, field) in values.iter_mut().zip(self.operands) {
+            *value = field.extract(word);
+        }
+        values
+    }
+
+    // Build the word for this instruction; None if the operand count is wrong
+    // or a value doesn't fit its field
+    pub fn encode(&self, values: &[i32]) -> Option<u32> {
+        if values.len() != self.operands.len() {
+            return None;
+        }
+        self.operands
+            .iter()
+            .zip(values)
+            .try_fold(self.bits, |word, (field, &value)| Some(word | field.insert(value)?))
+    }
+
+    // Like encode, but says what's wrong: the operand count, or which operand
+    // doesn't fit and why
+    pub fn encode_checked(&self, values: &[i64]) -> Result<u32, String> {
+        if values.len() != self.operands.len() {
+            return Err(format!("{} takes {} operand(s), got {}", self.mnemonic, self.operands.len(), values.len()));
+        }
+        self.operands.iter().zip(values).enumerate().try_fold(self.bits, |word, (index, (field, &value))| {
+            let bits = field
+                .insert_checked(value)
+                .map_err(|e| format!("{} operand {}: {}", self.mnemonic, index + 1, e))?;
+            Ok(word | bits)
+        })
+    }
+
+    // Operands as written in assembly, with offsets in bytes as the VM applies
+    // them. Branch offsets carry a sign (`goto +16`, `ifeq -8`).
+    pub fn format_operands(&self, word: u32) -> String {
+        self.operands.iter().map(|field| format!(" {}", field.format(field.extract(word), None, None))).collect()
+    }
+
+    // Like format_operands, but branches show the address they go to when
+    // the instruction is at addr (`goto 0x0024`)
+    pub fn format_operands_at(&self, word: u32, addr: usize) -> String {
+        self.operands.iter().map(|field| format!(" {}", field.format(field.extract(word), Some(addr), None))).collect()
+    }
+
+    // Like format_operands_at, but a target with a symbol shows its name
+    // (`call factorial`)
+    pub fn format_operands_named(&self, word: u32, addr: usize, symbols: &Symbols) -> String {
+        self.operands
+            .iter()
+            .map(|field| format!(" {}", field.format(field.extract(word), Some(addr), Some(symbols))))
+            .collect()
+    }
+}
+
+const GROUP: u32 = 0xF000_0000; // Opcode only
+const SUB: u32 = 0xFF00_0000; // Opcode and sub-opcode in bits 27:24
+const BINARY_IF: u32 = 0xFE00_0001; // Condition in bits 27:25, and bit 0 set for unsigned
+const UNARY_IF: u32 = 0xF300_0000; // Condition in bits 25:24
+const PRINT: u32 = 0xF000_0003; // Format in bits 1:0
+const MEMORY: u32 = 0xFF80_0000; // Sub-opcode, and bit 23 set for an address operand
+const MULH: u32 = 0xFF00_0001; // Sub-opcode, and bit 0 set for unsigned
+const FLAG_IF: u32 = 0xFFF0_0000; // Sub-opcode, and condition in bits 23:20
+
+// One line per instruction: variant, mnemonic, mask, bits and operand fields.
+// A word is that instruction when `word & mask == bits`. Generates the Op
+// enum, the INSTRUCTIONS table (indexed by Op) and decode.
+macro_rules! define_instructions {
+    ($($op:ident $mnemonic:literal $mask:ident $bits:literal [$($field:ident),*];)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Op {
+            $($op,)*
+        }
+
+        pub const INSTRUCTIONS: &[InstrSpec] = &[
+            $(InstrSpec { op: Op::$op, mnemonic: $mnemonic, bits: $bits, operands: &[$($field),*] },)*
+        ];
+
+        // The instruction a word encodes, if any
+        pub fn decode(word: u32) -> Option<Op> {
+            $(if word & $mask == $bits {
+                return Some(Op::$op);
+            })*
+            None
+        }
+    };
+}
+
+define_instructions! {
+    Exit     "exit"     SUB       0x0000_0000 [NUMBER];
+    Swap     "swap"     SUB       0x0100_0000 [SWAP_FROM, SWAP_TO];
+    Nop      "nop"      SUB       0x0200_0000 [];
+    Syscall  "syscall"  SUB       0x0300_0000 [NUMBER];
+    Input    "input"    SUB       0x0400_0000 [];
+    Stinput  "stinput"  SUB       0x0500_0000 [MAX_CHARS];
+    Fork     "fork"     SUB       0x0600_0000 [];
+    Wait     "wait"     SUB       0x0700_0000 [];
+    Cpuid    "cpuid"    SUB       0x0800_0000 [];
+    Counter  "counter"  SUB       0x0900_0000 [NUMBER];
+    Printf   "printf"   SUB       0x0B00_0000 [PRINT_FROM, FORMAT];
+    Trapv    "trapv"    SUB       0x0C00_0000 [NUMBER];
+    Debug    "debug"    SUB       0x0F00_0000 [];
+    Pop      "pop"      GROUP     0x1000_0000 [POP_BYTES];
+    Add      "add"      SUB       0x2000_0000 [];
+    Sub      "sub"      SUB       0x2100_0000 [];
+    Mul      "mul"      SUB       0x2200_0000 [];
+    Div      "div"      SUB       0x2300_0000 [];
+    Rem      "rem"      SUB       0x2400_0000 [];
+    And      "and"      SUB       0x2500_0000 [];
+    Or       "or"       SUB       0x2600_0000 [];
+    Xor      "xor"      SUB       0x2700_0000 [];
+    Lsl      "lsl"      SUB       0x2800_0000 [];
+    Lsr      "lsr"      SUB       0x2900_0000 [];
+    Min      "min"      SUB       0x2A00_0000 [];
+    Asr      "asr"      SUB       0x2B00_0000 [];
+    Qmul     "qmul"     SUB       0x2C00_0000 [ROUNDING];
+    Qdiv     "qdiv"     SUB       0x2D00_0000 [ROUNDING];
+    Max      "max"      SUB       0x2E00_0000 [];
+    Mulh     "mulh"     MULH      0x2F00_0000 [];
+    Mulhu    "mulhu"    MULH      0x2F00_0001 [];
+    Neg      "neg"      SUB       0x3000_0000 [];
+    Not      "not"      SUB       0x3100_0000 [];
+    Toupper  "toupper"  SUB       0x3200_0000 [];
+    Tolower  "tolower"  SUB       0x3300_0000 [];
+    Isdigit  "isdigit"  SUB       0x3400_0000 [];
+    Isalpha  "isalpha"  SUB       0x3500_0000 [];
+    Popcount "popcount" SUB       0x3600_0000 [];
+    Clz      "clz"      SUB       0x3700_0000 [];
+    Ctz      "ctz"      SUB       0x3800_0000 [];
+    Abs      "abs"      SUB       0x3900_0000 [];
+    Stprint  "stprint"  GROUP     0x4000_0000 [OFFSET];
+    Call     "call"     GROUP     0x5000_0000 [TARGET];
+    Return   "return"   GROUP     0x6000_0000 [OFFSET];
+    Goto     "goto"     GROUP     0x7000_0000 [TARGET];
+    Ifeq     "ifeq"     BINARY_IF 0x8000_0000 [BINARY_IF_TARGET];
+    Ifne     "ifne"     BINARY_IF 0x8200_0000 [BINARY_IF_TARGET];
+    Iflt     "iflt"     BINARY_IF 0x8400_0000 [BINARY_IF_TARGET];
+    Ifgt     "ifgt"     BINARY_IF 0x8600_0000 [BINARY_IF_TARGET];
+    Ifle     "ifle"     BINARY_IF 0x8800_0000 [BINARY_IF_TARGET];
+    Ifge     "ifge"     BINARY_IF 0x8A00_0000 [BINARY_IF_TARGET];
+    Ifltu    "ifltu"    BINARY_IF 0x8400_0001 [BINARY_IF_TARGET];
+    Ifgtu    "ifgtu"    BINARY_IF 0x8600_0001 [BINARY_IF_TARGET];
+    Ifleu    "ifleu"    BINARY_IF 0x8800_0001 [BINARY_IF_TARGET];
+    Ifgeu    "ifgeu"    BINARY_IF 0x8A00_0001 [BINARY_IF_TARGET];
+    Ifez     "ifez"     UNARY_IF  0x9000_0000 [UNARY_IF_TARGET];
+    Ifnz     "ifnz"     UNARY_IF  0x9100_0000 [UNARY_IF_TARGET];
+    Ifmi     "ifmi"     UNARY_IF  0x9200_0000 [UNARY_IF_TARGET];
+    Ifpl     "ifpl"     UNARY_IF  0x9300_0000 [UNARY_IF_TARGET];
+    Vadd     "vadd"     SUB       0xA000_0000 [];
+    Vsub     "vsub"     SUB       0xA100_0000 [];
+    Vmul     "vmul"     SUB       0xA200_0000 [];
+    Load     "load"     MEMORY    0xA400_0000 [];
+    Loadi    "loadi"    MEMORY    0xA480_0000 [ADDRESS];
+    Loadb    "loadb"    MEMORY    0xA500_0000 [];
+    Loadbi   "loadbi"   MEMORY    0xA580_0000 [ADDRESS];
+    Store    "store"    MEMORY    0xA600_0000 [];
+    Storei   "storei"   MEMORY    0xA680_0000 [ADDRESS];
+    Storeb   "storeb"   MEMORY    0xA700_0000 [];
+    Storebi  "storebi"  MEMORY    0xA780_0000 [ADDRESS];
+    Gload    "gload"    SUB       0xA800_0000 [INDEX];
+    Gstore   "gstore"   SUB       0xA900_0000 [INDEX];
+    Enter    "enter"    SUB       0xAA00_0000 [LOCALS];
+    Leave    "leave"    SUB       0xAB00_0000 [];
+    Lload    "lload"    SUB       0xAC00_0000 [LOCAL];
+    Lstore   "lstore"   SUB       0xAD00_0000 [LOCAL];
+    Alloc    "alloc"    SUB       0xAE00_0000 [];
+    Free     "free"     SUB       0xAF00_0000 [];
+    B64enc   "b64enc"   SUB       0xB000_0000 [];
+    B64dec   "b64dec"   SUB       0xB100_0000 [];
+    Hexenc   "hexenc"   SUB       0xB200_0000 [];
+    Hexdec   "hexdec"   SUB       0xB300_0000 [];
+    Memcpy   "memcpy"   SUB       0xB400_0000 [];
+    Memset   "memset"   SUB       0xB500_0000 [];
+    Memcmp   "memcmp"   SUB       0xB600_0000 [];
+    Add64    "add64"    SUB       0xB700_0000 [];
+    Sub64    "sub64"    SUB       0xB800_0000 [];
+    Mul64    "mul64"    SUB       0xB900_0000 [];
+    Cmp64    "cmp64"    SUB       0xBA00_0000 [];
+    Cmp      "cmp"      SUB       0xBB00_0000 [];
+    Beq      "beq"      FLAG_IF   0xBC00_0000 [FLAG_TARGET];
+    Bne      "bne"      FLAG_IF   0xBC10_0000 [FLAG_TARGET];
+    Bhs      "bhs"      FLAG_IF   0xBC20_0000 [FLAG_TARGET];
+    Blo      "blo"      FLAG_IF   0xBC30_0000 [FLAG_TARGET];
+    Bmi      "bmi"      FLAG_IF   0xBC40_0000 [FLAG_TARGET];
+    Bpl      "bpl"      FLAG_IF   0xBC50_0000 [FLAG_TARGET];
+    Bvs      "bvs"      FLAG_IF   0xBC60_0000 [FLAG_TARGET];
+    Bvc      "bvc"      FLAG_IF   0xBC70_0000 [FLAG_TARGET];
+    Bhi      "bhi"      FLAG_IF   0xBC80_0000 [FLAG_TARGET];
+    Bls      "bls"      FLAG_IF   0xBC90_0000 [FLAG_TARGET];
+    Bge      "bge"      FLAG_IF   0xBCA0_0000 [FLAG_TARGET];
+    Blt      "blt"      FLAG_IF   0xBCB0_0000 [FLAG_TARGET];
+    Bgt      "bgt"      FLAG_IF   0xBCC0_0000 [FLAG_TARGET];
+    Ble      "ble"      FLAG_IF   0xBCD0_0000 [FLAG_TARGET];
+    Dup      "dup"      GROUP     0xC000_0000 [OFFSET];
+    Print    "print"    PRINT     0xD000_0000 [OFFSET];
+    Printh   "printh"   PRINT     0xD000_0001 [OFFSET];
+    Printb   "printb"   PRINT     0xD000_0002 [OFFSET];
+    Printo   "printo"   PRINT     0xD000_0003 [OFFSET];
+    Dump     "dump"     GROUP     0xE000_0000 [];
+    Push     "push"     GROUP     0xF000_0000 [VALUE];
+}
+
+impl Op {
+    pub fn spec(self) -> &'static InstrSpec {
+        &INSTRUCTIONS[self as usize]
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        self.spec().mnemonic
+    }
+
+    // The canonical word for this instruction, with every operand zero;
+    // decode(op.encode()) is always Some(op)
+    pub fn encode(self) -> u32 {
+        self.spec().bits
+    }
+
+    // The word for this instruction with operands in table order (offsets in
+    // bytes); None if the count is wrong or a value doesn't fit its field.
+    // Decoding the word gives the op back and spec().operands gives the values.
+    pub fn encode_with(self, operands: &[i32]) -> Option<u32> {
+        self.spec().encode(operands)
+    }
+}
+
+// An instruction with its operands, in table order with offsets in bytes as
+// the VM applies them. Only values that fit their fields get in, so every
+// Instruction has a word, and decoding that word gives it back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    op: Op,
+    operands: [i32; 2], // Past the op's operand count these are 0
+}
+
+impl Instruction {
+    // None if the operand count is wrong or a value doesn't fit its field
+    pub fn new(op: Op, operands: &[i32]) -> Option<Instruction> {
+        op.encode_with(operands)?;
+        let mut values = [0; 2];
+        values[..operands.len()].copy_from_slice(operands);
+        Some(Instruction { op, operands: values })
+    }
+
+    // The instruction a word encodes, if any. Bits outside the op's fields
+    // are dropped, so encoding it again gives the canonical word.
+    pub fn decode(word: u32) -> Option<Instruction> {
+        let spec = lookup(word)?;
+        Some(Instruction { op: spec.op, operands: spec.operands(word) })
+    }
+
+    pub fn op(&self) -> Op {
+        self.op
+    }
+
+    pub fn operands(&self) -> &[i32] {
+        &self.operands[..self.op.spec().operands.len()]
+    }
+
+    pub fn encode(&self) -> u32 {
+        self.op.encode_with(self.operands()).expect("operands checked when the instruction was made")
+    }
+
+    // Like Display, but for the instruction at addr: branches show the
+    // address they go to (`goto 0x0024`)
+    pub fn display_at(&self, addr: usize) -> String {
+        let spec = self.op.spec();
+        format!("{}{}", spec.mnemonic, spec.format_operands_at(self.encode(), addr))
+    }
+
+    // Like display_at, with branch targets named from a program's symbols
+    pub fn display_named(&self, addr: usize, symbols: &Symbols) -> String {
+        let spec = self.op.spec();
+        format!("{}{}", spec.mnemonic, spec.format_operands_named(self.encode(), addr, symbols))
+    }
+}
+
+// `mnemonic operand...`, in the form assemble reads back, with branch
+// offsets signed (`push 42`, `goto +16`, `ifeq -8`)
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let spec = self.op.spec();
+        write!(f, "{}{}", spec.mnemonic, spec.format_operands(self.encode()))
+    }
+}
+
+// Parse one line of assembly as assemble does (`push 0x10`, `swap 4 0`),
+// with the same messages for what's wrong with it
+impl FromStr for Instruction {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Instruction, String> {
+        let word = assemble(text)?;
+        Ok(Instruction::decode(word).expect("assemble only gives defined instructions"))
+    }
+}
+
+// Parse a mnemonic; the operands are encoded separately (see assemble)
+impl FromStr for Op {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Op, String> {
+        if let Some(spec) = find(text) {
+            return Ok(spec.op);
+        }
+        let lower = text.to_ascii_lowercase();
+        let closest = INSTRUCTIONS
+            .iter()
+            .map(|spec| (edit_distance(&lower, spec.mnemonic), spec.mnemonic))
+            .min()
+            .filter(|&(distance, _)| distance <= 2);
+        match closest {
+            Some((_, mnemonic)) => Err(format!("unknown mnemonic {:?} (did you mean {:?}?)", text, mnemonic)),
+            None => Err(format!("unknown mnemonic {:?}", text)),
+        }
+    }
+}
+
+// Levenshtein distance, for suggesting a mnemonic after a typo
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diagonal + usize::from(ca != cb)).min(above + 1).min(row[j] + 1);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.mnemonic())
+    }
+}
+
+// A program file as load_file reads it, starting at address 0: the magic
+// bytes and header, then each word little-endian
+pub fn encode_program(words: &[u32]) -> Vec<u8> {
+    let code: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    join_program(&Sections::default(), &code)
+}
+
+// The words of a program file, skipping any symbol section; a short last
+// word is padded with zeros, as the VM reads it
+pub fn decode_program(bytes: &[u8]) -> Result<Vec<u32>, String> {
+    let (_, code) = split_program(bytes)?;
+    Ok(code
+        .chunks(4)
+        .map(|chunk| {
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from_le_bytes(word)
+        })
+        .collect())
+}
+
+// Find the table entry for a word, if any
+pub fn lookup(word: u32) -> Option<&'static InstrSpec> {
+    decode(word).map(Op::spec)
+}
+
+// Render a word as `mnemonic operand...`, in the form assemble reads back.
+// Words that decode to nothing are shown as data.
+pub fn disassemble(word: u32) -> String {
+    match Instruction::decode(word) {
+        Some(instruction) => instruction.to_string(),
+        None => format!(".word 0x{:08x}", word),
+    }
+}
+
+// Like disassemble, for the word at addr: branches show their target address
+pub fn disassemble_at(word: u32, addr: usize) -> String {
+    match Instruction::decode(word) {
+        Some(instruction) => instruction.display_at(addr),
+        None => format!(".word 0x{:08x}", word),
+    }
+}
+
+// Like disassemble_at, with branch targets named from a program's symbols
+pub fn disassemble_named(word: u32, addr: usize, symbols: &Symbols) -> String {
+    match Instruction::decode(word) {
+        Some(instruction) => instruction.display_named(addr, symbols),
+        None => format!(".word 0x{:08x}", word),
+    }
+}
+
+// The table entry for a mnemonic
+pub fn find(mnemonic: &str) -> Option<&'static InstrSpec> {
+    INSTRUCTIONS.iter().find(|spec| spec.mnemonic == mnemonic)
+}
+
+// Parse an assembly number: decimal or 0x hex, optionally signed
+pub fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i64>().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+// Encode one line of assembly such as `push -3` or `swap 4 0`. Operands are
+// decimal or 0x hex, with offsets in bytes as disassemble prints them.
+pub fn assemble(line: &str) -> Result<u32, String> {
+    let mut parts = line.split_whitespace();
+    let op: Op = parts.next().ok_or("empty instruction")?.parse()?;
+    let values = parts
+        .map(|text| parse_number(text).ok_or_else(|| format!("invalid operand {:?}", text)))
+        .collect::<Result<Vec<i64>, String>>()?;
+    op.spec().encode_checked(&values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Values spread across a field's whole range: both ends, the values
+    // either side of 0 and evenly spaced ones between, all multiples of scale
+    fn samples(field: &Field) -> Vec<i32> {
+        let (low, high) = field.range();
+        let step = field.scale as i64;
+        let stride = ((high - low) / 64 / step).max(1) * step;
+        let mut values: Vec<i64> = (low..=high).step_by(stride as usize).collect();
+        values.extend([low, high, low + step, high - step, 0, step]);
+        if field.signed {
+            values.extend([-step, -2 * step]);
+        }
+        values.into_iter().map(|value| value as i32).collect()
+    }
+
+    // Every combination of samples for the op's operands
+    fn instructions(spec: &InstrSpec) -> Vec<Instruction> {
+        let mut operands: Vec<Vec<i32>> = vec![Vec::new()];
+        for field in spec.operands {
+            operands = operands
+                .iter()
+                .flat_map(|values| samples(field).into_iter().map(move |value| [values.as_slice(), &[value]].concat()))
+                .collect();
+        }
+        operands
+            .iter()
+            .map(|values| Instruction::new(spec.op, values).unwrap_or_else(|| panic!("{} {:?}", spec.mnemonic, values)))
+            .collect()
+    }
+
+    #[test]
+    fn instructions_round_trip_across_operand_ranges() {
+        for spec in INSTRUCTIONS {
+            for instruction in instructions(spec) {
+                let word = instruction.encode();
+                assert_eq!(Instruction::decode(word), Some(instruction), "{:08x}", word);
+                assert_eq!(decode(word), Some(spec.op), "{:08x}", word);
+                assert_eq!(assemble(&disassemble(word)), Ok(word), "{}", disassemble(word));
+                assert_eq!(instruction.to_string().parse(), Ok(instruction), "{}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn instructions_parse_with_their_operands() {
+        let parsed = |text: &str| text.parse::<Instruction>();
+        assert_eq!(parsed("push 0x10"), Ok(Instruction::new(Op::Push, &[16]).unwrap()));
+        assert_eq!(parsed("  goto   -8 "), Ok(Instruction::new(Op::Goto, &[-8]).unwrap()));
+        assert_eq!(parsed("swap +4 0"), Ok(Instruction::new(Op::Swap, &[4, 0]).unwrap()));
+        assert_eq!(parsed("pus 3"), Err("unknown mnemonic \"pus\" (did you mean \"push\"?)".to_string()));
+        assert_eq!(parsed("frobnicate"), Err("unknown mnemonic \"frobnicate\"".to_string()));
+        assert_eq!(parsed(""), Err("empty instruction".to_string()));
+        assert_eq!(parsed("push"), Err("push takes 1 operand(s), got 0".to_string()));
+        assert_eq!(parsed("push ten"), Err("invalid operand \"ten\"".to_string()));
+        assert!(parsed("goto 6").unwrap_err().contains("not a multiple of 4"));
+        assert!(parsed("push 0x8000000").unwrap_err().contains("out of range"));
+    }
+
+    #[test]
+    fn instructions_display_with_their_operands() {
+        let show = |op: Op, operands: &[i32]| Instruction::new(op, operands).unwrap();
+        assert_eq!(show(Op::Push, &[42]).to_string(), "push 42");
+        assert_eq!(show(Op::Goto, &[16]).to_string(), "goto +16");
+        assert_eq!(show(Op::Ifeq, &[-8]).to_string(), "ifeq -8");
+        assert_eq!(show(Op::Swap, &[4, 0]).to_string(), "swap 4 0");
+        assert_eq!(show(Op::Add, &[]).to_string(), "add");
+        assert_eq!(show(Op::Goto, &[0x14]).display_at(0x10), "goto 0x0024");
+        assert_eq!(show(Op::Ifeq, &[-8]).display_at(0x10), "ifeq 0x0008");
+        assert_eq!(show(Op::Push, &[42]).display_at(0x10), "push 42");
+
+        let mut symbols = Symbols::new();
+        symbols.insert("loop", 0x08);
+        assert_eq!(show(Op::Ifeq, &[-8]).display_named(0x10, &symbols), "ifeq loop");
+        assert_eq!(show(Op::Goto, &[4]).display_named(0x10, &symbols), "goto 0x0014");
+    }
+
+    #[test]
+    fn negative_branch_offsets_round_trip() {
+        for spec in INSTRUCTIONS.iter().filter(|spec| spec.operands.iter().any(|field| field.branch)) {
+            let (low, _) = spec.operands[0].range();
+            for offset in [-4, -8, -64, low as i32] {
+                let instruction = Instruction::new(spec.op, &[offset]).unwrap();
+                let decoded = Instruction::decode(instruction.encode()).unwrap();
+                assert_eq!(decoded.operands(), &[offset], "{}", spec.mnemonic);
+            }
+        }
+    }
+
+    #[test]
+    fn decoding_drops_bits_outside_the_fields() {
+        // exit keeps its number in bits 11:0; bits 23:12 belong to no field
+        let instruction = Instruction::decode(0x0012_3007).unwrap();
+        assert_eq!((instruction.op(), instruction.operands()), (Op::Exit, &[7][..]));
+        assert_eq!(instruction.encode(), 0x0000_0007);
+    }
+
+    #[test]
+    fn out_of_range_operands_are_rejected() {
+        for spec in INSTRUCTIONS {
+            for (index, field) in spec.operands.iter().enumerate() {
+                let (low, high) = field.range();
+                let mut bad = vec![low - field.scale as i64, high + field.scale as i64];
+                if field.scale > 1 {
+                    bad.push(1);
+                }
+                for value in bad {
+                    let mut values = [0i64; 2];
+                    values[index] = value;
+                    let values = &values[..spec.operands.len()];
+                    assert!(spec.encode_checked(values).is_err(), "{} {:?}", spec.mnemonic, values);
+                    if let Ok(value) = i32::try_from(value) {
+                        let mut narrow = [0i32; 2];
+                        narrow[index] = value;
+                        assert_eq!(Instruction::new(spec.op, &narrow[..values.len()]), None, "{} {:?}", spec.mnemonic, values);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_operand_counts_are_rejected() {
+        for spec in INSTRUCTIONS {
+            let values = vec![0; spec.operands.len() + 1];
+            assert_eq!(Instruction::new(spec.op, &values), None, "{}", spec.mnemonic);
+            if !spec.operands.is_empty() {
+                assert_eq!(Instruction::new(spec.op, &[]), None, "{}", spec.mnemonic);
+            }
+        }
+    }
+
+    #[test]
+    fn programs_round_trip() {
+        let words: Vec<u32> = INSTRUCTIONS.iter().flat_map(instructions).map(|i| i.encode()).collect();
+        assert_eq!(decode_program(&encode_program(&words)), Ok(words));
+        assert_eq!(decode_program(&encode_program(&[])), Ok(Vec::new()));
+    }
+}
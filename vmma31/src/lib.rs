@@ -0,0 +1,1208 @@
+// VMMA31 interpreter as a library. The vmma31 binary is a thin CLI over
+// this crate; other programs can embed the VM directly: load bytecode from a
+// file or byte slice, configure it, run it (to completion, for a number of
+// steps or until a deadline) and inspect the final stack and memory.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "regex")]
+use regex::bytes::Regex;
+#[cfg(feature = "manifest")]
+use sha2::{Digest, Sha256};
+
+use conventions::CallFrame;
+use guest::{Process, ROOT_PID};
+use heap::Heap;
+use output::Output;
+use predecode::Decoded;
+use trace::Trace;
+
+pub use isa::{assemble, decode, decode_program, disassemble, disassemble_at, disassemble_named, encode_program, Field, InstrSpec, Instruction, Op, INSTRUCTIONS};
+pub use conventions::{lint_calls, CallViolation};
+pub use coredump::CoreDump;
+pub use error::{VmError, FAULT_EXIT_CODE};
+pub use hexfile::InputFormat;
+pub use output::FlushPolicy;
+pub use program::{compress_program, join_program, split_program, Sections, SourceLines, Symbols};
+pub use snapshot::VmSnapshot;
+pub use telemetry::Telemetry;
+pub use trace::TraceFormat;
+pub use verify::{verify, Verdict};
+pub use vm_io::{StreamIo, VmIo};
+
+pub mod asm;
+mod ascii;
+mod backtrace;
+#[cfg(feature = "bignum")]
+mod bignum;
+mod capabilities;
+pub mod cfg;
+mod codec;
+pub mod compile;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+mod conventions;
+mod coredump;
+mod counters;
+#[cfg(feature = "dap")]
+pub mod dap;
+pub mod debugger;
+mod error;
+mod expr;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod fixed;
+mod flags;
+mod isa;
+pub mod link;
+pub mod manifest;
+mod math;
+#[cfg(feature = "regex")]
+mod matching;
+mod memops;
+pub mod opt;
+mod output;
+#[cfg(feature = "playground")]
+pub mod playground;
+mod predecode;
+mod preprocess;
+mod program;
+mod shadow;
+mod snapshot;
+mod guest;
+mod heap;
+mod hexfile;
+mod zlib;
+mod sort;
+mod telemetry;
+pub mod testing;
+mod trace;
+pub mod verify;
+mod vm_io;
+pub mod wasm;
+
+pub const DEFAULT_RAM_SIZE: usize = 4096;
+pub const MAX_RAM_SIZE: usize = 1 << 30; // SP-relative offsets are computed with i32 arithmetic
+pub const MAGIC: [u8; 4] = [0xde, 0xad, 0xbe, 0xef];
+const DEADLINE_CHECK_INTERVAL: usize = 1024; // Instructions between clock reads in run_until
+
+// The contents of a program file, or everything on stdin when filename is "-"
+pub fn read_program(filename: &str) -> Result<Vec<u8>, String> {
+    if filename == "-" {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes).map_err(|e| format!("Failed to read program from stdin: {}", e))?;
+        return Ok(bytes);
+    }
+    fs::read(filename).map_err(|e| format!("Failed to open file: {}", e))
+}
+
+// Why a bounded run handed control back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    Exited(i32), // The program finished with this exit code
+    Resumable,   // The deadline passed first; call again to continue
+}
+
+// Result of run_steps: how many instructions ran and why the batch ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepBatchOutcome {
+    pub steps: usize,
+    pub stop: StepStop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepStop {
+    Exited(i32), // The program finished with this exit code
+    StepLimit,   // The requested number of steps ran; the program can be resumed
+}
+
+// Result of run_with_fuel: how the run ended and how much fuel was left
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuelOutcome {
+    pub stop: FuelStop,
+    pub fuel_left: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FuelStop {
+    Exited(i32),      // The program finished with this exit code
+    Trapped(VmError), // A fault stopped the program
+    OutOfFuel,        // Every unit of fuel was spent; the program can be resumed
+}
+
+pub struct VM {
+    memory: Vec<u8>,
+    pc: usize,     // Program counter
+    sp: usize,     // Stack pointer
+    fp: usize,     // Frame pointer: where enter saved the previous one
+    flags: u32,    // N, Z, C and V as cmp last set them
+    exited: bool,  // Exit flag
+    exit_code: i32, // Exit code
+    code_size: usize, // Size of the loaded bytecode
+    sections: Sections, // Symbols and source lines from the program file
+    decoded: Vec<Option<Decoded>>, // Decoded code words; None where a write means decoding again
+    #[cfg(feature = "regex")]
+    allow_regex: bool, // Capability flag for the regex syscalls
+    #[cfg(feature = "regex")]
+    regexes: Vec<Regex>, // Patterns registered by the host, indexed by id
+    check_calls: bool, // Report calling-convention violations
+    dump_backtrace: bool, // dump also prints the call chain
+    call_frames: Vec<CallFrame>, // Active calls, tracked while check_calls is set
+    call_violations: Vec<CallViolation>, // What check_calls has found, for the host to report
+    shadow_stack_enabled: bool, // Check every return against the shadow stack
+    shadow_stack: Vec<u32>,     // Return addresses of active calls
+    return_stack: bool,         // shadow_stack is the only record of them; the data stack has none
+    strict: bool,               // Fault on memory accesses outside RAM instead of ignoring them
+    trap_overflow: bool,        // Fault when add, sub, mul, qmul or qdiv overflows instead of wrapping
+    globals: (usize, usize),    // Region gload and gstore index: address and length in words
+    heap: Heap,                 // Blocks handed out by alloc, between the program and the stack
+    pid: u32,                 // Id of the running guest process
+    parent_pid: u32,          // Id of its parent (0 for the root)
+    next_pid: u32,            // Id handed out by the next fork
+    processes: Vec<Process>,  // Processes that aren't running, oldest first
+    switched: bool,           // A context switch happened during this step
+    telemetry: Telemetry,     // High-water marks for --stats
+    instructions_executed: u64, // Instructions run so far, across all processes
+    branches_taken: u64,      // Instructions that moved the PC somewhere other than the next word
+    #[cfg(feature = "manifest")]
+    input_digest: Sha256,     // Hash of everything read by input and stinput
+    input_bytes: u64,         // Number of bytes read by input and stinput
+    output: Output,           // Buffered guest output, and the VmIo it goes to
+    captured_output: Option<String>, // Collects output instead of printing when set
+    trace: Option<Trace>,     // Where --trace lines go, when tracing
+    fault: Option<VmError>,   // Set by the first fault; the run stops there
+    scripted_input: Option<VecDeque<String>>, // Lines to read instead of stdin when set
+    input_log: Option<Box<dyn Write>>, // Receives a copy of every line read, when recording
+}
+
+impl VM {
+    // A VM with ram_size bytes of memory, which should be a multiple of 4 no
+    // larger than MAX_RAM_SIZE. The stack starts at the top and grows down.
+    pub fn new(ram_size: usize) -> VM {
+        VM {
+            memory: vec![0u8; ram_size],
+            pc: 0,
+            sp: ram_size, // Stack starts at the bottom
+            fp: ram_size,
+            flags: 0,
+            exited: false,
+            exit_code: 0,
+            code_size: 0, // Initialize to 0, will be set in load_file
+            sections: Sections::default(),
+            decoded: Vec::new(),
+            #[cfg(feature = "regex")]
+            allow_regex: false,
+            #[cfg(feature = "regex")]
+            regexes: Vec::new(),
+            check_calls: false,
+            dump_backtrace: false,
+            call_frames: Vec::new(),
+            call_violations: Vec::new(),
+            shadow_stack_enabled: true,
+            shadow_stack: Vec::new(),
+            return_stack: false,
+            strict: false,
+            trap_overflow: false,
+            globals: (0, 0),
+            heap: Heap::default(),
+            pid: ROOT_PID,
+            parent_pid: 0,
+            next_pid: ROOT_PID + 1,
+            processes: Vec::new(),
+            switched: false,
+            telemetry: Telemetry::new(ram_size),
+            instructions_executed: 0,
+            branches_taken: 0,
+            #[cfg(feature = "manifest")]
+            input_digest: Sha256::new(),
+            input_bytes: 0,
+            output: Output::new(FlushPolicy::Line),
+            captured_output: None,
+            trace: None,
+            fault: None,
+            scripted_input: None,
+            input_log: None,
+        }
+    }
+
+    // Create a VM with DEFAULT_RAM_SIZE bytes of memory and a program already
+    // loaded from magic-prefixed bytecode
+    pub fn from_bytes(bytes: &[u8]) -> Result<VM, String> {
+        let mut vm = VM::new(DEFAULT_RAM_SIZE);
+        vm.load_bytes(bytes)?;
+        Ok(vm)
+    }
+
+    // Load bytecode file into memory, excluding magic bytes. Intel HEX and
+    // hex word files are recognized and converted, and "-" reads stdin.
+    pub fn load_file(&mut self, filename: &str) -> Result<(), String> {
+        self.load_file_as(filename, InputFormat::Auto)
+    }
+
+    // Like load_file, with the file's format given
+    pub fn load_file_as(&mut self, filename: &str, format: InputFormat) -> Result<(), String> {
+        self.load_bytes_as(&read_program(filename)?, format)
+    }
+
+    // Load the contents of a program file in the given format
+    pub fn load_bytes_as(&mut self, bytes: &[u8], format: InputFormat) -> Result<(), String> {
+        self.load_bytes(&hexfile::decode(bytes, format)?)
+    }
+
+    // Load magic-prefixed bytecode, as found in a program file, keeping its
+    // symbols and source lines for the tracer and debugger. Data and bss
+    // blocks are placed at their addresses, and the PC at the entry point.
+    pub fn load_bytes(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let (sections, code) = split_program(bytes)?;
+        sections.check_entry(code.len())?;
+        sections.check_blocks(code.len(), self.memory.len())?;
+        self.load_code(&code)?;
+        for (addr, data) in &sections.data {
+            self.memory[*addr..*addr + data.len()].copy_from_slice(data);
+        }
+        for &(addr, len) in &sections.bss {
+            self.memory[addr..addr + len].fill(0);
+        }
+        if let Some((addr, count)) = sections.globals {
+            self.memory[addr..addr + count * 4].fill(0);
+            self.globals = (addr, count);
+        }
+        self.return_stack |= sections.return_stack;
+        self.heap = Heap::new(sections.heap_start(code.len()));
+        self.pc = sections.entry;
+        self.sections = sections;
+        Ok(())
+    }
+
+    // Load bytecode that is already in memory (without the magic bytes)
+    pub fn load_code(&mut self, code: &[u8]) -> Result<(), String> {
+        if code.len() > self.memory.len() {
+            return Err("Program too large for memory".to_string());
+        }
+        self.sections = Sections::default();
+        self.globals = (0, 0);
+        self.heap = Heap::new(code.len().next_multiple_of(4));
+        self.memory[..code.len()].copy_from_slice(code);
+        self.code_size = code.len();
+        self.predecode();
+        Ok(())
+    }
+
+    // Run the virtual machine; returns the exit code, or the fault that stopped it
+    pub fn run(&mut self) -> Result<i32, VmError> {
+        while self.step()? {}
+        Ok(self.exit_code)
+    }
+
+    // Run until the program finishes or the deadline passes. The clock is only
+    // read every DEADLINE_CHECK_INTERVAL instructions, so the deadline may be
+    // overshot by that many steps.
+    pub fn run_until(&mut self, deadline: Instant) -> Result<RunStatus, VmError> {
+        loop {
+            if let StepStop::Exited(code) = self.run_steps(DEADLINE_CHECK_INTERVAL)?.stop {
+                return Ok(RunStatus::Exited(code));
+            }
+            if Instant::now() >= deadline {
+                return Ok(RunStatus::Resumable);
+            }
+        }
+    }
+
+    pub fn run_for(&mut self, duration: Duration) -> Result<RunStatus, VmError> {
+        self.run_until(Instant::now() + duration)
+    }
+
+    // Execute at most max_steps instructions
+    pub fn run_steps(&mut self, max_steps: usize) -> Result<StepBatchOutcome, VmError> {
+        for steps in 0..max_steps {
+            if !self.step()? {
+                return Ok(StepBatchOutcome { steps, stop: StepStop::Exited(self.exit_code) });
+            }
+        }
+        let stop = if self.finished() {
+            StepStop::Exited(self.exit_code)
+        } else {
+            StepStop::StepLimit
+        };
+        Ok(StepBatchOutcome { steps: max_steps, stop })
+    }
+
+    // Run with a budget of `fuel` instructions, one unit per instruction
+    // executed. Unlike the deadline-based runs the stopping point depends only
+    // on the program and its input, so a runaway loop is cut off at the same
+    // instruction every time.
+    pub fn run_with_fuel(&mut self, fuel: u64) -> FuelOutcome {
+        let start = self.instructions_executed;
+        let stop = loop {
+            if self.instructions_executed - start >= fuel {
+                break if self.finished() { FuelStop::Exited(self.exit_code) } else { FuelStop::OutOfFuel };
+            }
+            match self.step() {
+                Ok(true) => {}
+                Ok(false) => break FuelStop::Exited(self.exit_code),
+                Err(error) => break FuelStop::Trapped(error),
+            }
+        };
+        FuelOutcome { stop, fuel_left: fuel - (self.instructions_executed - start) }
+    }
+
+    // High-water marks gathered so far
+    pub fn telemetry(&self) -> Telemetry {
+        self.telemetry
+    }
+
+    // The program has exited or run off the end of the code
+    pub fn finished(&self) -> bool {
+        self.exited || self.pc >= self.code_size
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    pub fn fp(&self) -> usize {
+        self.fp
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
+
+    // Instructions run so far, across all guest processes
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions_executed
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // The loaded program, without the magic bytes
+    pub fn code(&self) -> &[u8] {
+        &self.memory[..self.code_size]
+    }
+
+    // Where the loaded program file says execution starts
+    pub fn entry(&self) -> usize {
+        self.sections.entry
+    }
+
+    // Names for code addresses, from the loaded program file
+    pub fn symbols(&self) -> &Symbols {
+        &self.sections.symbols
+    }
+
+    // Where each instruction came from, from the program file's debug section
+    pub fn source_lines(&self) -> &SourceLines {
+        &self.sections.lines
+    }
+
+    // The loaded program file's data and bss blocks, as kind, address and length
+    pub fn blocks(&self) -> impl Iterator<Item = (&'static str, usize, usize)> + '_ {
+        self.sections.blocks()
+    }
+
+    // Words on the stack, top first
+    pub fn stack(&self) -> Vec<u32> {
+        (self.sp..self.memory.len()).step_by(4).map(|addr| self.read_u32(addr)).collect()
+    }
+
+    // Output is printed unless capture_output has been called
+    pub fn capture_output(&mut self) {
+        self.captured_output = Some(String::new());
+    }
+
+    pub fn captured_output(&self) -> Option<&str> {
+        self.captured_output.as_deref()
+    }
+
+    // Feed input and stinput from these lines instead of stdin
+    pub fn set_input<I: IntoIterator<Item = String>>(&mut self, lines: I) {
+        self.scripted_input = Some(lines.into_iter().map(|line| line + "\n").collect());
+    }
+
+    // Read input and write output through io instead of stdin and stdout.
+    // Lines given to set_input and capture_output still take precedence.
+    pub fn set_io(&mut self, io: Box<dyn VmIo>) {
+        self.output.set_io(io);
+    }
+
+    // Copy every line input and stinput read to out, in the form set_input
+    // takes back, so an interactive session can be replayed exactly
+    pub fn record_input(&mut self, out: Box<dyn Write>) {
+        self.input_log = Some(out);
+    }
+
+    #[cfg(feature = "regex")]
+    pub fn set_allow_regex(&mut self, allow: bool) {
+        self.allow_regex = allow;
+    }
+
+    pub fn set_check_calls(&mut self, check: bool) {
+        self.check_calls = check;
+    }
+
+    // Have dump print the call chain after the stack, as format_backtrace does
+    pub fn set_dump_backtrace(&mut self, enabled: bool) {
+        self.dump_backtrace = enabled;
+    }
+
+    pub fn set_shadow_stack(&mut self, enabled: bool) {
+        self.shadow_stack_enabled = enabled;
+    }
+
+    // Keep return addresses on a stack of their own, out of the guest's
+    // reach, instead of on the data stack: call pushes nothing there and
+    // return pops nothing but its offset. Programs whose file has the
+    // RETURN_STACK flag get this when loaded. It holds as many return
+    // addresses as memory has words; a deeper call is a stack overflow.
+    pub fn set_return_stack(&mut self, enabled: bool) {
+        self.return_stack = enabled;
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    // Make add, sub, mul, qmul and qdiv fault on signed overflow instead of
+    // wrapping. Programs can also turn this on and off themselves with trapv.
+    pub fn set_trap_overflow(&mut self, trap: bool) {
+        self.trap_overflow = trap;
+    }
+
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.output.set_policy(policy);
+    }
+
+    // Write out any buffered guest output and trace lines
+    pub fn flush_output(&mut self) {
+        self.output.flush();
+        self.flush_trace();
+    }
+
+    // Execute one instruction; returns false once the program has finished
+    fn step(&mut self) -> Result<bool, VmError> {
+        if let Some(fault) = &self.fault {
+            return Err(fault.clone());
+        }
+        // A forked process that runs off the end of the code exits with 0
+        if self.pc >= self.code_size && self.pid != ROOT_PID {
+            self.switched = false;
+            self.exit_process(0);
+            return Ok(true);
+        }
+        if self.finished() {
+            return Ok(false);
+        }
+        let instruction = self.read_u32(self.pc);
+        let pc_before = self.pc;
+        let sp_before = self.sp;
+        self.instructions_executed += 1;
+        self.switched = false;
+        self.execute_instruction(instruction);
+        if self.trace.is_some() {
+            self.trace_step(pc_before, instruction, sp_before);
+        }
+        if let Some(fault) = &self.fault {
+            return Err(fault.clone());
+        }
+        if self.switched {
+            return Ok(true); // Another process was resumed at its own PC
+        }
+        if self.check_calls {
+            self.check_frame(pc_before);
+        }
+        
+        // Only increment PC if it wasn't modified by the instruction
+        if self.pc == pc_before && !self.exited {
+            self.pc += 4; // Instructions are 4 bytes
+        } else if self.pc != pc_before {
+            self.branches_taken += 1;
+        }
+        Ok(true)
+    }
+
+    // Read a 4-byte little-endian u32 from memory at the given address
+    fn read_u32(&self, addr: usize) -> u32 {
+        if addr + 3 >= self.memory.len() {
+            return 0; // Return 0 if out of bounds
+        }
+        u32::from_le_bytes(self.memory[addr..addr + 4].try_into().unwrap())
+    }
+
+    // Write a 4-byte u32 to memory at the given address in little-endian
+    fn write_u32(&mut self, addr: usize, value: u32) {
+        if addr + 3 < self.memory.len() {
+            self.memory[addr..addr + 4].copy_from_slice(&value.to_le_bytes());
+            self.invalidate_code(addr, 4);
+            self.trace_write(addr, &value.to_le_bytes());
+        }
+    }
+
+    // Whether the len bytes at addr are inside memory. In strict mode an
+    // access that isn't raises OutOfBounds; otherwise the caller ignores it.
+    fn check_bounds(&mut self, addr: usize, len: usize) -> bool {
+        let in_bounds = addr.checked_add(len).is_some_and(|end| end <= self.memory.len());
+        if !in_bounds && self.strict {
+            self.raise(VmError::OutOfBounds { pc: self.pc, sp: self.sp, addr });
+        }
+        in_bounds
+    }
+
+    // Push a value onto the stack
+    fn push(&mut self, value: u32) {
+        if self.sp >= self.heap.top() + 4 { // Prevent underflow, and running into the heap
+            self.sp -=
+             4;
+            self.write_u32(self.sp, value);
+            self.telemetry.lowest_sp = self.telemetry.lowest_sp.min(self.sp);
+        } else {
+            self.raise(VmError::StackOverflow { pc: self.pc });
+        }
+    }
+
+    // Pop a value from the stack
+    fn pop(&mut self) -> u32 {
+        if self.sp + 4 <= self.memory.len() { // Prevent overflow
+            let value = self.read_u32(self.sp);
+            self.sp += 4;
+            value
+        } else {
+            self.raise(VmError::StackUnderflow { pc: self.pc });
+            0
+        }
+    }
+
+    // Peek a value from the stack at sp + offset
+    fn peek(&mut self, offset: i32) -> u32 {
+        let addr = (self.sp as i32 + offset) as usize;
+        if self.check_bounds(addr, 4) {
+            self.read_u32(addr)
+        } else {
+            0
+        }
+    }
+
+    // Execute a single instruction
+    fn execute_instruction(&mut self, instruction: u32) {
+        match self.decoded(instruction) {
+            Some(Decoded { handler, operands: [a, b] }) => handler(self, a, b),
+            None => self.raise(VmError::InvalidInstruction { pc: self.pc, word: instruction }),
+        }
+    }
+
+    fn exec_exit(&mut self, code: i32) {
+        if self.pid == ROOT_PID {
+            self.exit_code = code;
+            self.exited = true;
+        } else {
+            self.exit_process(code);
+        }
+    }
+
+    fn exec_swap(&mut self, from: i32, to: i32) {
+        let addr1 = (self.sp as i32 + from) as usize;
+        let addr2 = (self.sp as i32 + to) as usize;
+        if self.check_bounds(addr1, 4) && self.check_bounds(addr2, 4) {
+            let val1 = self.read_u32(addr1);
+            let val2 = self.read_u32(addr2);
+            self.write_u32(addr1, val2);
+            self.write_u32(addr2, val1);
+        }
+    }
+
+    fn exec_input(&mut self) {
+        let input = self.read_input_line();
+        let input = input.trim();
+        
+        let value = if input.starts_with("0x") || input.starts_with("0X") {
+            // Parse hex
+            i32::from_str_radix(&input[2..], 16)
+        } else if input.starts_with("0b") || input.starts_with("0B") {
+            // Parse binary
+            i32::from_str_radix(&input[2..], 2)
+        } else {
+            // Parse decimal
+            input.parse::<i32>()
+        }.unwrap_or(0);
+        
+        self.push(value as u32);
+    }
+
+    fn exec_stinput(&mut self, max_chars: u32) {
+        let input = self.read_input_line();
+        let trimmed = input.trim();
+        
+        if trimmed.is_empty() {
+            self.push(0);
+        } else {
+            let bytes = trimmed.as_bytes();
+            let len = if max_chars == 0xFFFFFF { bytes.len() } else { bytes.len().min(max_chars as usize) };
+            
+            let mut chunks = Vec::new();
+            let mut start = 0;
+            while start < len {
+                let end = (start + 3).min(len);
+                let mut value = [0u8; 4];
+                value[..end - start].copy_from_slice(&bytes[start..end]);
+                value[3] = if end < len { 0x01 } else { 0x00 };
+                chunks.push(u32::from_le_bytes(value));
+                start += 3;
+            }
+            
+            // Push chunks in reverse order
+            for &chunk in chunks.iter().rev() {
+                self.push(chunk);
+            }
+        }
+    }
+
+    // Read a line for input/stinput, flushing pending output first so prompts show
+    fn read_input_line(&mut self) -> String {
+        self.flush_output(); // Show any pending prompt
+        
+        let input = match &mut self.scripted_input {
+            Some(lines) => lines.pop_front().unwrap_or_default(),
+            None => self.output.read_line(),
+        };
+        if let Some(log) = &mut self.input_log {
+            let _ = log.write_all(input.as_bytes()).and_then(|_| log.flush());
+        }
+        #[cfg(feature = "manifest")]
+        self.input_digest.update(input.as_bytes());
+        self.input_bytes += input.len() as u64;
+        input
+    }
+
+    // Host services that don't warrant their own opcode. Arguments are popped
+    // from the stack by each handler; unknown syscall numbers are ignored.
+    fn exec_syscall(&mut self, number: u32) {
+        match number {
+            #[cfg(feature = "bignum")]
+            bignum::BIGNUM_ADD..=bignum::BIGNUM_POW => self.exec_bignum(number),
+            math::MATH_SQRT..=math::MATH_POW => self.exec_math(number),
+            #[cfg(feature = "regex")]
+            matching::REGEX_MATCH | matching::REGEX_MATCH_REGISTERED => self.exec_regex(number),
+            sort::SORT_WORDS => self.exec_sort(),
+            _ => {}
+        }
+    }
+
+    fn exec_pop(&mut self, offset: usize) {
+        if self.sp + offset <= self.memory.len() {
+            self.sp += offset;
+        } else {
+            self.sp = self.memory.len();
+        }
+    }
+
+    fn exec_binary_arithmetic(&mut self, op: Op, rounding: u32) {
+        let right = self.pop() as i32;
+        let left = self.pop() as i32;
+        let result = match op {
+            Op::Add => self.narrow(left as i64 + right as i64),
+            Op::Sub => self.narrow(left as i64 - right as i64),
+            Op::Mul => self.narrow(left as i64 * right as i64),
+            // The smallest word divided by -1 wraps, as the C and wasm backends do
+            Op::Div if right != 0 => left.wrapping_div(right),
+            Op::Rem if right != 0 => left.wrapping_rem(right),
+            Op::Qdiv if right != 0 => self.narrow(fixed::div(left, right, fixed::Rounding::from_bits(rounding))),
+            Op::Div | Op::Rem | Op::Qdiv => {
+                self.raise(VmError::DivideByZero { pc: self.pc });
+                0
+            }
+            Op::And => left & right,
+            Op::Or => left | right,
+            Op::Xor => left ^ right,
+            // Shift counts are taken mod 32, as the C and wasm backends do
+            Op::Lsl => left.wrapping_shl(right as u32),
+            Op::Lsr => (left as u32).wrapping_shr(right as u32) as i32,
+            Op::Asr => left.wrapping_shr(right as u32),
+            Op::Min => left.min(right),
+            Op::Max => left.max(right),
+            Op::Mulh => ((left as i64 * right as i64) >> 32) as i32,
+            Op::Mulhu => ((left as u32 as u64 * right as u32 as u64) >> 32) as i32,
+            Op::Qmul => self.narrow(fixed::mul(left, right, fixed::Rounding::from_bits(rounding))),
+            _ => 0
+        };
+        self.push(result as u32);
+    }
+
+    // A result computed in 64 bits as a word: wrapped, or with the overflow
+    // trap on, a fault if it doesn't fit
+    fn narrow(&mut self, value: i64) -> i32 {
+        if self.trap_overflow && i32::try_from(value).is_err() {
+            self.raise(VmError::Overflow { pc: self.pc });
+        }
+        value as i32
+    }
+
+    fn exec_unary_arithmetic(&mut self, op: Op) {
+        let value = self.pop() as i32;
+        let result = match op {
+            Op::Neg => value.wrapping_neg(),
+            Op::Not => !value,
+            Op::Toupper => ascii::map_chars(value as u32, u8::to_ascii_uppercase) as i32,
+            Op::Tolower => ascii::map_chars(value as u32, u8::to_ascii_lowercase) as i32,
+            Op::Isdigit => ascii::all_chars(value as u32, u8::is_ascii_digit) as i32,
+            Op::Isalpha => ascii::all_chars(value as u32, u8::is_ascii_alphabetic) as i32,
+            Op::Popcount => value.count_ones() as i32,
+            Op::Clz => value.leading_zeros() as i32,
+            Op::Ctz => value.trailing_zeros() as i32,
+            Op::Abs => value.wrapping_abs(),
+            _ => 0
+        };
+        self.push(result as u32);
+    }
+
+    // Element-wise arithmetic on two groups of four words. The word at the
+    // lowest address of each group is lane 0, so a group is pushed lane 3 first.
+    fn exec_vector_arithmetic(&mut self, op: Op) {
+        let right = self.pop_vector();
+        let left = self.pop_vector();
+        // Fixed-width lane loops; LLVM lowers these to SIMD instructions where the target has them
+        let mut result = [0i32; 4];
+        for lane in 0..4 {
+            result[lane] = match op {
+                Op::Vadd => left[lane].wrapping_add(right[lane]),
+                Op::Vsub => left[lane].wrapping_sub(right[lane]),
+                Op::Vmul => left[lane].wrapping_mul(right[lane]),
+                _ => 0
+            };
+        }
+        for &value in result.iter().rev() {
+            self.push(value as u32);
+        }
+    }
+
+    // Pop four words as a vector, lane 0 first
+    fn pop_vector(&mut self) -> [i32; 4] {
+        let mut lanes = [0i32; 4];
+        for lane in lanes.iter_mut() {
+            *lane = self.pop() as i32;
+        }
+        lanes
+    }
+
+    // Arithmetic on 64-bit values, each two words with the low word at the
+    // lower address, so a value is pushed high word first. cmp64 compares as
+    // signed and pushes -1, 0 or 1, like memcmp.
+    fn exec_wide_arithmetic(&mut self, op: Op) {
+        let right = self.pop_wide();
+        let left = self.pop_wide();
+        let result = match op {
+            Op::Add64 => left.wrapping_add(right),
+            Op::Sub64 => left.wrapping_sub(right),
+            Op::Mul64 => left.wrapping_mul(right),
+            _ => {
+                self.push(left.cmp(&right) as i32 as u32);
+                return;
+            }
+        };
+        self.push((result >> 32) as u32);
+        self.push(result as u32);
+    }
+
+    // Pop a 64-bit value, low word first
+    fn pop_wide(&mut self) -> i64 {
+        let low = self.pop() as u64;
+        ((self.pop() as u64) << 32 | low) as i64
+    }
+
+    // Push the len bytes (4 or 1) at an absolute address, zero-extended. The
+    // address is the operand of the immediate forms, otherwise popped.
+    // Outside memory it reads 0, unless strict.
+    fn exec_load(&mut self, addr: Option<u32>, len: usize) {
+        let addr = addr.unwrap_or_else(|| self.pop()) as usize;
+        let value = match self.check_bounds(addr, len) {
+            true if len == 4 => self.read_u32(addr),
+            true => self.memory[addr] as u32,
+            false => 0,
+        };
+        self.push(value);
+    }
+
+    // Pop an address (unless given as the operand), then a value, and write
+    // the value's low len bytes there. Outside memory it's dropped, unless
+    // strict.
+    fn exec_store(&mut self, addr: Option<u32>, len: usize) {
+        let addr = addr.unwrap_or_else(|| self.pop()) as usize;
+        let value = self.pop();
+        if self.fault.is_none() && self.check_bounds(addr, len) {
+            let bytes = value.to_le_bytes();
+            self.memory[addr..addr + len].copy_from_slice(&bytes[..len]);
+            self.invalidate_code(addr, len);
+            self.trace_write(addr, &bytes[..len]);
+        }
+    }
+
+    fn exec_gload(&mut self, index: usize) {
+        let value = self.global_addr(index).map_or(0, |addr| self.read_u32(addr));
+        self.push(value);
+    }
+
+    fn exec_gstore(&mut self, index: usize) {
+        let value = self.pop();
+        if let Some(addr) = self.global_addr(index).filter(|_| self.fault.is_none()) {
+            self.write_u32(addr, value);
+        }
+    }
+
+    // The address of global index, if the globals region has it. Past the
+    // region a load reads 0 and a store is dropped, unless strict.
+    fn global_addr(&mut self, index: usize) -> Option<usize> {
+        let (base, count) = self.globals;
+        if index < count {
+            return Some(base + index * 4);
+        }
+        if self.strict {
+            self.raise(VmError::OutOfBounds { pc: self.pc, sp: self.sp, addr: base + index * 4 });
+        }
+        None
+    }
+
+    // Start a frame: push the frame pointer, point it at the saved one, and
+    // reserve words below it for locals, which keep whatever was there
+    fn exec_enter(&mut self, words: usize) {
+        self.push(self.fp as u32);
+        self.fp = self.sp;
+        if words * 4 + self.heap.top() > self.sp {
+            self.raise(VmError::StackOverflow { pc: self.pc });
+            return;
+        }
+        self.sp -= words * 4;
+        self.telemetry.lowest_sp = self.telemetry.lowest_sp.min(self.sp);
+    }
+
+    // End the frame enter started, dropping its locals and anything pushed
+    // since, and restore the caller's frame pointer
+    fn exec_leave(&mut self) {
+        self.sp = self.fp.min(self.memory.len());
+        self.fp = self.pop() as usize;
+    }
+
+    // The address of the word index words from the frame pointer: locals
+    // are -1 down to -n, the saved frame pointer 0, the return address 1
+    // and the arguments (the last pushed first) from 2 up. Outside memory
+    // lload reads 0 and lstore writes nothing, unless strict.
+    fn local_addr(&self, index: i32) -> usize {
+        (self.fp as i64 + index as i64 * 4) as usize
+    }
+
+    fn exec_lload(&mut self, index: i32) {
+        let addr = self.local_addr(index);
+        let value = if self.check_bounds(addr, 4) { self.read_u32(addr) } else { 0 };
+        self.push(value);
+    }
+
+    fn exec_lstore(&mut self, index: i32) {
+        let value = self.pop();
+        let addr = self.local_addr(index);
+        if self.fault.is_none() && self.check_bounds(addr, 4) {
+            self.write_u32(addr, value);
+        }
+    }
+
+    fn exec_stprint(&mut self, offset: i32) {
+        let mut addr = (self.sp as i32 + offset) as usize;
+        let mut text = String::new();
+        while addr < self.memory.len() {
+            let byte = self.memory[addr];
+            if byte == 0 {
+                break;
+            } else if byte != 1 { // Skip continuation byte
+                text.push(byte as char);
+            }
+            addr += 1;
+        }
+        self.check_bounds(addr, 1); // No terminator before the end of memory
+        self.write_output(&text);
+    }
+
+    fn exec_call(&mut self, target: i32) {
+        let call_pc = self.pc;
+        if self.return_stack {
+            if self.shadow_stack.len() >= self.memory.len() / 4 {
+                self.raise(VmError::StackOverflow { pc: self.pc });
+                return;
+            }
+            self.shadow_call((self.pc + 4) as u32);
+        } else {
+            self.push((self.pc + 4) as u32); // Push next instruction address
+            if self.shadow_stack_enabled {
+                self.shadow_call((self.pc + 4) as u32);
+            }
+        }
+        self.pc = target as usize;
+        if self.check_calls {
+            self.track_call(call_pc);
+        }
+    }
+
+    // return offset: first drop offset bytes from the data stack (a negative
+    // offset moves SP down instead, and one that would take SP outside
+    // memory is ignored), then go to the return address. That's popped from
+    // the data stack, or from the return stack in return stack mode. With
+    // no return address left there, return does nothing more and the
+    // program carries on with the next instruction.
+    fn exec_return(&mut self, offset: i32) {
+        let sp = self.sp as i64 + offset as i64;
+        if (0..=self.memory.len() as i64).contains(&sp) {
+            self.sp = sp as usize;
+        }
+        if self.check_calls {
+            self.check_return(self.pc);
+        }
+
+        if self.return_stack {
+            if let Some(return_addr) = self.shadow_stack.pop() {
+                self.pc = return_addr as usize;
+            }
+        } else if self.sp < self.memory.len() {
+            let return_addr = self.pop();
+            if self.shadow_stack_enabled && !self.shadow_return(self.pc, return_addr) {
+                return;
+            }
+            self.pc = return_addr as usize;
+        }
+    }
+
+    fn exec_goto(&mut self, target: i32) {
+        self.pc = target as usize;
+    }
+
+    fn exec_binary_if(&mut self, op: Op, target: i32) {
+        let right = self.peek(0) as i32;
+        let left = self.peek(4) as i32;
+        let condition_met = match op {
+            Op::Ifeq => left == right,
+            Op::Ifne => left != right,
+            Op::Iflt => left < right,
+            Op::Ifgt => left > right,
+            Op::Ifle => left <= right,
+            Op::Ifge => left >= right,
+            Op::Ifltu => (left as u32) < right as u32,
+            Op::Ifgtu => left as u32 > right as u32,
+            Op::Ifleu => left as u32 <= right as u32,
+            Op::Ifgeu => left as u32 >= right as u32,
+            _ => false
+        };
+        
+        if condition_met {
+            self.pc = target as usize;
+        }
+    }
+
+    fn exec_unary_if(&mut self, op: Op, target: i32) {
+        let value = self.peek(0) as i32;
+        let condition_met = match op {
+            Op::Ifez => value == 0,
+            Op::Ifnz => value != 0,
+            Op::Ifmi => value < 0,  // Negative
+            Op::Ifpl => value >= 0, // Positive or zero
+            _ => false
+        };
+        
+        if condition_met {
+            self.pc = target as usize;
+        }
+    }
+
+    fn exec_dup(&mut self, offset: i32) {
+        let value = self.peek(offset);
+        self.push(value);
+    }
+
+    // Print the word at the offset as printf's format says; print, printh,
+    // printb and printo are formats 0 to 3
+    fn exec_print(&mut self, offset: i32, format: u32) {
+        let text = format_word(self.peek(offset), format);
+        self.write_output(&text);
+    }
+
+    fn exec_dump(&mut self) {
+        let mut addr = self.sp;
+        while addr < self.memory.len() {
+            let value = self.read_u32(addr);
+            self.write_output(&format!("{:04x}: {:08x}\n", addr - self.sp, value));
+            addr += 4;
+        }
+        if self.dump_backtrace {
+            let backtrace = self.format_backtrace();
+            self.write_output(&backtrace);
+        }
+    }
+
+    // All guest output goes through here so it can be measured
+    fn write_output(&mut self, text: &str) {
+        self.flush_trace(); // Keep the trace in step with output on a shared terminal
+        match &mut self.captured_output {
+            Some(output) => output.push_str(text),
+            None => self.output.write(text),
+        }
+        self.telemetry.output_bytes += text.len() as u64;
+    }
+}
+
+// Text for a printed word. Format bits 2:0 pick decimal, hex, binary, octal
+// or 4 for the low byte as a character (U+0000 to U+00FF); higher values are
+// decimal. Bit 3 leaves off the newline, and bits 11:5 give a minimum width,
+// counting any sign or prefix, padded on the left with spaces, or with
+// zeros after the sign or prefix when bit 4 is set.
+fn format_word(value: u32, format: u32) -> String {
+    let (prefix, digits) = match format & 7 {
+        1 => ("0x", format!("{:x}", value)),
+        2 => ("0b", format!("{:b}", value)),
+        3 => ("0o", format!("{:o}", value)),
+        4 => ("", (value as u8 as char).to_string()),
+        _ if (value as i32) < 0 => ("-", (value as i32).unsigned_abs().to_string()),
+        _ => ("", value.to_string()),
+    };
+    let pad = ((format >> 5) as usize).saturating_sub(prefix.len() + digits.chars().count());
+    let mut text = match format & 0x10 {
+        0 => format!("{}{}{}", " ".repeat(pad), prefix, digits),
+        _ => format!("{}{}{}", prefix, "0".repeat(pad), digits),
+    };
+    if format & 8 == 0 {
+        text.push('\n');
+    }
+    text
+}
+
+// Handler that executes each instruction, looked up once when the
+// instruction is decoded. Every arm is a separate function with its op fixed,
+// so the shared exec_* methods can be specialized per instruction instead of
+// matching on the op again at run time.
+pub(crate) type Handler = fn(&mut VM, i32, i32);
+
+pub(crate) fn handler(op: Op) -> Handler {
+    match op {
+        Op::Exit => |vm, a, _| vm.exec_exit(a),
+        Op::Swap => |vm, a, b| vm.exec_swap(a, b),
+        Op::Nop | Op::Debug => |_, _, _| {},
+        Op::Syscall => |vm, a, _| vm.exec_syscall(a as u32),
+        Op::Input => |vm, _, _| vm.exec_input(),
+        Op::Stinput => |vm, a, _| vm.exec_stinput(a as u32),
+        Op::Fork => |vm, _, _| vm.fork(),
+        Op::Wait => |vm, _, _| vm.wait(),
+        Op::Cpuid => |vm, _, _| vm.exec_cpuid(),
+        Op::Counter => |vm, a, _| vm.exec_counter(a as u32),
+        Op::Trapv => |vm, a, _| vm.trap_overflow = a != 0,
+        Op::Pop => |vm, a, _| vm.exec_pop(a as usize),
+        Op::Add => |vm, a, _| vm.exec_binary_arithmetic(Op::Add, a as u32),
+        Op::Sub => |vm, a, _| vm.exec_binary_arithmetic(Op::Sub, a as u32),
+        Op::Mul => |vm, a, _| vm.exec_binary_arithmetic(Op::Mul, a as u32),
+        Op::Div => |vm, a, _| vm.exec_binary_arithmetic(Op::Div, a as u32),
+        Op::Rem => |vm, a, _| vm.exec_binary_arithmetic(Op::Rem, a as u32),
+        Op::And => |vm, a, _| vm.exec_binary_arithmetic(Op::And, a as u32),
+        Op::Or => |vm, a, _| vm.exec_binary_arithmetic(Op::Or, a as u32),
+        Op::Xor => |vm, a, _| vm.exec_binary_arithmetic(Op::Xor, a as u32),
+        Op::Lsl => |vm, a, _| vm.exec_binary_arithmetic(Op::Lsl, a as u32),
+        Op::Lsr => |vm, a, _| vm.exec_binary_arithmetic(Op::Lsr, a as u32),
+        Op::Asr => |vm, a, _| vm.exec_binary_arithmetic(Op::Asr, a as u32),
+        Op::Min => |vm, a, _| vm.exec_binary_arithmetic(Op::Min, a as u32),
+        Op::Max => |vm, a, _| vm.exec_binary_arithmetic(Op::Max, a as u32),
+        Op::Mulh => |vm, a, _| vm.exec_binary_arithmetic(Op::Mulh, a as u32),
+        Op::Mulhu => |vm, a, _| vm.exec_binary_arithmetic(Op::Mulhu, a as u32),
+        Op::Qmul => |vm, a, _| vm.exec_binary_arithmetic(Op::Qmul, a as u32),
+        Op::Qdiv => |vm, a, _| vm.exec_binary_arithmetic(Op::Qdiv, a as u32),
+        Op::Neg => |vm, _, _| vm.exec_unary_arithmetic(Op::Neg),
+        Op::Not => |vm, _, _| vm.exec_unary_arithmetic(Op::Not),
+        Op::Toupper => |vm, _, _| vm.exec_unary_arithmetic(Op::Toupper),
+        Op::Tolower => |vm, _, _| vm.exec_unary_arithmetic(Op::Tolower),
+        Op::Isdigit => |vm, _, _| vm.exec_unary_arithmetic(Op::Isdigit),
+        Op::Isalpha => |vm, _, _| vm.exec_unary_arithmetic(Op::Isalpha),
+        Op::Popcount => |vm, _, _| vm.exec_unary_arithmetic(Op::Popcount),
+        Op::Clz => |vm, _, _| vm.exec_unary_arithmetic(Op::Clz),
+        Op::Ctz => |vm, _, _| vm.exec_unary_arithmetic(Op::Ctz),
+        Op::Abs => |vm, _, _| vm.exec_unary_arithmetic(Op::Abs),
+        Op::Stprint => |vm, a, _| vm.exec_stprint(a),
+        Op::Call => |vm, a, _| vm.exec_call(a),
+        Op::Return => |vm, a, _| vm.exec_return(a),
+        Op::Goto => |vm, a, _| vm.exec_goto(a),
+        Op::Ifeq => |vm, a, _| vm.exec_binary_if(Op::Ifeq, a),
+        Op::Ifne => |vm, a, _| vm.exec_binary_if(Op::Ifne, a),
+        Op::Iflt => |vm, a, _| vm.exec_binary_if(Op::Iflt, a),
+        Op::Ifgt => |vm, a, _| vm.exec_binary_if(Op::Ifgt, a),
+        Op::Ifle => |vm, a, _| vm.exec_binary_if(Op::Ifle, a),
+        Op::Ifge => |vm, a, _| vm.exec_binary_if(Op::Ifge, a),
+        Op::Ifltu => |vm, a, _| vm.exec_binary_if(Op::Ifltu, a),
+        Op::Ifgtu => |vm, a, _| vm.exec_binary_if(Op::Ifgtu, a),
+        Op::Ifleu => |vm, a, _| vm.exec_binary_if(Op::Ifleu, a),
+        Op::Ifgeu => |vm, a, _| vm.exec_binary_if(Op::Ifgeu, a),
+        Op::Ifez => |vm, a, _| vm.exec_unary_if(Op::Ifez, a),
+        Op::Ifnz => |vm, a, _| vm.exec_unary_if(Op::Ifnz, a),
+        Op::Ifmi => |vm, a, _| vm.exec_unary_if(Op::Ifmi, a),
+        Op::Ifpl => |vm, a, _| vm.exec_unary_if(Op::Ifpl, a),
+        Op::Vadd => |vm, _, _| vm.exec_vector_arithmetic(Op::Vadd),
+        Op::Vsub => |vm, _, _| vm.exec_vector_arithmetic(Op::Vsub),
+        Op::Vmul => |vm, _, _| vm.exec_vector_arithmetic(Op::Vmul),
+        Op::Load => |vm, _, _| vm.exec_load(None, 4),
+        Op::Loadi => |vm, a, _| vm.exec_load(Some(a as u32), 4),
+        Op::Loadb => |vm, _, _| vm.exec_load(None, 1),
+        Op::Loadbi => |vm, a, _| vm.exec_load(Some(a as u32), 1),
+        Op::Store => |vm, _, _| vm.exec_store(None, 4),
+        Op::Storei => |vm, a, _| vm.exec_store(Some(a as u32), 4),
+        Op::Storeb => |vm, _, _| vm.exec_store(None, 1),
+        Op::Storebi => |vm, a, _| vm.exec_store(Some(a as u32), 1),
+        Op::Gload => |vm, a, _| vm.exec_gload(a as usize),
+        Op::Gstore => |vm, a, _| vm.exec_gstore(a as usize),
+        Op::Enter => |vm, a, _| vm.exec_enter(a as usize),
+        Op::Leave => |vm, _, _| vm.exec_leave(),
+        Op::Lload => |vm, a, _| vm.exec_lload(a),
+        Op::Lstore => |vm, a, _| vm.exec_lstore(a),
+        Op::Alloc => |vm, _, _| vm.exec_alloc(),
+        Op::Free => |vm, _, _| vm.exec_free(),
+        Op::Memcpy => |vm, _, _| vm.exec_memcpy(),
+        Op::Memset => |vm, _, _| vm.exec_memset(),
+        Op::Memcmp => |vm, _, _| vm.exec_memcmp(),
+        Op::Add64 => |vm, _, _| vm.exec_wide_arithmetic(Op::Add64),
+        Op::Sub64 => |vm, _, _| vm.exec_wide_arithmetic(Op::Sub64),
+        Op::Mul64 => |vm, _, _| vm.exec_wide_arithmetic(Op::Mul64),
+        Op::Cmp64 => |vm, _, _| vm.exec_wide_arithmetic(Op::Cmp64),
+        Op::Cmp => |vm, _, _| vm.exec_cmp(),
+        Op::Beq => |vm, a, _| vm.exec_flag_branch(Op::Beq, a),
+        Op::Bne => |vm, a, _| vm.exec_flag_branch(Op::Bne, a),
+        Op::Bhs => |vm, a, _| vm.exec_flag_branch(Op::Bhs, a),
+        Op::Blo => |vm, a, _| vm.exec_flag_branch(Op::Blo, a),
+        Op::Bmi => |vm, a, _| vm.exec_flag_branch(Op::Bmi, a),
+        Op::Bpl => |vm, a, _| vm.exec_flag_branch(Op::Bpl, a),
+        Op::Bvs => |vm, a, _| vm.exec_flag_branch(Op::Bvs, a),
+        Op::Bvc => |vm, a, _| vm.exec_flag_branch(Op::Bvc, a),
+        Op::Bhi => |vm, a, _| vm.exec_flag_branch(Op::Bhi, a),
+        Op::Bls => |vm, a, _| vm.exec_flag_branch(Op::Bls, a),
+        Op::Bge => |vm, a, _| vm.exec_flag_branch(Op::Bge, a),
+        Op::Blt => |vm, a, _| vm.exec_flag_branch(Op::Blt, a),
+        Op::Bgt => |vm, a, _| vm.exec_flag_branch(Op::Bgt, a),
+        Op::Ble => |vm, a, _| vm.exec_flag_branch(Op::Ble, a),
+        Op::B64enc => |vm, _, _| vm.exec_codec(Op::B64enc),
+        Op::B64dec => |vm, _, _| vm.exec_codec(Op::B64dec),
+        Op::Hexenc => |vm, _, _| vm.exec_codec(Op::Hexenc),
+        Op::Hexdec => |vm, _, _| vm.exec_codec(Op::Hexdec),
+        Op::Dup => |vm, a, _| vm.exec_dup(a),
+        Op::Print => |vm, a, _| vm.exec_print(a, 0),
+        Op::Printh => |vm, a, _| vm.exec_print(a, 1),
+        Op::Printb => |vm, a, _| vm.exec_print(a, 2),
+        Op::Printo => |vm, a, _| vm.exec_print(a, 3),
+        Op::Printf => |vm, a, b| vm.exec_print(a, b as u32),
+        Op::Dump => |vm, _, _| vm.exec_dump(),
+        Op::Push => |vm, a, _| vm.push(a as u32),
+    }
+}
+
+impl Default for VM {
+    fn default() -> VM {
+        VM::new(DEFAULT_RAM_SIZE)
+    }
+}
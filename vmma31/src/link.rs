@@ -0,0 +1,206 @@
+// Object files and the linker behind `vmma31 link`. An object is what
+// `vmma31 asm --object` makes of one source file: a program file (see
+// program.rs) under OBJECT_TAG, whose labels may refer to other files.
+// Besides the usual sections it has:
+//
+//   "GLB\n"               GLOBAL_TAG, only when the file exports names
+//   u32                   length in bytes of what follows
+//   u8 len, name          one entry per name given to .global
+//   "REL\n"               RELOC_TAG, only when labels are used
+//   u32                   length in bytes of what follows
+//   u32 addr, u8 operand, u8 len, name   one entry per use of a label
+//
+// A relocation says the word at addr uses the address of name: operand
+// gives which of the instruction's operands (counting from 0), or 255 for
+// a whole `.word`. The object's code and data hold the value as far as the
+// assembler knew it, 0 for names from other files.
+//
+// Linking puts the objects' code one after another, in the order given, and
+// moves each object's code labels along with it. Data and bss blocks stay
+// at the addresses their source gave, so objects must pick blocks that
+// don't overlap. Every relocation is then filled in again: a name resolves
+// to the object's own label if it has one, otherwise to a .global of
+// another object. The first object's entry point is the program's, and at
+// most one object can have a globals region, which becomes the program's;
+// its named globals are only known inside that object. Objects written for
+// a separate return stack can only be linked with others that are too.
+
+use std::fs;
+
+use crate::isa;
+use crate::program::{join_file, join_program, push_name, split_file, Reader, Sections, OBJECT_TAG};
+
+pub const GLOBAL_TAG: [u8; 4] = *b"GLB\n";
+pub const RELOC_TAG: [u8; 4] = *b"REL\n";
+const WHOLE_WORD: u8 = 255;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    pub sections: Sections, // Symbols hold every label, not only the globals
+    pub globals: Vec<String>,
+    pub relocations: Vec<Relocation>,
+    pub code: Vec<u8>,
+}
+
+// A use of name's address at addr, in operand (None for a whole word)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub addr: usize,
+    pub operand: Option<usize>,
+    pub name: String,
+}
+
+impl Object {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut globals = Vec::new();
+        for name in &self.globals {
+            push_name(&mut globals, name);
+        }
+        let mut relocations = Vec::new();
+        for relocation in &self.relocations {
+            relocations.extend_from_slice(&(relocation.addr as u32).to_le_bytes());
+            relocations.push(relocation.operand.map_or(WHOLE_WORD, |operand| operand as u8));
+            push_name(&mut relocations, &relocation.name);
+        }
+        let others: Vec<([u8; 4], Vec<u8>)> =
+            [(GLOBAL_TAG, globals), (RELOC_TAG, relocations)].into_iter().filter(|(_, body)| !body.is_empty()).collect();
+        join_file(OBJECT_TAG, &self.sections, &others, &self.code)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Object, String> {
+        let (sections, others, code) = split_file(bytes, OBJECT_TAG, &[GLOBAL_TAG, RELOC_TAG])?;
+        let mut object = Object { sections, code: code.to_vec(), ..Object::default() };
+        for (tag, body) in others {
+            if tag == GLOBAL_TAG {
+                let mut reader = Reader { bytes: body, what: "global section" };
+                while !reader.bytes.is_empty() {
+                    object.globals.push(reader.name()?.to_string());
+                }
+            } else {
+                let mut reader = Reader { bytes: body, what: "relocation section" };
+                while !reader.bytes.is_empty() {
+                    let addr = reader.u32()? as usize;
+                    let operand = Some(reader.u8()?).filter(|&operand| operand != WHOLE_WORD).map(usize::from);
+                    object.relocations.push(Relocation { addr, operand, name: reader.name()?.to_string() });
+                }
+            }
+        }
+        Ok(object)
+    }
+
+    pub fn load(path: &str) -> Result<Object, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        Object::from_bytes(&bytes).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // Whether addr is in (or just past) the code rather than a data or bss
+    // block, so it moves when the code does
+    fn in_code(&self, addr: usize) -> bool {
+        addr <= self.code.len() && !self.sections.blocks().any(|(_, start, len)| (start..=start + len).contains(&addr))
+    }
+}
+
+// Link named objects into a program file
+pub fn link(objects: &[(&str, Object)]) -> Result<Vec<u8>, String> {
+    let mut bases = Vec::with_capacity(objects.len());
+    let mut code = Vec::new();
+    for (file, object) in objects {
+        if !object.code.len().is_multiple_of(4) {
+            return Err(format!("{}: code isn't a whole number of words", file));
+        }
+        bases.push(code.len());
+        code.extend_from_slice(&object.code);
+    }
+    let place = |index: usize, addr: usize| if objects[index].1.in_code(addr) { addr + bases[index] } else { addr };
+
+    // Everything but the relocations, moved to where it now lives
+    let mut sections = Sections::default();
+    let mut globals: Vec<(&str, usize, usize)> = Vec::new(); // Name, address, object index
+    for (index, (file, object)) in objects.iter().enumerate() {
+        if index > 0 && object.sections.entry != 0 {
+            return Err(format!("{}: only the first object can set the entry point", file));
+        }
+        if object.sections.return_stack != objects[0].1.sections.return_stack {
+            let (with, without) = if object.sections.return_stack { (*file, objects[0].0) } else { (objects[0].0, *file) };
+            return Err(format!("{} uses a separate return stack but {} doesn't", with, without));
+        }
+        for (addr, name) in object.sections.symbols.iter() {
+            sections.symbols.insert(name, place(index, addr));
+        }
+        for (addr, source, line) in object.sections.lines.iter() {
+            sections.lines.insert(addr + bases[index], source, line);
+        }
+        for (kind, addr, len) in object.sections.blocks() {
+            let overlap = objects[..index].iter().flat_map(|(other, object)| object.sections.blocks().map(move |block| (other, block)));
+            for (other, (other_kind, start, other_len)) in overlap {
+                if addr < start + other_len && start < addr + len {
+                    return Err(format!("{}: the {} at 0x{:x} overlaps the {} at 0x{:x} in {}", file, kind, addr, other_kind, start, other));
+                }
+            }
+        }
+        sections.data.extend(object.sections.data.iter().cloned());
+        sections.bss.extend(object.sections.bss.iter().cloned());
+        if object.sections.globals.is_some() {
+            if let Some(other) = objects[..index].iter().find(|(_, object)| object.sections.globals.is_some()) {
+                return Err(format!("{}: only one object can have a globals region, and {} has one", file, other.0));
+            }
+            sections.globals = object.sections.globals;
+        }
+        for name in &object.globals {
+            let addr = object.sections.symbols.address_of(name).ok_or_else(|| format!("{}: .global {} isn't defined", file, name))?;
+            if let Some(&(_, _, other)) = globals.iter().find(|(global, _, _)| global == name) {
+                return Err(format!("{} is defined in both {} and {}", name, objects[other].0, file));
+            }
+            globals.push((name, place(index, addr), index));
+        }
+    }
+    if let Some((_, first)) = objects.first() {
+        sections.entry = first.sections.entry;
+        sections.return_stack = first.sections.return_stack;
+    }
+
+    for (index, (file, object)) in objects.iter().enumerate() {
+        for relocation in &object.relocations {
+            let name = relocation.name.as_str();
+            let target = match object.sections.symbols.address_of(name) {
+                Some(addr) => place(index, addr),
+                None => match globals.iter().find(|(global, _, _)| *global == name) {
+                    Some(&(_, addr, _)) => addr,
+                    None => return Err(format!("{}: undefined symbol {}", file, name)),
+                },
+            };
+            let site = relocation.addr;
+            let word = if site + 4 <= object.code.len() {
+                &mut code[bases[index] + site..][..4]
+            } else {
+                let block = sections.data.iter_mut().find(|(start, bytes)| (*start..*start + bytes.len()).contains(&site));
+                match block {
+                    Some((start, bytes)) if site + 4 <= *start + bytes.len() => &mut bytes[site - *start..][..4],
+                    _ => return Err(format!("{}: relocation at 0x{:x} is outside the code and data", file, site)),
+                }
+            };
+            let old = u32::from_le_bytes((*word).try_into().unwrap());
+            let new = patch(old, relocation.operand, target, site + bases[index])
+                .map_err(|e| format!("{}: {} at 0x{:x}: {}", file, name, site, e))?;
+            word.copy_from_slice(&new.to_le_bytes());
+        }
+    }
+
+    sections.check_entry(code.len())?;
+    sections.check_blocks(code.len(), u32::MAX as usize)?;
+    Ok(join_program(&sections, &code))
+}
+
+// The word at site with target put in operand: as an offset from site for
+// a branch, otherwise as the address itself
+fn patch(word: u32, operand: Option<usize>, target: usize, site: usize) -> Result<u32, String> {
+    let Some(operand) = operand else {
+        return u32::try_from(target).map_err(|_| "address doesn't fit in a word".to_string());
+    };
+    let field = isa::lookup(word)
+        .and_then(|spec| spec.operands.get(operand))
+        .ok_or_else(|| format!("word {:08x} has no operand {}", word, operand))?;
+    let value = if field.branch { target as i64 - site as i64 } else { target as i64 };
+    let mask = ((1u32 << field.width) - 1) << field.shift;
+    Ok(word & !mask | field.insert_checked(value)?)
+}
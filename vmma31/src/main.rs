@@ -1,440 +1,503 @@
-use std::env;
-use std::fs::File;
-use std::io::{self, BufReader, Read, Write};
-use std::process;
-
-const RAM_SIZE: usize = 4096;
-const MAGIC: [u8; 4] = [0xde, 0xad, 0xbe, 0xef];
-
-struct VM {
-    memory: [u8; RAM_SIZE],
-    pc: usize,     // Program counter
-    sp: usize,     // Stack pointer
-    exited: bool,  // Exit flag
-    exit_code: i32, // Exit code
-    code_size: usize, // Size of the loaded bytecode
-}
-
-impl VM {
-    fn new() -> VM {
-        VM {
-            memory: [0u8; RAM_SIZE],
-            pc: 0,
-            sp: RAM_SIZE, // Stack starts at the bottom (4096)
-            exited: false,
-            exit_code: 0,
-            code_size: 0, // Initialize to 0, will be set in load_file
-        }
-    }
-
-    // Load bytecode file into memory, excluding magic bytes
-    fn load_file(&mut self, filename: &str) -> Result<(), String> {
-        let file = File::open(filename).map_err(|e| format!("Failed to open file: {}", e))?;
-        let mut reader = BufReader::new(file);
-
-        // Check magic bytes
-        let mut magic = [0u8; 4];
-        reader.read_exact(&mut magic).map_err(|e| format!("Failed to read magic bytes: {}", e))?;
-        if magic != MAGIC {
-            return Err(format!("Invalid magic bytes: {:?}", magic));
-        }
-
-        // Read the rest of the file into memory and track code size
-        let bytes_read = reader.read(&mut self.memory).map_err(|e| format!("Failed to read file: {}", e))?;
-        if bytes_read > RAM_SIZE {
-            return Err("File too large for memory".to_string());
-        }
-        self.code_size = bytes_read; // Store the size of the loaded bytecode
-
-        Ok(())
-    }
-
-    // Run the virtual machine
-    fn run(&mut self) -> i32 {
-        while self.pc < self.code_size && !self.exited {
-            let instruction = self.read_u32(self.pc);
-            let pc_before = self.pc;
-            self.execute_instruction(instruction);
-            
-            // Only increment PC if it wasn't modified by the instruction
-            if self.pc == pc_before && !self.exited {
-                self.pc += 4; // Instructions are 4 bytes
-            }
-        }
-        self.exit_code
-    }
-
-    // Read a 4-byte little-endian u32 from memory at the given address
-    fn read_u32(&self, addr: usize) -> u32 {
-        if addr + 3 >= RAM_SIZE {
-            return 0; // Return 0 if out of bounds
-        }
-        u32::from_le_bytes(self.memory[addr..addr + 4].try_into().unwrap())
-    }
-
-    // Write a 4-byte u32 to memory at the given address in little-endian
-    fn write_u32(&mut self, addr: usize, value: u32) {
-        if addr + 3 < RAM_SIZE {
-            self.memory[addr..addr + 4].copy_from_slice(&value.to_le_bytes());
-        }
-    }
-
-    // Push a value onto the stack
-    fn push(&mut self, value: u32) {
-        if self.sp >= 4 { // Prevent underflow
-            self.sp -=
-             4;
-            self.write_u32(self.sp, value);
-        }
-    }
-
-    // Pop a value from the stack
-    fn pop(&mut self) -> u32 {
-        if self.sp + 4 <= RAM_SIZE { // Prevent overflow
-            let value = self.read_u32(self.sp);
-            self.sp += 4;
-            value
-        } else {
-            0 // Return 0 if stack is empty
-        }
-    }
-
-    // Peek a value from the stack at sp + offset
-    fn peek(&self, offset: i32) -> u32 {
-        let addr = (self.sp as i32 + offset) as usize;
-        if addr + 3 < RAM_SIZE {
-            self.read_u32(addr)
-        } else {
-            0
-        }
-    }
-
-    // Execute a single instruction
-    fn execute_instruction(&mut self, instruction: u32) {
-        let opcode = (instruction >> 28) & 0xF;
-
-        match opcode {
-            0 => self.exec_miscellaneous(instruction),
-            1 => self.exec_pop(instruction),
-            2 => self.exec_binary_arithmetic(instruction),
-            3 => self.exec_unary_arithmetic(instruction),
-            4 => self.exec_stprint(instruction),
-            5 => self.exec_call(instruction),
-            6 => self.exec_return(instruction),
-            7 => self.exec_goto(instruction),
-            8 => self.exec_binary_if(instruction),
-            9 => self.exec_unary_if(instruction),
-            12 => self.exec_dup(instruction),
-            13 => self.exec_print(instruction),
-            14 => self.exec_dump(),
-            15 => self.exec_push(instruction),
-            _ => {} // Unknown opcode, ignore
-        }
-    }
-
-    fn exec_miscellaneous(&mut self, instruction: u32) {
-        let subopcode = (instruction >> 24) & 0xF;
-        match subopcode {
-            0 => { // exit [code]
-                let code = instruction & 0xFFF; // Only 12 bits for exit code
-                self.exit_code = code as i32;
-                self.exited = true;
-            }
-            1 => { // swap [from] [to]
-                let from_raw = (instruction >> 12) & 0xFFF;
-                let to_raw = instruction & 0xFFF;
-                // Sign-extend 12-bit values and multiply by 4 (word offsets)
-                let from = ((from_raw as i32) << 20 >> 20) * 4;
-                let to = ((to_raw as i32) << 20 >> 20) * 4;
-                let addr1 = (self.sp as i32 + from) as usize;
-                let addr2 = (self.sp as i32 + to) as usize;
-                if addr1 + 3 < RAM_SIZE && addr2 + 3 < RAM_SIZE {
-                    let val1 = self.read_u32(addr1);
-                    let val2 = self.read_u32(addr2);
-                    self.write_u32(addr1, val2);
-                    self.write_u32(addr2, val1);
-                }
-            }
-            2 => {} // nop
-            4 => { // input
-                print!(""); // Flush any pending output
-                io::stdout().flush().unwrap();
-                
-                let mut input = String::new();
-                io::stdin().read_line(&mut input).expect("Failed to read input");
-                let input = input.trim();
-                
-                let value = if input.starts_with("0x") || input.starts_with("0X") {
-                    // Parse hex
-                    i32::from_str_radix(&input[2..], 16)
-                } else if input.starts_with("0b") || input.starts_with("0B") {
-                    // Parse binary
-                    i32::from_str_radix(&input[2..], 2)
-                } else {
-                    // Parse decimal
-                    input.parse::<i32>()
-                }.unwrap_or(0);
-                
-                self.push(value as u32);
-            }
-            5 => { // stinput [max_chars]
-                print!(""); // Flush any pending output
-                io::stdout().flush().unwrap();
-                
-                let max_chars = instruction & 0xFFFFFF;
-                let mut input = String::new();
-                io::stdin().read_line(&mut input).expect("Failed to read input");
-                let trimmed = input.trim();
-                
-                if trimmed.is_empty() {
-                    self.push(0);
-                } else {
-                    let bytes = trimmed.as_bytes();
-                    let len = if max_chars == 0xFFFFFF { bytes.len() } else { bytes.len().min(max_chars as usize) };
-                    
-                    let mut chunks = Vec::new();
-                    let mut start = 0;
-                    while start < len {
-                        let end = (start + 3).min(len);
-                        let mut value = [0u8; 4];
-                        for i in 0..(end - start) {
-                            value[i] = bytes[start + i];
-                        }
-                        value[3] = if end < len { 0x01 } else { 0x00 };
-                        chunks.push(u32::from_le_bytes(value));
-                        start += 3;
-                    }
-                    
-                    // Push chunks in reverse order
-                    for &chunk in chunks.iter().rev() {
-                        self.push(chunk);
-                    }
-                }
-            }
-            _ => {} // debug or unknown, ignore
-        }
-    }
-
-    fn exec_pop(&mut self, instruction: u32) {
-        let offset = (instruction >> 2) & 0x3FFFFFF;
-        let offset = offset as usize * 4; // Multiply by 4 for byte addressing
-        
-        if self.sp + offset <= RAM_SIZE {
-            self.sp += offset;
-        } else {
-            self.sp = RAM_SIZE;
-        }
-    }
-
-    fn exec_binary_arithmetic(&mut self, instruction: u32) {
-        let subopcode = (instruction >> 24) & 0xF;
-        let right = self.pop() as i32;
-        let left = self.pop() as i32;
-        let result = match subopcode {
-            0 => left + right,    // add
-            1 => left - right,    // sub
-            2 => left * right,    // mul
-            3 => if right != 0 { left / right } else { 0 },    // div
-            4 => if right != 0 { left % right } else { 0 },    // rem
-            5 => left & right,    // and
-            6 => left | right,    // or
-            7 => left ^ right,    // xor
-            8 => left << right,   // lsl
-            9 => (left as u32 >> right as u32) as i32, // lsr
-            11 => left >> right,  // asr
-            _ => 0
-        };
-        self.push(result as u32);
-    }
-
-    fn exec_unary_arithmetic(&mut self, instruction: u32) {
-        let subopcode = (instruction >> 24) & 0xF;
-        let value = self.pop() as i32;
-        let result = match subopcode {
-            0 => -value, // neg
-            1 => !value, // not
-            _ => 0
-        };
-        self.push(result as u32);
-    }
-
-    fn exec_stprint(&mut self, instruction: u32) {
-        let offset_raw = (instruction >> 2) & 0x3FFFFFF; // Bits 27:2
-        let offset = if (offset_raw & (1 << 25)) != 0 {
-            ((offset_raw | 0xFC000000) as i32) * 4 // Sign-extend from bit 25
-        } else {
-            (offset_raw as i32) * 4
-        };
-        
-        let mut addr = (self.sp as i32 + offset) as usize;
-        while addr < RAM_SIZE {
-            let byte = self.memory[addr];
-            if byte == 0 {
-                break;
-            } else if byte != 1 { // Skip continuation byte
-                print!("{}", byte as char);
-            }
-            addr += 1;
-        }
-        io::stdout().flush().unwrap();
-    }
-
-    fn exec_call(&mut self, instruction: u32) {
-        let offset_raw = (instruction >> 2) & 0x3FFFFFF;
-        let offset = if (offset_raw & (1 << 25)) != 0 {
-            ((offset_raw | 0xFC000000) as i32) * 4
-        } else {
-            (offset_raw as i32) * 4
-        };
-        
-        self.push((self.pc + 4) as u32); // Push next instruction address
-        self.pc = ((self.pc as i32) + offset) as usize;
-    }
-
-    fn exec_return(&mut self, instruction: u32) {
-        let offset_raw = (instruction >> 2) & 0x3FFFFFF;
-        let offset = if (offset_raw & (1 << 25)) != 0 {
-            ((offset_raw | 0xFC000000) as i32) * 4
-        } else {
-            (offset_raw as i32) * 4
-        };
-        
-        if offset as usize > 0 && self.sp + (offset as usize) <= RAM_SIZE {
-            self.sp += offset as usize;
-        }
-        
-        if self.sp < RAM_SIZE {
-            self.pc = self.pop() as usize;
-        }
-    }
-
-    fn exec_goto(&mut self, instruction: u32) {
-        let offset_raw = (instruction >> 2) & 0x3FFFFFF;
-        let offset = if (offset_raw & (1 << 25)) != 0 {
-            ((offset_raw | 0xFC000000) as i32) * 4
-        } else {
-            (offset_raw as i32) * 4
-        };
-        
-        self.pc = ((self.pc as i32) + offset) as usize;
-    }
-
-    fn exec_binary_if(&mut self, instruction: u32) {
-        let condition = (instruction >> 25) & 0x7;
-        let offset_raw = (instruction >> 2) & 0x7FFFFF; // 23 bits for PC relative offset
-        let offset = if (offset_raw & (1 << 22)) != 0 {
-            ((offset_raw | 0xFF800000) as i32) * 4
-        } else {
-            (offset_raw as i32) * 4
-        };
-        
-        let right = self.peek(0) as i32;
-        let left = self.peek(4) as i32;
-        let condition_met = match condition {
-            0 => left == right,  // eq
-            1 => left != right,  // ne
-            2 => left < right,   // lt
-            3 => left > right,   // gt
-            4 => left <= right,  // le
-            5 => left >= right,  // ge
-            _ => false
-        };
-        
-        if condition_met {
-            self.pc = ((self.pc as i32) + offset) as usize;
-        }
-    }
-
-    fn exec_unary_if(&mut self, instruction: u32) {
-        let condition = (instruction >> 24) & 0x3; // Bits 25:24 contain the condition
-        let offset_raw = (instruction >> 2) & 0x3FFFFF; // 22 bits for PC relative offset
-        let offset = if (offset_raw & (1 << 21)) != 0 {
-            ((offset_raw | 0xFFC00000) as i32) * 4
-        } else {
-            (offset_raw as i32) * 4
-        };
-        
-        let value = self.peek(0) as i32;
-        let condition_met = match condition {
-            0 => value == 0,  // ez (equals zero)
-            1 => value != 0,  // nz (not zero)
-            2 => value < 0,   // mi (minus/negative)
-            3 => value >= 0,  // pl (plus/positive or zero)
-            _ => false
-        };
-        
-        if condition_met {
-            self.pc = ((self.pc as i32) + offset) as usize;
-        }
-    }
-
-    fn exec_dup(&mut self, instruction: u32) {
-        let offset_raw = (instruction >> 2) & 0x3FFFFFF;
-        let offset = if (offset_raw & (1 << 25)) != 0 {
-            ((offset_raw | 0xFC000000) as i32) * 4
-        } else {
-            (offset_raw as i32) * 4
-        };
-        
-        let value = self.peek(offset);
-        self.push(value);
-    }
-
-    fn exec_print(&mut self, instruction: u32) {
-        let offset_raw = (instruction >> 2) & 0x3FFFFFF;
-        let offset = if (offset_raw & (1 << 25)) != 0 {
-            ((offset_raw | 0xFC000000) as i32) * 4
-        } else {
-            (offset_raw as i32) * 4
-        };
-        
-        let fmt = instruction & 0x3;
-        let value = self.peek(offset) as i32;
-        match fmt {
-            0 => println!("{}", value),              // decimal
-            1 => println!("0x{:x}", value),         // hex
-            2 => println!("0b{:b}", value),         // binary
-            3 => println!("0o{:o}", value),         // octal
-            _ => {}
-        }
-    }
-
-    fn exec_dump(&mut self) {
-        if self.sp >= RAM_SIZE {
-            return; // Stack empty
-        }
-        let mut addr = self.sp;
-        while addr < RAM_SIZE {
-            let value = self.read_u32(addr);
-            println!("{:04x}: {:08x}", addr - self.sp, value);
-            addr += 4;
-        }
-    }
-
-    fn exec_push(&mut self, instruction: u32) {
-        let value_raw = instruction & 0x0FFFFFFF; // Extract bits 27:0
-        let value = if (value_raw & (1 << 27)) != 0 {
-            (value_raw | 0xF0000000) as i32 // Sign-extend if bit 27 is set
-        } else {
-            value_raw as i32
-        };
-        self.push(value as u32);
-    }
-}
-
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <bytecode_file>", args[0]);
-        process::exit(1);
-    }
-
-    let mut vm = VM::new();
-    if let Err(e) = vm.load_file(&args[1]) {
-        eprintln!("Error: {}", e);
-        process::exit(1);
-    }
-
-    let exit_code = vm.run();
-    process::exit(exit_code);
-}
\ No newline at end of file
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use vmma31::manifest::Outcome;
+use vmma31::{
+    asm, compile, debugger, link, opt, wasm, CoreDump, compress_program, decode_program, lint_calls, split_program, FlushPolicy, InputFormat, read_program, verify, RunStatus, StreamIo, TraceFormat, DEFAULT_RAM_SIZE, FAULT_EXIT_CODE, MAX_RAM_SIZE, VM,
+};
+#[cfg(feature = "conformance")]
+use vmma31::conformance;
+#[cfg(feature = "dap")]
+use vmma31::dap;
+#[cfg(feature = "manifest")]
+use vmma31::manifest;
+#[cfg(feature = "snapshot")]
+use vmma31::VmSnapshot;
+
+const TIMEOUT_EXIT_CODE: i32 = 124; // Same status as coreutils timeout
+const WATCHDOG_GRACE: Duration = Duration::from_millis(250); // Lets run_for report a timeout first
+
+// Parse durations like "5s", "250ms", "2m"; a bare number is seconds
+fn parse_duration(text: &str) -> Option<Duration> {
+    let (number, scale) = if let Some(ms) = text.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(s) = text.strip_suffix('s') {
+        (s, 1.0)
+    } else if let Some(m) = text.strip_suffix('m') {
+        (m, 60.0)
+    } else {
+        (text, 1.0)
+    };
+    let seconds = number.parse::<f64>().ok()? * scale;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+// Parse memory sizes like "65536", "64k" or "1m" (binary units). The size
+// must be a nonzero multiple of 4 no larger than MAX_RAM_SIZE.
+fn parse_ram_size(text: &str) -> Option<usize> {
+    let (number, scale) = match text.char_indices().last()? {
+        (i, 'k' | 'K') => (&text[..i], 1 << 10),
+        (i, 'm' | 'M') => (&text[..i], 1 << 20),
+        _ => (text, 1),
+    };
+    let size = number.parse::<usize>().ok()?.checked_mul(scale)?;
+    (size > 0 && size % 4 == 0 && size <= MAX_RAM_SIZE).then_some(size)
+}
+
+// Report the timeout and exit. Both the watchdog and the main thread may get
+// here at once; whichever loses the lock waits until the process is gone.
+fn exit_timed_out(timeout: Duration) -> ! {
+    static REPORTING: Mutex<()> = Mutex::new(());
+    let _guard = REPORTING.lock();
+    let _ = io::stdout().flush();
+    eprintln!("Error: Timed out after {:?}", timeout);
+    process::exit(TIMEOUT_EXIT_CODE);
+}
+
+// Write a subcommand's output to path, or to stdout when path is "-"
+fn write_output(path: &str, bytes: &[u8]) -> Result<(), String> {
+    if path == "-" {
+        let mut stdout = io::stdout().lock();
+        return stdout.write_all(bytes).and_then(|()| stdout.flush()).map_err(|e| format!("Failed to write to stdout: {}", e));
+    }
+    fs::write(path, bytes).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+// What --check-calls found, once the run has stopped
+fn report_call_violations(vm: &VM) {
+    for violation in vm.call_violations() {
+        eprintln!("calling convention: {}", violation);
+    }
+}
+
+fn usage_error(usage: &str) -> ! {
+    eprintln!("{}", usage);
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let usage = format!(
+        "Usage: {0} [options] <bytecode_file>\n       \
+         {0} conformance <spec_dir>\n       \
+         {0} asm <source_file> -o <bytecode_file> [--symbols | --debug-info | --object]\n       \
+         {0} link <object_file>... -o <bytecode_file>\n       \
+         {0} compile <bytecode_file> -o <c_file>\n       \
+         {0} to-wasm <bytecode_file> -o <wasm_file>\n       \
+         {0} opt <bytecode_file> -o <bytecode_file>\n       \
+         {0} compress <bytecode_file> -o <bytecode_file>\n       \
+         {0} verify [--check-calls] <bytecode_file>\n       \
+         {0} debug [options] <bytecode_file>\n       \
+         {0} core <core_file>\n       \
+         {0} dap\n\
+         Options:\n  \
+         --allow-regex        Enable the regex syscalls\n  \
+         --regex <pattern>    Pre-register a pattern for the registered-regex syscall\n  \
+         --check-calls        Report calling-convention violations\n  \
+         --dump-backtrace     Have dump print the call chain after the stack\n  \
+         --ram-size <size>    Bytes of guest memory (default {3}; e.g. 65536, 64k, 1m)\n  \
+         --no-shadow-stack    Don't check return addresses against the shadow stack\n  \
+         --return-stack       Keep return addresses on a separate stack, not the data stack\n  \
+         --strict             Stop with status {2} on memory accesses outside RAM\n  \
+         --trap-overflow      Stop with status {2} when add, sub, mul, qmul or qdiv overflows\n  \
+         --timeout <time>     Stop with status {1} after a wall-clock limit (e.g. 5s, 500ms)\n  \
+         --flush <policy>     When to flush output: line (default), none, exit or a byte count\n  \
+         --trace              Print each executed instruction to stderr\n  \
+         --trace-file <file>  Write the instruction trace to a file instead\n  \
+         --trace-format <fmt> Trace as text (default) or json, one object per line\n  \
+         --record-io <file>   Save every line the program reads to a file\n  \
+         --replay-io <file>   Read the program's input from a recorded file instead of stdin\n  \
+         --stdin-file <file>  Read the program's input from a file instead of stdin\n  \
+         --stdout-file <file> Write the program's output to a file instead of stdout\n  \
+         --stats              Print stack and output high-water marks to stderr on exit\n  \
+         --emit-manifest <file>  Write a JSON reproducibility manifest for the run\n  \
+         --snapshot-out <file>   Save the VM state to a file when the run stops\n  \
+         --resume <snapshot>     Continue from a saved state instead of a bytecode file\n  \
+         --format <fmt>       Program file format: auto (default), binary, ihex or hex\n  \
+         --disassemble        List the program's instructions instead of running it\n  \
+         --core-dump          On a fault, save the VM state next to the program as <name>.core\n\
+         A <bytecode_file> of - reads the program from stdin, and -o - writes to stdout",
+        args[0], TIMEOUT_EXIT_CODE, FAULT_EXIT_CODE, DEFAULT_RAM_SIZE
+    );
+
+    #[cfg(feature = "conformance")]
+    if args.get(1).map(String::as_str) == Some("conformance") {
+        let dir = args.get(2).unwrap_or_else(|| usage_error(&usage));
+        match conformance::run_suite(dir) {
+            Ok(all_passed) => process::exit(if all_passed { 0 } else { 1 }),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    #[cfg(feature = "dap")]
+    if args.get(1).map(String::as_str) == Some("dap") {
+        dap::run();
+        return;
+    }
+
+    if args.get(1).map(String::as_str) == Some("asm") {
+        let (source, output, sections) = match &args[2..] {
+            [source, flag, output] if flag == "-o" => (source, output, None),
+            [source, flag, output, sections] if flag == "-o" => (source, output, Some(sections.as_str())),
+            _ => usage_error(&usage),
+        };
+        let assemble = |text: &str| match sections {
+            None => asm::assemble_file(text, source),
+            Some("--symbols") => asm::assemble_with_symbols(text, source),
+            Some("--debug-info") => asm::assemble_with_debug_info(text, source),
+            Some("--object") => asm::assemble_object(text, source),
+            Some(_) => usage_error(&usage),
+        };
+        let result = fs::read_to_string(source)
+            .map_err(|e| format!("Failed to read {}: {}", source, e))
+            .and_then(|text| assemble(&text))
+            .and_then(|bytecode| write_output(output, &bytecode));
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.get(1).map(String::as_str) == Some("link") {
+        let (inputs, output) = match &args[2..] {
+            [inputs @ .., flag, output] if flag == "-o" && !inputs.is_empty() => (inputs, output),
+            _ => usage_error(&usage),
+        };
+        let result = inputs
+            .iter()
+            .map(|path| link::Object::load(path).map(|object| (path.as_str(), object)))
+            .collect::<Result<Vec<_>, String>>()
+            .and_then(|objects| link::link(&objects))
+            .and_then(|bytecode| write_output(output, &bytecode));
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.get(1).map(String::as_str) == Some("core") {
+        let path = match &args[2..] {
+            [path] => path,
+            _ => usage_error(&usage),
+        };
+        match CoreDump::load(path) {
+            Ok(core) => debugger::inspect_core(&mut VM::from_core(&core)),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.get(1).map(String::as_str) == Some("verify") {
+        let (path, check_calls) = match &args[2..] {
+            [path] => (path, false),
+            [flag, path] if flag == "--check-calls" => (path, true),
+            _ => usage_error(&usage),
+        };
+        let bytes = fs::read(path).unwrap_or_else(|e| {
+            eprintln!("Error: Failed to read {}: {}", path, e);
+            process::exit(1);
+        });
+        let verdicts = verify(&bytes);
+        for verdict in &verdicts {
+            println!("{}", verdict);
+        }
+        if !verdicts.is_empty() {
+            process::exit(1);
+        }
+        // Only a sound file has a control-flow graph worth following
+        if check_calls {
+            let (sections, _) = split_program(&bytes).expect("verified");
+            let words = decode_program(&bytes).expect("verified");
+            let violations = lint_calls(&words, &sections.symbols);
+            for violation in &violations {
+                println!("calling convention: {}", violation);
+            }
+            if !violations.is_empty() {
+                process::exit(1);
+            }
+        }
+        println!("{}: ok", path);
+        return;
+    }
+
+    if let Some(command @ ("compile" | "to-wasm" | "opt" | "compress")) = args.get(1).map(String::as_str) {
+        let (input, output) = match &args[2..] {
+            [input, flag, output] if flag == "-o" => (input, output),
+            _ => usage_error(&usage),
+        };
+        let translate = match command {
+            "compile" => |bytes: &[u8]| compile::compile_c(bytes).map(String::into_bytes),
+            "opt" => opt::optimize,
+            "compress" => compress_program,
+            _ => wasm::compile_wasm,
+        };
+        let result = fs::read(input)
+            .map_err(|e| format!("Failed to read {}: {}", input, e))
+            .and_then(|bytes| translate(&bytes))
+            .and_then(|translated| write_output(output, &translated));
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // Memory is sized when the VM is created, before the other options apply to it
+    let ram_size = match args.iter().position(|arg| arg == "--ram-size") {
+        Some(index) => args
+            .get(index + 1)
+            .and_then(|text| parse_ram_size(text))
+            .unwrap_or_else(|| usage_error(&usage)),
+        None => DEFAULT_RAM_SIZE,
+    };
+    let mut vm = VM::new(ram_size);
+    let mut filename = None;
+    let mut timeout = None;
+    let mut stats = false;
+    let mut trace: Option<Box<dyn Write>> = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut stdin_file: Option<Box<dyn BufRead>> = None;
+    let mut stdout_file: Option<Box<dyn Write>> = None;
+    #[cfg(feature = "manifest")]
+    let mut manifest_path = None;
+    #[cfg(feature = "snapshot")]
+    let mut snapshot_path = None;
+    #[cfg(feature = "snapshot")]
+    let mut resume = false;
+    let mut format = InputFormat::Auto;
+    let mut disassemble = false;
+    let mut core_dump = false;
+    let debug = args.get(1).map(String::as_str) == Some("debug");
+    let mut rest = args[if debug { 2 } else { 1 }..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            #[cfg(feature = "regex")]
+            "--allow-regex" => vm.set_allow_regex(true),
+            "--check-calls" => vm.set_check_calls(true),
+            "--dump-backtrace" => vm.set_dump_backtrace(true),
+            "--no-shadow-stack" => vm.set_shadow_stack(false),
+            "--return-stack" => vm.set_return_stack(true),
+            "--strict" => vm.set_strict(true),
+            "--trap-overflow" => vm.set_trap_overflow(true),
+            "--stats" => stats = true,
+            "--trace" => trace = Some(Box::new(io::stderr())),
+            "--trace-file" => {
+                let path = rest.next().unwrap_or_else(|| usage_error(&usage));
+                match fs::File::create(path) {
+                    Ok(file) => trace = Some(Box::new(file)),
+                    Err(e) => {
+                        eprintln!("Error: Failed to create {}: {}", path, e);
+                        process::exit(1);
+                    }
+                }
+            }
+            "--record-io" => {
+                let path = rest.next().unwrap_or_else(|| usage_error(&usage));
+                match fs::File::create(path) {
+                    Ok(file) => vm.record_input(Box::new(file)),
+                    Err(e) => {
+                        eprintln!("Error: Failed to create {}: {}", path, e);
+                        process::exit(1);
+                    }
+                }
+            }
+            "--replay-io" => {
+                let path = rest.next().unwrap_or_else(|| usage_error(&usage));
+                match fs::read_to_string(path) {
+                    Ok(text) => vm.set_input(text.lines().map(String::from)),
+                    Err(e) => {
+                        eprintln!("Error: Failed to read {}: {}", path, e);
+                        process::exit(1);
+                    }
+                }
+            }
+            "--stdin-file" => {
+                let path = rest.next().unwrap_or_else(|| usage_error(&usage));
+                match fs::File::open(path) {
+                    Ok(file) => stdin_file = Some(Box::new(io::BufReader::new(file))),
+                    Err(e) => {
+                        eprintln!("Error: Failed to open {}: {}", path, e);
+                        process::exit(1);
+                    }
+                }
+            }
+            "--stdout-file" => {
+                let path = rest.next().unwrap_or_else(|| usage_error(&usage));
+                match fs::File::create(path) {
+                    Ok(file) => stdout_file = Some(Box::new(file)),
+                    Err(e) => {
+                        eprintln!("Error: Failed to create {}: {}", path, e);
+                        process::exit(1);
+                    }
+                }
+            }
+            "--trace-format" => {
+                let text = rest.next().unwrap_or_else(|| usage_error(&usage));
+                trace_format = TraceFormat::parse(text).unwrap_or_else(|| usage_error(&usage));
+            }
+            "--ram-size" => {
+                rest.next(); // Already applied
+            }
+            "--flush" => {
+                let text = rest.next().unwrap_or_else(|| usage_error(&usage));
+                vm.set_flush_policy(FlushPolicy::parse(text).unwrap_or_else(|| usage_error(&usage)));
+            }
+            "--format" => {
+                let text = rest.next().unwrap_or_else(|| usage_error(&usage));
+                format = InputFormat::parse(text).unwrap_or_else(|| usage_error(&usage));
+            }
+            "--disassemble" => disassemble = true,
+            "--core-dump" => core_dump = true,
+            #[cfg(feature = "regex")]
+            "--regex" => {
+                let pattern = rest.next().unwrap_or_else(|| usage_error(&usage));
+                if let Err(e) = vm.register_regex(pattern) {
+                    eprintln!("Error: Invalid regex {:?}: {}", pattern, e);
+                    process::exit(1);
+                }
+            }
+            #[cfg(feature = "manifest")]
+            "--emit-manifest" => {
+                manifest_path = Some(rest.next().unwrap_or_else(|| usage_error(&usage)));
+            }
+            "--timeout" => {
+                let text = rest.next().unwrap_or_else(|| usage_error(&usage));
+                timeout = Some(parse_duration(text).unwrap_or_else(|| usage_error(&usage)));
+            }
+            #[cfg(feature = "snapshot")]
+            "--snapshot-out" => {
+                snapshot_path = Some(rest.next().unwrap_or_else(|| usage_error(&usage)));
+            }
+            #[cfg(feature = "snapshot")]
+            "--resume" if filename.is_none() => {
+                filename = Some(rest.next().unwrap_or_else(|| usage_error(&usage)));
+                resume = true;
+            }
+            _ if filename.is_none() && !arg.starts_with("--") => filename = Some(arg),
+            _ => usage_error(&usage),
+        }
+    }
+    let filename = filename.unwrap_or_else(|| usage_error(&usage));
+    if let Some(out) = trace {
+        vm.set_trace(out, trace_format);
+    }
+    if stdin_file.is_some() || stdout_file.is_some() {
+        vm.set_io(Box::new(StreamIo::new(stdin_file, stdout_file)));
+    }
+
+    // Read once, since "-" is stdin; the manifest hashes the same bytes
+    let program = read_program(filename).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    #[cfg(feature = "snapshot")]
+    let loaded = if resume {
+        VmSnapshot::from_bytes(&program)
+            .map_err(|e| format!("Invalid snapshot {}: {}", filename, e))
+            .and_then(|snapshot| vm.restore(&snapshot))
+    } else {
+        vm.load_bytes_as(&program, format)
+    };
+    #[cfg(not(feature = "snapshot"))]
+    let loaded = vm.load_bytes_as(&program, format);
+    if let Err(e) = loaded {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+    if disassemble {
+        let symbols = vm.symbols();
+        if vm.entry() != 0 {
+            println!("entry point {:#06x}", vm.entry());
+        }
+        for (index, bytes) in vm.code().chunks_exact(4).enumerate() {
+            let (addr, word) = (index * 4, u32::from_le_bytes(bytes.try_into().unwrap()));
+            if let Some(name) = symbols.name_at(addr) {
+                println!("{}:", name);
+            }
+            // Name branch targets when the file says what they're called
+            let text = if symbols.is_empty() { vmma31::disassemble(word) } else { vmma31::disassemble_named(word, addr, symbols) };
+            println!("{:04x}: {:08x}  {}", addr, word, text);
+        }
+        for (kind, addr, len) in vm.blocks() {
+            println!("{} {:#06x}, {} bytes", kind, addr, len);
+        }
+        return;
+    }
+    if debug {
+        debugger::run(&mut vm);
+        vm.flush_output();
+        report_call_violations(&vm);
+        return;
+    }
+
+    // run_for stops compute-bound programs at the deadline; the watchdog is a
+    // backstop for guests blocked reading input, which it can't interrupt
+    if let Some(timeout) = timeout {
+        thread::spawn(move || {
+            thread::sleep(timeout + WATCHDOG_GRACE);
+            exit_timed_out(timeout);
+        });
+    }
+
+    let outcome = match timeout {
+        Some(timeout) => match vm.run_for(timeout) {
+            Ok(RunStatus::Exited(code)) => Outcome::Exited(code),
+            Ok(RunStatus::Resumable) => Outcome::TimedOut,
+            Err(error) => Outcome::Faulted(error),
+        },
+        None => match vm.run() {
+            Ok(code) => Outcome::Exited(code),
+            Err(error) => Outcome::Faulted(error),
+        },
+    };
+    vm.flush_output();
+    report_call_violations(&vm);
+    if stats {
+        eprintln!("{}", vm.telemetry());
+    }
+    #[cfg(feature = "manifest")]
+    if let Some(path) = manifest_path {
+        if let Err(e) = manifest::write_manifest(path, &vm, filename, &program, timeout, &outcome) {
+            eprintln!("Error: {}", e);
+        }
+    }
+    #[cfg(feature = "snapshot")]
+    if let Some(path) = snapshot_path {
+        if let Err(e) = vm.snapshot().save(path) {
+            eprintln!("Error: {}", e);
+        }
+    }
+    match outcome {
+        Outcome::Exited(code) => process::exit(code),
+        Outcome::TimedOut => exit_timed_out(timeout.unwrap_or_default()),
+        Outcome::Faulted(error) => {
+            eprintln!("Error: {}", error);
+            eprint!("{}", vm.format_backtrace());
+            if let Some(core) = vm.core_dump().filter(|_| core_dump) {
+                let path = match filename.as_str() {
+                    "-" => PathBuf::from("stdin.core"),
+                    _ => Path::new(filename).with_extension("core"),
+                };
+                match core.save(&path.to_string_lossy()) {
+                    Ok(()) => eprintln!("Core dumped to {}", path.display()),
+                    Err(e) => eprintln!("Error: {}", e),
+                }
+            }
+            process::exit(FAULT_EXIT_CODE);
+        }
+    }
+}
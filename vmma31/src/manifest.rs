@@ -0,0 +1,70 @@
+// Reproducibility manifest written by --emit-manifest: enough about a run to
+// verify a result attached to a bug report or grading record later.
+
+#[cfg(feature = "manifest")]
+use std::{fs, time::Duration};
+
+#[cfg(feature = "manifest")]
+use serde_json::json;
+#[cfg(feature = "manifest")]
+use sha2::{Digest, Sha256};
+
+use crate::VmError;
+#[cfg(feature = "manifest")]
+use crate::VM;
+
+// How the run ended, as recorded in the manifest
+pub enum Outcome {
+    Exited(i32),
+    TimedOut,
+    Faulted(VmError),
+}
+
+#[cfg(feature = "manifest")]
+pub fn write_manifest(
+    path: &str,
+    vm: &VM,
+    program_path: &str,
+    program: &[u8],
+    timeout: Option<Duration>,
+    outcome: &Outcome,
+) -> Result<(), String> {
+    let exit = match outcome {
+        Outcome::Exited(code) => json!({ "status": "exited", "code": code }),
+        Outcome::TimedOut => json!({ "status": "timeout" }),
+        Outcome::Faulted(error) => json!({ "status": "fault", "error": error.kind(), "pc": error.pc() }),
+    };
+    #[cfg(feature = "regex")]
+    let (allow_regex, regexes): (bool, Vec<&str>) = (vm.allow_regex, vm.regexes.iter().map(|r| r.as_str()).collect());
+    #[cfg(not(feature = "regex"))]
+    let (allow_regex, regexes): (bool, Vec<&str>) = (false, Vec::new());
+
+    let manifest = json!({
+        "vmma31_version": env!("CARGO_PKG_VERSION"),
+        "program": {
+            "path": program_path,
+            "sha256": format!("{:x}", Sha256::digest(program)),
+            "bytes": program.len(),
+        },
+        "config": {
+            "ram_size": vm.memory.len(),
+            "timeout_ms": timeout.map(|t| t.as_millis() as u64),
+            "check_calls": vm.check_calls,
+            "shadow_stack": vm.shadow_stack_enabled,
+            "return_stack": vm.return_stack,
+            "strict": vm.strict,
+            "trap_overflow": vm.trap_overflow,
+            "capabilities": { "regex": allow_regex },
+            "registered_regexes": regexes,
+        },
+        "input": {
+            "sha256": format!("{:x}", vm.input_digest.clone().finalize()),
+            "bytes": vm.input_bytes,
+        },
+        "exit": exit,
+        "instructions": vm.instructions_executed,
+    });
+
+    let text = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+    fs::write(path, text + "\n").map_err(|e| format!("Failed to write manifest: {}", e))
+}
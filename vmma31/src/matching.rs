@@ -0,0 +1,77 @@
+// Regex syscalls. Patterns are compiled by the host regex engine and run
+// over raw bytes in VM memory. Matching is a capability: unless the VM was
+// started with it enabled, every regex syscall reports REGEX_DISABLED.
+
+use regex::bytes::Regex;
+
+use crate::VM;
+
+pub const REGEX_MATCH: u32 = 0x30; // Pattern is a packed string in memory
+pub const REGEX_MATCH_REGISTERED: u32 = 0x31; // Pattern was registered by the host
+
+// Pushed in place of the match start when there is no match
+const NO_MATCH: i32 = -1;
+const BAD_PATTERN: i32 = -2;
+const REGEX_DISABLED: i32 = -3;
+
+impl VM {
+    // Pops [len] [subject] [pattern] (top first), where pattern is the address
+    // of a packed string or, for REGEX_MATCH_REGISTERED, a registered pattern
+    // id. Searches the len bytes at subject and pushes the match start (as an
+    // offset from subject, or a negative code) followed by the match length.
+    pub(crate) fn exec_regex(&mut self, number: u32) {
+        let len = self.pop() as usize;
+        let subject = self.pop() as usize;
+        let pattern = self.pop() as usize;
+
+        let (start, length) = self.regex_search(number, pattern, subject, len);
+        self.push(start as u32);
+        self.push(length);
+    }
+
+    fn regex_search(&self, number: u32, pattern: usize, subject: usize, len: usize) -> (i32, u32) {
+        if !self.allow_regex {
+            return (REGEX_DISABLED, 0);
+        }
+        let compiled;
+        let regex = if number == REGEX_MATCH_REGISTERED {
+            match self.regexes.get(pattern) {
+                Some(regex) => regex,
+                None => return (BAD_PATTERN, 0),
+            }
+        } else {
+            let source = self.read_packed_string(pattern);
+            compiled = match String::from_utf8(source).ok().and_then(|s| Regex::new(&s).ok()) {
+                Some(regex) => regex,
+                None => return (BAD_PATTERN, 0),
+            };
+            &compiled
+        };
+
+        let end = subject.saturating_add(len).min(self.memory.len());
+        let haystack = self.memory.get(subject..end).unwrap_or(&[]);
+        match regex.find(haystack) {
+            Some(m) => (m.start() as i32, m.len() as u32),
+            None => (NO_MATCH, 0),
+        }
+    }
+
+    // Compile a pattern ahead of time and return the id guests use to refer to it
+    pub fn register_regex(&mut self, pattern: &str) -> Result<u32, regex::Error> {
+        self.regexes.push(Regex::new(pattern)?);
+        Ok(self.regexes.len() as u32 - 1)
+    }
+
+    // Bytes of the packed string at addr, up to the terminating NUL, with
+    // continuation bytes removed
+    fn read_packed_string(&self, addr: usize) -> Vec<u8> {
+        self.memory
+            .get(addr..)
+            .unwrap_or(&[])
+            .iter()
+            .copied()
+            .take_while(|&byte| byte != 0)
+            .filter(|&byte| byte != 1)
+            .collect()
+    }
+}
@@ -0,0 +1,28 @@
+// Math syscalls over Q16.16 fixed-point words. Results are computed with
+// host f64 functions and rounded back to Q16.16; out-of-range results
+// saturate and undefined ones (sqrt of a negative, log of zero) give 0.
+
+use crate::fixed;
+use crate::VM;
+
+pub const MATH_SQRT: u32 = 0x20;
+pub const MATH_SIN: u32 = 0x21; // Radians
+pub const MATH_COS: u32 = 0x22; // Radians
+pub const MATH_LOG: u32 = 0x23; // Natural log
+pub const MATH_POW: u32 = 0x24;
+
+impl VM {
+    // Unary functions pop [x]; pow pops [exponent] [base]. The result is pushed.
+    pub(crate) fn exec_math(&mut self, number: u32) {
+        let x = fixed::to_f64(self.pop() as i32);
+        let result = match number {
+            MATH_SQRT => x.sqrt(),
+            MATH_SIN => x.sin(),
+            MATH_COS => x.cos(),
+            MATH_LOG => x.ln(),
+            MATH_POW => fixed::to_f64(self.pop() as i32).powf(x),
+            _ => return,
+        };
+        self.push(fixed::from_f64(result) as u32);
+    }
+}
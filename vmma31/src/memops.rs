@@ -0,0 +1,65 @@
+// Block memory instructions: memcpy, memset and memcmp, over ranges given
+// by address and length on the stack. Each does what a loop of loadb and
+// storeb would: outside memory, bytes read as 0 and writes are dropped,
+// unless strict, where any range reaching outside memory faults before
+// anything is written.
+
+use crate::VM;
+
+impl VM {
+    // memcpy: pop len, src and dst, and copy len bytes from src to dst as if
+    // through a buffer, so the ranges may overlap
+    pub(crate) fn exec_memcpy(&mut self) {
+        let (len, src, dst) = (self.pop() as usize, self.pop() as usize, self.pop() as usize);
+        self.check_bounds(src, len);
+        self.check_bounds(dst, len);
+        if self.fault.is_none() {
+            let bytes: Vec<u8> = (0..self.bytes_inside(dst, len)).map(|i| self.byte_at(src + i)).collect();
+            self.write_bytes(dst, &bytes);
+        }
+    }
+
+    // memset: pop len, a value and dst, and fill len bytes at dst with the
+    // value's low byte
+    pub(crate) fn exec_memset(&mut self) {
+        let (len, value, dst) = (self.pop() as usize, self.pop() as u8, self.pop() as usize);
+        self.check_bounds(dst, len);
+        if self.fault.is_none() {
+            let bytes = vec![value; self.bytes_inside(dst, len)];
+            self.write_bytes(dst, &bytes);
+        }
+    }
+
+    // memcmp: pop len, b and a, and compare len bytes at a with those at b
+    // as unsigned. Pushes -1, 0 or 1 as the first difference is lower in a,
+    // absent or higher in a.
+    pub(crate) fn exec_memcmp(&mut self) {
+        let (len, b, a) = (self.pop() as usize, self.pop() as usize, self.pop() as usize);
+        self.check_bounds(a, len);
+        self.check_bounds(b, len);
+        if self.fault.is_some() {
+            return;
+        }
+        // Past both ranges' ends in memory the bytes are all 0
+        let n = self.bytes_inside(a.min(b), len);
+        let order = (0..n).map(|i| self.byte_at(a + i).cmp(&self.byte_at(b + i))).find(|order| order.is_ne());
+        self.push(order.map_or(0, |order| order as i32) as u32);
+    }
+
+    // How many of the len bytes at addr are inside memory
+    fn bytes_inside(&self, addr: usize, len: usize) -> usize {
+        len.min(self.memory.len().saturating_sub(addr))
+    }
+
+    fn byte_at(&self, addr: usize) -> u8 {
+        self.memory.get(addr).copied().unwrap_or(0)
+    }
+
+    fn write_bytes(&mut self, addr: usize, bytes: &[u8]) {
+        if !bytes.is_empty() {
+            self.memory[addr..addr + bytes.len()].copy_from_slice(bytes);
+            self.invalidate_code(addr, bytes.len());
+            self.trace_write(addr, bytes);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+// Peephole optimizer behind `vmma31 opt`. It rewrites short instruction
+// sequences inside a basic block (so no branch lands in the middle of one)
+// into cheaper equivalents, then closes the gaps and re-encodes every branch
+// so offsets still reach the same instructions:
+//
+//   push k; pop 4           removed
+//   push k; pop n           pop n-4
+//   neg; neg / not; not     removed
+//   push a; push b; add     push a+b (also sub, mul, and, or, xor) if it fits
+//   goto/call/if to a goto  retargeted to where that goto goes
+//
+// Blocks that can't be reached from the entry (see Cfg::reachable), such as
+// padding or functions nothing calls, are removed too.
+//
+// The push rules change what's left in memory below the stack pointer, so
+// they're skipped for programs that read there (any negative stack offset,
+// or any absolute load, which could be of anywhere).
+//
+// Rules are applied until nothing changes, and symbols and source lines move
+// with their code (or go with it, if it's removed). Programs that treat their
+// own code as data or compute code addresses (other than call's return
+// address) may not survive having instructions move.
+
+use crate::cfg::Cfg;
+use crate::isa::{self, Op};
+use crate::program::{join_program, split_program, Sections};
+
+// Optimize magic-prefixed bytecode, returning the same format
+pub fn optimize(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut sections = split_program(bytes)?.0;
+    let mut words = isa::decode_program(bytes)?;
+    loop {
+        let before = words.clone();
+        thread_branches(&mut words);
+        words = peephole(&words, &mut sections)?;
+        words = remove_unreachable(&words, &mut sections)?;
+        if words == before {
+            return Ok(encode(&words, &sections));
+        }
+    }
+}
+
+// Remove only the unreachable code from magic-prefixed bytecode
+pub fn eliminate_dead_code(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut sections = split_program(bytes)?.0;
+    let words = isa::decode_program(bytes)?;
+    let words = remove_unreachable(&words, &mut sections)?;
+    Ok(encode(&words, &sections))
+}
+
+fn encode(words: &[u32], sections: &Sections) -> Vec<u8> {
+    let code: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    join_program(sections, &code)
+}
+
+// The offset of a word's branch operand, if it has one
+fn branch_offset(word: u32) -> Option<i32> {
+    let spec = isa::lookup(word)?;
+    spec.operands.iter().find(|field| field.branch).map(|field| field.extract(word))
+}
+
+// The same branch with a new offset; None if it doesn't fit the field
+fn with_branch_offset(word: u32, offset: i32) -> Option<u32> {
+    isa::lookup(word)?.encode(&[offset])
+}
+
+// Point branches that land on a goto straight at that goto's destination
+fn thread_branches(words: &mut [u32]) {
+    for index in 0..words.len() {
+        let addr = index as i64 * 4;
+        let Some(offset) = branch_offset(words[index]) else {
+            continue;
+        };
+        let mut target = addr + offset as i64;
+        // Follow at most one goto per word, so a ring of gotos can't loop forever
+        for _ in 0..words.len() {
+            let next = usize::try_from(target / 4)
+                .ok()
+                .filter(|_| target % 4 == 0)
+                .and_then(|slot| words.get(slot))
+                .filter(|&&word| isa::decode(word) == Some(Op::Goto))
+                .map(|&word| target + branch_offset(word).unwrap_or(0) as i64);
+            match next {
+                Some(next) if next != target => target = next,
+                _ => break,
+            }
+        }
+        // A branch to itself would fall through instead of looping
+        if target == addr {
+            continue;
+        }
+        let threaded = i32::try_from(target - addr).ok().and_then(|offset| with_branch_offset(words[index], offset));
+        if let Some(word) = threaded {
+            words[index] = word;
+        }
+    }
+}
+
+// Apply the sequence rules once, then move the code together
+fn peephole(words: &[u32], sections: &mut Sections) -> Result<Vec<u32>, String> {
+    let below_stack = words.iter().any(|&word| reads_below_stack(word));
+    let cfg = Cfg::build_with_entry(words, sections.entry);
+    let mut leader = vec![false; words.len()];
+    for block in cfg.blocks() {
+        leader[block.start / 4] = true;
+    }
+
+    // Each original word's replacement: itself, nothing, or new words
+    let mut replacements: Vec<Vec<u32>> = Vec::with_capacity(words.len());
+    let mut index = 0;
+    while index < words.len() {
+        // Only sequences whose later words no branch can reach
+        let fits = |len: usize| index + len <= words.len() && !leader[index + 1..index + len].contains(&true);
+        let rewrite = if fits(3) && !below_stack { fold_constants(&words[index..index + 3]) } else { None }
+            .map(|words| (3, words))
+            .or_else(|| {
+                if fits(2) { cancel_pair(words[index], words[index + 1], below_stack) } else { None }.map(|words| (2, words))
+            });
+        match rewrite {
+            Some((len, new_words)) => {
+                // Keep a word where a branch lands, or a branch just after the
+                // sequence back to its start would become a branch to itself
+                let new_words = if new_words.is_empty() && leader[index] { vec![Op::Nop.encode()] } else { new_words };
+                replacements.push(new_words);
+                replacements.extend((1..len).map(|_| Vec::new()));
+                index += len;
+            }
+            None => {
+                replacements.push(vec![words[index]]);
+                index += 1;
+            }
+        }
+    }
+    relocate(words, &replacements, sections)
+}
+
+// Drop every block the entry can't reach. Only dead code branches into
+// dead code, so the branches that remain all have somewhere to go.
+fn remove_unreachable(words: &[u32], sections: &mut Sections) -> Result<Vec<u32>, String> {
+    let cfg = Cfg::build_with_entry(words, sections.entry);
+    let mut live = vec![false; words.len()];
+    for id in cfg.reachable() {
+        let block = cfg.block(id);
+        live[block.start / 4..block.end / 4].fill(true);
+    }
+    let replacements: Vec<Vec<u32>> =
+        words.iter().zip(&live).map(|(&word, &live)| if live { vec![word] } else { Vec::new() }).collect();
+    relocate(words, &replacements, sections)
+}
+
+// push a; push b; op with both operands known
+fn fold_constants(sequence: &[u32]) -> Option<Vec<u32>> {
+    let push_value = |word: u32| (isa::decode(word) == Some(Op::Push)).then(|| Op::Push.spec().operands(word)[0]);
+    let (left, right) = (push_value(sequence[0])?, push_value(sequence[1])?);
+    let value = match isa::decode(sequence[2])? {
+        // Left alone when they overflow, as that faults under trapv
+        Op::Add => left.checked_add(right)?,
+        Op::Sub => left.checked_sub(right)?,
+        Op::Mul => left.checked_mul(right)?,
+        Op::And => left & right,
+        Op::Or => left | right,
+        Op::Xor => left ^ right,
+        _ => return None,
+    };
+    Some(vec![Op::Push.encode_with(&[value])?])
+}
+
+// Two instructions that undo each other, or a push dropped straight away
+fn cancel_pair(first: u32, second: u32, below_stack: bool) -> Option<Vec<u32>> {
+    match (isa::decode(first)?, isa::decode(second)?) {
+        (Op::Neg, Op::Neg) | (Op::Not, Op::Not) => Some(Vec::new()),
+        (Op::Push, Op::Pop) if !below_stack => match Op::Pop.spec().operands(second)[0] {
+            4 => Some(Vec::new()),
+            bytes if bytes > 4 => Some(vec![Op::Pop.encode_with(&[bytes - 4])?]),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The instruction addresses memory below the stack pointer, where popped
+// values are still readable, or may do
+fn reads_below_stack(word: u32) -> bool {
+    if matches!(isa::decode(word), Some(Op::Load | Op::Loadi | Op::Loadb | Op::Loadbi | Op::Memcpy | Op::Memcmp)) {
+        return true;
+    }
+    isa::lookup(word).is_some_and(|spec| {
+        spec.operands.iter().any(|field| field.signed && !field.branch && field.scale == 4 && field.extract(word) < 0)
+    })
+}
+
+// Lay out the replacements and re-encode every surviving branch for its new
+// address. Replacement words are never branches; targets inside a removed
+// sequence can only be its first word, which maps to whatever replaced it.
+fn relocate(words: &[u32], replacements: &[Vec<u32>], sections: &mut Sections) -> Result<Vec<u32>, String> {
+    let mut new_index = Vec::with_capacity(words.len() + 1);
+    let mut count = 0;
+    for replacement in replacements {
+        new_index.push(count);
+        count += replacement.len();
+    }
+    new_index.push(count);
+    let shrink = (words.len() - count) as i64 * 4;
+    let new_addr = |addr: i64| -> i64 {
+        match usize::try_from(addr / 4) {
+            Ok(slot) if addr % 4 == 0 && slot <= words.len() => new_index[slot] as i64 * 4,
+            _ if addr > words.len() as i64 * 4 => addr - shrink,
+            _ => addr,
+        }
+    };
+    // Symbols past the code name data, which stays where it is
+    let moved = |addr: usize| match replacements.get(addr / 4) {
+        Some(replacement) if replacement.is_empty() => None,
+        _ if addr > words.len() * 4 => Some(addr),
+        _ => usize::try_from(new_addr(addr as i64)).ok(),
+    };
+    sections.entry = moved(sections.entry).unwrap_or(0);
+    sections.symbols.relocate(moved);
+    sections.lines.relocate(moved);
+
+    let mut out = Vec::with_capacity(count);
+    for (index, replacement) in replacements.iter().enumerate() {
+        for &word in replacement {
+            let word = match branch_offset(word).filter(|_| replacement == &[words[index]]) {
+                Some(offset) => {
+                    let (old, new) = (index as i64 * 4, out.len() as i64 * 4);
+                    let offset = new_addr(old + offset as i64) - new;
+                    i32::try_from(offset)
+                        .ok()
+                        .and_then(|offset| with_branch_offset(word, offset))
+                        .ok_or_else(|| format!("can't re-encode the branch at 0x{:04x}", old))?
+                }
+                None => word,
+            };
+            out.push(word);
+        }
+    }
+    Ok(out)
+}
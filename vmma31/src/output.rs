@@ -0,0 +1,80 @@
+// Buffered guest output. Everything a program prints collects in one buffer,
+// and the flush policy decides when it's handed to the VM's VmIo (stdout by
+// default): batching writes matters for output-heavy programs, while
+// interactive ones want every byte shown immediately. Pending output is
+// always flushed before reading input, so prompts appear whatever the policy.
+
+use crate::vm_io::{StreamIo, VmIo};
+
+// Pending output is flushed once it reaches this size whatever the policy,
+// as a BufWriter would
+const BUFFER_CAPACITY: usize = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    Unbuffered,   // Flush after every write
+    Line,         // Flush when a write contains a newline
+    Bytes(usize), // Flush once this many bytes are pending
+    Exit,         // Flush only when input is read or the program ends
+}
+
+impl FlushPolicy {
+    // Parse the --flush argument: none, line, exit or a byte count
+    pub fn parse(text: &str) -> Option<FlushPolicy> {
+        match text {
+            "none" => Some(FlushPolicy::Unbuffered),
+            "line" => Some(FlushPolicy::Line),
+            "exit" => Some(FlushPolicy::Exit),
+            _ => text.parse().ok().filter(|&n| n > 0).map(FlushPolicy::Bytes),
+        }
+    }
+}
+
+pub(crate) struct Output {
+    io: Box<dyn VmIo>,
+    policy: FlushPolicy,
+    pending: String, // Written since the last flush
+}
+
+impl Output {
+    pub(crate) fn new(policy: FlushPolicy) -> Output {
+        Output { io: Box::new(StreamIo::default()), policy, pending: String::new() }
+    }
+
+    pub(crate) fn set_policy(&mut self, policy: FlushPolicy) {
+        self.policy = policy;
+    }
+
+    // Send output to io, and read input from it, from now on
+    pub(crate) fn set_io(&mut self, io: Box<dyn VmIo>) {
+        self.flush();
+        self.io = io;
+    }
+
+    pub(crate) fn write(&mut self, text: &str) {
+        self.pending.push_str(text);
+        let flush = match self.policy {
+            FlushPolicy::Unbuffered => true,
+            FlushPolicy::Line => text.contains('\n'),
+            FlushPolicy::Bytes(limit) => self.pending.len() >= limit,
+            FlushPolicy::Exit => false,
+        };
+        if flush || self.pending.len() >= BUFFER_CAPACITY {
+            self.flush();
+        }
+    }
+
+    pub(crate) fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            self.io.write(&self.pending);
+            self.pending.clear();
+        }
+        self.io.flush();
+    }
+
+    // A line of input, after showing any pending output
+    pub(crate) fn read_line(&mut self) -> String {
+        self.flush();
+        self.io.read_line()
+    }
+}
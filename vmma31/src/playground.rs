@@ -0,0 +1,34 @@
+// Entry point for running programs in a browser playground. Everything goes
+// through memory: the program and its input come in as bytes and text, the
+// output goes back as text, and nothing touches stdin, stdout or the clock,
+// so the interpreter builds for wasm32-unknown-unknown with this feature.
+// Runs are cut off after PLAYGROUND_FUEL instructions so a runaway loop
+// can't hang the page.
+
+use crate::testing::ScriptedIo;
+use crate::{FuelStop, VM};
+
+pub const PLAYGROUND_FUEL: u64 = 100_000_000;
+
+// Run a program file's bytes with the given input, one line per input read,
+// and return what it printed. Load errors and faults end the output with an
+// "Error: " line, as the CLI would print them.
+pub fn run_program(bytes: &[u8], input: &str) -> String {
+    let mut vm = match VM::from_bytes(bytes) {
+        Ok(vm) => vm,
+        Err(e) => return format!("Error: {}\n", e),
+    };
+    let io = ScriptedIo::new(input.lines());
+    vm.set_io(Box::new(io.clone()));
+    let error = match vm.run_with_fuel(PLAYGROUND_FUEL).stop {
+        FuelStop::Exited(_) => None,
+        FuelStop::Trapped(error) => Some(error.to_string()),
+        FuelStop::OutOfFuel => Some(format!("Stopped after {} instructions", PLAYGROUND_FUEL)),
+    };
+    vm.flush_output();
+    let mut output = io.output();
+    if let Some(error) = error {
+        output += &format!("Error: {}\n", error);
+    }
+    output
+}
@@ -0,0 +1,56 @@
+// Decoded copy of the loaded program, so the main loop doesn't run every word
+// through the decoder each time it executes. Entry i is the instruction at
+// address 4 * i, with branch operands already turned into target addresses.
+// Writes into the code region clear the entries they touch, and a cleared
+// entry is decoded again the next time it runs, so self-modifying programs
+// still see their changes.
+
+use crate::isa;
+use crate::{handler, Handler, VM};
+
+#[derive(Clone, Copy)]
+pub(crate) struct Decoded {
+    pub(crate) handler: Handler,
+    pub(crate) operands: [i32; 2], // As in InstrSpec::operands, but branches hold their target
+}
+
+impl VM {
+    // Decode the whole code region; called whenever it is replaced wholesale
+    pub(crate) fn predecode(&mut self) {
+        self.decoded = (0..self.code_size / 4).map(|index| decode_at(self.read_u32(index * 4), index * 4)).collect();
+    }
+
+    // The instruction at the PC, from the cache when it's there
+    pub(crate) fn decoded(&mut self, word: u32) -> Option<Decoded> {
+        let aligned = self.pc.is_multiple_of(4);
+        if let Some(Some(decoded)) = self.decoded.get(self.pc / 4).filter(|_| aligned) {
+            return Some(*decoded);
+        }
+        let decoded = decode_at(word, self.pc);
+        if let Some(slot) = self.decoded.get_mut(self.pc / 4).filter(|_| aligned) {
+            *slot = decoded;
+        }
+        decoded
+    }
+
+    // Forget the decoded form of any instruction overlapping the len bytes at addr
+    pub(crate) fn invalidate_code(&mut self, addr: usize, len: usize) {
+        if addr < self.code_size && len > 0 {
+            let last = (addr + len - 1) / 4;
+            for slot in self.decoded.iter_mut().take(last + 1).skip(addr / 4) {
+                *slot = None;
+            }
+        }
+    }
+}
+
+fn decode_at(word: u32, pc: usize) -> Option<Decoded> {
+    let spec = isa::lookup(word)?;
+    let mut operands = spec.operands(word);
+    for (value, field) in operands.iter_mut().zip(spec.operands) {
+        if field.branch {
+            *value = (pc as i32).wrapping_add(*value);
+        }
+    }
+    Some(Decoded { handler: handler(spec.op), operands })
+}
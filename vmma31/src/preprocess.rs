@@ -0,0 +1,281 @@
+// Macro expansion and includes for the assembler, done before either pass
+// sees the source. A macro is defined before its first use:
+//
+//   .macro print_at offset      name, then any parameters
+//     dup \offset               \param is replaced by the argument
+//     print 0
+//     pop 4
+//   .endm
+//
+//   print_at 8                  expands to the three lines above
+//
+// Arguments are separated like instruction operands (see
+// expr::split_operands), and a quoted string is one argument. `\@` is
+// replaced by a number that is different in every expansion, for labels
+// inside a macro (`loop\@:`). A macro can use other macros, but not define
+// them.
+//
+//   .include "lib.s"            the lines of lib.s, found next to this file
+//
+// Included files can include others, but not one that is already being
+// included. Their macros can be used after the .include.
+//
+// Every line out of here carries where it came from: its line in the
+// source and the line in each file and macro definition it went through,
+// so errors can point at all of them.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::asm::{is_label, split_label, strip_comment};
+use crate::expr::split_operands;
+use crate::isa;
+
+// How deep macros can use macros before it's taken to be runaway recursion
+const MAX_DEPTH: usize = 64;
+
+// Where a line is: its line in the source being assembled, then each file
+// included and macro used on the way to it, outermost first, with the line
+// in that file or macro definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Location {
+    pub line: usize,
+    pub within: Vec<(Within, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Within {
+    File(String),
+    Macro(String, Option<String>), // Name, and the file it's defined in unless the source
+}
+
+impl Location {
+    // The innermost file this line is in (None for the source itself) and
+    // its line there; a line from a macro is where the macro was used
+    pub fn source_line(&self) -> (Option<&str>, usize) {
+        let mut position = (None, self.line);
+        for (within, line) in &self.within {
+            match within {
+                Within::File(file) => position = (Some(file.as_str()), *line),
+                Within::Macro(..) => break,
+            }
+        }
+        position
+    }
+
+    fn inside(&self, within: Within, line: usize) -> Location {
+        let mut inner = self.clone();
+        inner.within.push((within, line));
+        inner
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}", self.line)?;
+        for (within, line) in &self.within {
+            match within {
+                Within::File(file) => write!(f, ", in {} at line {}", file, line)?,
+                Within::Macro(name, None) => write!(f, ", in macro {} at line {}", name, line)?,
+                Within::Macro(name, Some(file)) => write!(f, ", in macro {} at {} line {}", name, file, line)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// A line after expansion, without its comment
+pub(crate) struct SourceLine {
+    pub text: String,
+    pub at: Location,
+}
+
+struct Macro {
+    defined_at: Location,
+    file: Option<String>, // Defined in, unless the source
+    path: String,         // Of the file it's defined in, for includes
+    params: Vec<String>,
+    body: Vec<(usize, String)>,
+}
+
+struct Expander {
+    macros: HashMap<String, Macro>,
+    expansions: usize,       // For \@
+    including: Vec<PathBuf>, // Files being read, outermost first, to catch cycles
+    out: Vec<SourceLine>,
+}
+
+// The source read from file (empty when it has no name) with every macro
+// definition taken out and every use and include expanded. Includes are
+// found relative to the file's directory.
+pub(crate) fn expand(source: &str, file: &str) -> Result<Vec<SourceLine>, String> {
+    let mut expander = Expander { macros: HashMap::new(), expansions: 0, including: Vec::new(), out: Vec::new() };
+    expander.including.extend(fs::canonicalize(file).ok());
+    expander.lines(&numbered(source), None, file)?;
+    Ok(expander.out)
+}
+
+fn numbered(source: &str) -> Vec<(usize, String)> {
+    source.lines().enumerate().map(|(index, line)| (index + 1, strip_comment(line).trim().to_string())).collect()
+}
+
+impl Expander {
+    // Expand lines, which are inside parent (None for the source itself):
+    // an included file or the body of a macro used at the given location.
+    // path is the file they're from, for finding includes.
+    fn lines(&mut self, lines: &[(usize, String)], parent: Option<(&Location, Within)>, path: &str) -> Result<(), String> {
+        let at = |line: usize| match &parent {
+            None => Location { line, within: Vec::new() },
+            Some((used_at, within)) => used_at.inside(within.clone(), line),
+        };
+        // The file lines are in for macros defined here: None for the source
+        let file = match &parent {
+            None => None,
+            Some((_, Within::File(file))) => Some(file.clone()),
+            Some((_, Within::Macro(_, file))) => file.clone(),
+        };
+        let mut index = 0;
+        while index < lines.len() {
+            let (line_no, text) = &lines[index];
+            index += 1;
+            let mut rest = text.as_str();
+            while let Some((_, after)) = split_label(rest) {
+                rest = after;
+            }
+            let (first, after) = split_first_word(rest);
+            let words = split_operands(after);
+            if first == ".macro" {
+                let defined_at = at(*line_no);
+                if rest.len() != text.len() {
+                    return Err(format!("{}: a label can't go on a .macro line", defined_at));
+                }
+                let (name, params) = self.definition(words, &defined_at)?;
+                let end = lines[index..].iter().position(|(_, text)| split_first_word(text).0 == ".endm");
+                let Some(end) = end else {
+                    return Err(format!("{}: .macro {} has no .endm", defined_at, name));
+                };
+                if let Some((line, _)) = lines[index..index + end].iter().find(|(_, text)| split_first_word(text).0 == ".macro") {
+                    return Err(format!("{}: a macro can't be defined inside another", at(*line)));
+                }
+                let body = lines[index..index + end].to_vec();
+                self.macros.insert(name, Macro { defined_at, file: file.clone(), path: path.to_string(), params, body });
+                index += end + 1;
+            } else if first == ".endm" {
+                return Err(format!("{}: .endm without .macro", at(*line_no)));
+            } else if first == ".include" {
+                let included = match words[..] {
+                    [name] => name.strip_prefix('"').and_then(|name| name.strip_suffix('"')),
+                    _ => None,
+                };
+                let included = included.ok_or_else(|| format!("{}: .include takes a quoted file name", at(*line_no)))?;
+                self.include(included, at(*line_no), path)?;
+            } else if let Some(definition) = self.macros.get(first) {
+                let used_at = at(*line_no);
+                if words.len() != definition.params.len() {
+                    return Err(format!(
+                        "{}: macro {} takes {} argument(s), got {} (defined at {})",
+                        used_at,
+                        first,
+                        definition.params.len(),
+                        words.len(),
+                        definition.defined_at
+                    ));
+                }
+                let depth = used_at.within.iter().filter(|(within, _)| matches!(within, Within::Macro(..))).count();
+                if depth >= MAX_DEPTH {
+                    return Err(format!(
+                        "line {}: macros nested more than {} deep (does {}, defined at {}, use itself?)",
+                        used_at.line, MAX_DEPTH, first, definition.defined_at
+                    ));
+                }
+                // Labels before the macro name label the first line of its expansion
+                let labels = &text[..text.len() - rest.len()];
+                if !labels.is_empty() {
+                    self.out.push(SourceLine { text: labels.trim().to_string(), at: used_at.clone() });
+                }
+                self.expansions += 1;
+                let body: Vec<(usize, String)> = definition
+                    .body
+                    .iter()
+                    .map(|(line, text)| (*line, substitute(text, &definition.params, &words, self.expansions)))
+                    .collect();
+                let within = Within::Macro(first.to_string(), definition.file.clone());
+                let path = definition.path.clone();
+                self.lines(&body, Some((&used_at, within)), &path)?;
+            } else if !text.is_empty() {
+                self.out.push(SourceLine { text: text.clone(), at: at(*line_no) });
+            }
+        }
+        Ok(())
+    }
+
+    // Expand the file named name, included at the given location from the
+    // file at path
+    fn include(&mut self, name: &str, at: Location, path: &str) -> Result<(), String> {
+        let included = Path::new(path).parent().unwrap_or(Path::new("")).join(name);
+        let source = fs::read_to_string(&included).map_err(|e| format!("{}: can't read {}: {}", at, included.display(), e))?;
+        let canonical = fs::canonicalize(&included).unwrap_or_else(|_| included.clone());
+        if self.including.contains(&canonical) {
+            return Err(format!("{}: {} is already being included", at, included.display()));
+        }
+        self.including.push(canonical);
+        let file = included.to_string_lossy().into_owned();
+        self.lines(&numbered(&source), Some((&at, Within::File(file.clone()))), &file)?;
+        self.including.pop();
+        Ok(())
+    }
+
+    // The name and parameters after a .macro
+    fn definition(&self, words: Vec<&str>, at: &Location) -> Result<(String, Vec<String>), String> {
+        let Some((&name, params)) = words.split_first() else {
+            return Err(format!("{}: .macro needs a name", at));
+        };
+        if !is_label(name) || name.starts_with('.') || name.parse::<isa::Op>().is_ok() {
+            return Err(format!("{}: {:?} can't be a macro name", at, name));
+        }
+        if let Some(other) = self.macros.get(name) {
+            return Err(format!("{}: macro {} is already defined at {}", at, name, other.defined_at));
+        }
+        for (index, param) in params.iter().enumerate() {
+            if !param.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') || param.is_empty() {
+                return Err(format!("{}: invalid parameter name {:?}", at, param));
+            }
+            if params[..index].contains(param) {
+                return Err(format!("{}: duplicate parameter {:?}", at, param));
+            }
+        }
+        Ok((name.to_string(), params.iter().map(|param| param.to_string()).collect()))
+    }
+}
+
+// The first word of a line, such as its mnemonic, and the rest
+fn split_first_word(text: &str) -> (&str, &str) {
+    text.split_once(char::is_whitespace).unwrap_or((text, ""))
+}
+
+// A body line with each \param replaced by its argument and \@ by unique
+fn substitute(text: &str, params: &[String], arguments: &[&str], unique: usize) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(index) = rest.find('\\') {
+        out.push_str(&rest[..index]);
+        let after = &rest[index + 1..];
+        let len = after.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after.len());
+        if let Some(after) = after.strip_prefix('@') {
+            out.push_str(&unique.to_string());
+            rest = after;
+        } else if let Some(param) = params.iter().position(|param| *param == after[..len]) {
+            out.push_str(arguments[param]);
+            rest = &after[len..];
+        } else {
+            // Not a parameter, such as an escape in a string; `\\` is kept whole
+            let keep = if after.starts_with('\\') { 2 } else { 1 };
+            out.push_str(&rest[index..index + keep]);
+            rest = &rest[index + keep..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
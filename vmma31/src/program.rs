@@ -0,0 +1,540 @@
+// Program file layout. A file is the magic bytes, a header, optional
+// sections, then the code, which is loaded at address 0:
+//
+//   de ad be ef           MAGIC
+//   "HDR\n"               HEADER_TAG
+//   u32                   format version, FORMAT_VERSION
+//   u32                   entry point: the address execution starts at
+//   u32                   code length in bytes
+//   u32                   flags, from version 2 on (see below)
+//   u32                   CRC-32, only with the CHECKSUM flag
+//   "SYM\n"               SYMBOL_TAG, only when there are symbols
+//   u32                   length in bytes of the entries that follow
+//   u32 addr, u8 len, name   one entry per symbol, name in UTF-8
+//   "DBG\n"               DEBUG_TAG, only when there are source lines
+//   u32                   length in bytes of what follows
+//   u8 count, then count × (u8 len, name)   source file names
+//   u32 addr, u8 file, u32 line             one entry per instruction
+//   "DAT\n"               DATA_TAG, only when there's initialized data
+//   u32                   length in bytes of what follows
+//   u32 addr, u32 len, bytes   one entry per block, copied to addr on load
+//   "BSS\n"               BSS_TAG, only when there's zeroed data
+//   u32                   length in bytes of what follows
+//   u32 addr, u32 len          one entry per block, zeroed on load
+//   "VAR\n"               GLOBALS_TAG, only when there's a globals region
+//   u32                   length in bytes of what follows (8)
+//   u32 addr, u32 count        the region gload and gstore index, count
+//                              words from addr, zeroed on load
+//   ...code words...
+//
+// Numbers are little-endian. Files from before the header have the code
+// straight after MAGIC; they're read as version 0, starting at address 0.
+// Read as code words the tags are 0x0a524448, 0x0a4d5953, 0x0a474244,
+// 0x0a544144, 0x0a535342 and 0x0a524156, which are no instruction, so no
+// such file could start with them. Files from a later version are refused
+// rather than misread. Object files (see link.rs) have the same layout
+// under OBJECT_TAG instead of HEADER_TAG, and can't be loaded until linked.
+// Data and bss blocks and the globals region go after the code, wherever
+// the file says. The symbols and source lines don't affect running at all,
+// but the disassembler, tracer and debugger use them to name addresses and
+// show where an instruction came from.
+//
+// With the COMPRESSED flag, everything after the header is a zlib stream
+// that inflates to the sections and code; the code length is still that of
+// the inflated code. With CHECKSUM, the header ends with the CRC-32 (as in
+// zlib and PNG) of every other byte of the file, checked before anything
+// else is read, so a damaged or truncated file is refused outright. Files
+// are always written with it. RETURN_STACK says the program was written for
+// a separate return stack (see VM::set_return_stack), so call and return
+// leave the data stack alone.
+
+use std::borrow::Cow;
+
+use crate::{zlib, MAGIC, MAX_RAM_SIZE};
+
+pub const HEADER_TAG: [u8; 4] = *b"HDR\n";
+pub const FORMAT_VERSION: u32 = 2;
+const FLAGS_VERSION: u32 = 2; // The first version with the flags field
+pub const COMPRESSED: u32 = 1;
+pub const CHECKSUM: u32 = 2;
+pub const RETURN_STACK: u32 = 4;
+const KNOWN_FLAGS: u32 = COMPRESSED | CHECKSUM | RETURN_STACK;
+const HEADER_LEN: usize = 28; // MAGIC, tag, version, entry, code length, flags and checksum
+const CHECKSUM_AT: usize = HEADER_LEN - 4;
+pub const SYMBOL_TAG: [u8; 4] = *b"SYM\n";
+pub const DEBUG_TAG: [u8; 4] = *b"DBG\n";
+pub const DATA_TAG: [u8; 4] = *b"DAT\n";
+pub const BSS_TAG: [u8; 4] = *b"BSS\n";
+pub const GLOBALS_TAG: [u8; 4] = *b"VAR\n";
+pub const OBJECT_TAG: [u8; 4] = *b"OBJ\n";
+
+// Everything a program file holds besides its code
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sections {
+    pub entry: usize, // Where execution starts
+    pub symbols: Symbols,
+    pub lines: SourceLines,
+    pub data: Vec<(usize, Vec<u8>)>, // Initialized data: address and contents
+    pub bss: Vec<(usize, usize)>,    // Zeroed data: address and length in bytes
+    pub globals: Option<(usize, usize)>, // Globals region: address and length in words
+    pub return_stack: bool,          // Return addresses go on a separate stack (RETURN_STACK)
+}
+
+impl Sections {
+    // Check that the entry point is an instruction in code_len bytes of
+    // code; 0 is always allowed, so empty code can be loaded
+    pub fn check_entry(&self, code_len: usize) -> Result<(), String> {
+        if !self.entry.is_multiple_of(4) || (self.entry >= code_len && self.entry != 0) {
+            return Err(format!("Entry point 0x{:x} is not an instruction in the code", self.entry));
+        }
+        Ok(())
+    }
+
+    // Each data and bss block as (kind, address, length), in file order,
+    // then the globals region
+    pub fn blocks(&self) -> impl Iterator<Item = (&'static str, usize, usize)> + '_ {
+        let data = self.data.iter().map(|(addr, bytes)| ("data", *addr, bytes.len()));
+        let globals = self.globals.map(|(addr, count)| ("globals region", addr, count.saturating_mul(4)));
+        data.chain(self.bss.iter().map(|&(addr, len)| ("bss", addr, len))).chain(globals)
+    }
+
+    // Where the heap starts: the first word after code_len bytes of code
+    // and every block
+    pub fn heap_start(&self, code_len: usize) -> usize {
+        let end = self.blocks().map(|(_, addr, len)| addr.saturating_add(len)).fold(code_len, usize::max);
+        end.next_multiple_of(4)
+    }
+
+    // Check that every block lies after code_len bytes of code
+    // and inside memory_size bytes of memory
+    pub fn check_blocks(&self, code_len: usize, memory_size: usize) -> Result<(), String> {
+        for (kind, addr, len) in self.blocks() {
+            if addr < code_len {
+                return Err(format!("The {} at 0x{:x} overlaps the code", kind, addr));
+            }
+            if addr.checked_add(len).is_none_or(|end| end > memory_size) {
+                return Err(format!("The {} at 0x{:x} doesn't fit in memory", kind, addr));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Names for code addresses, ordered by address. An address can have more
+// than one name; the first one given is the one shown.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    entries: Vec<(usize, String)>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn insert(&mut self, name: &str, addr: usize) {
+        let index = self.entries.partition_point(|(a, _)| *a <= addr);
+        self.entries.insert(index, (addr, name.to_string()));
+    }
+
+    // The name shown for addr
+    pub fn name_at(&self, addr: usize) -> Option<&str> {
+        let index = self.entries.partition_point(|(a, _)| *a < addr);
+        self.entries.get(index).filter(|(a, _)| *a == addr).map(|(_, name)| name.as_str())
+    }
+
+    // The nearest symbol at or before addr, with its address
+    pub fn containing(&self, addr: usize) -> Option<(usize, &str)> {
+        let index = self.entries.partition_point(|(a, _)| *a <= addr);
+        let start = self.entries[..index].last()?.0;
+        Some((start, self.name_at(start)?))
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<usize> {
+        self.entries.iter().find(|(_, n)| n == name).map(|(addr, _)| *addr)
+    }
+
+    // (address, name) pairs in address order
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.entries.iter().map(|(addr, name)| (*addr, name.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Move every symbol to a new address after code has been rearranged,
+    // dropping those whose code is gone (new_addr gives None)
+    pub(crate) fn relocate(&mut self, new_addr: impl Fn(usize) -> Option<usize>) {
+        self.entries = self.entries.drain(..).filter_map(|(addr, name)| Some((new_addr(addr)?, name))).collect();
+        self.entries.sort_by_key(|(addr, _)| *addr);
+    }
+}
+
+// The source file and line each instruction was assembled from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceLines {
+    files: Vec<String>,
+    entries: Vec<(usize, usize, u32)>, // Address, index into files, line
+}
+
+impl SourceLines {
+    pub fn new() -> SourceLines {
+        SourceLines::default()
+    }
+
+    // Note that the instruction at addr comes from line (counting from 1) of file
+    pub fn insert(&mut self, addr: usize, file: &str, line: u32) {
+        let file = match self.files.iter().position(|f| f == file) {
+            Some(index) => index,
+            None => {
+                self.files.push(file.to_string());
+                self.files.len() - 1
+            }
+        };
+        let index = self.entries.partition_point(|(a, _, _)| *a < addr);
+        match self.entries.get_mut(index) {
+            Some(entry) if entry.0 == addr => *entry = (addr, file, line),
+            _ => self.entries.insert(index, (addr, file, line)),
+        }
+    }
+
+    // The file and line of the instruction at addr
+    pub fn at(&self, addr: usize) -> Option<(&str, u32)> {
+        let index = self.entries.partition_point(|(a, _, _)| *a < addr);
+        self.entries.get(index).filter(|(a, _, _)| *a == addr).map(|&(_, file, line)| (self.files[file].as_str(), line))
+    }
+
+    // (address, file, line) in address order
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str, u32)> {
+        self.entries.iter().map(|&(addr, file, line)| (addr, self.files[file].as_str(), line))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Like Symbols::relocate
+    pub(crate) fn relocate(&mut self, new_addr: impl Fn(usize) -> Option<usize>) {
+        self.entries = self.entries.drain(..).filter_map(|(addr, file, line)| Some((new_addr(addr)?, file, line))).collect();
+        self.entries.sort_by_key(|(addr, _, _)| *addr);
+        self.entries.dedup_by_key(|(addr, _, _)| *addr);
+    }
+}
+
+// Split a program file into its sections and its code, inflating the code
+// of a compressed file
+pub fn split_program(bytes: &[u8]) -> Result<(Sections, Cow<'_, [u8]>), String> {
+    let (sections, _, code) = split_file(bytes, HEADER_TAG, &[])?;
+    Ok((sections, code))
+}
+
+// A program file with everything after the header compressed
+pub fn compress_program(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let (sections, code) = split_program(bytes)?;
+    let plain = join_program(&sections, &code);
+    let mut out = header(HEADER_TAG, &sections, code.len(), COMPRESSED | CHECKSUM);
+    out.extend_from_slice(&zlib::compress(&plain[HEADER_LEN..]));
+    seal(&mut out);
+    Ok(out)
+}
+
+// The start of a file up to its sections, with the checksum left as 0.
+// Flags the sections call for are added to flags.
+fn header(header_tag: [u8; 4], sections: &Sections, code_len: usize, mut flags: u32) -> Vec<u8> {
+    if sections.return_stack {
+        flags |= RETURN_STACK;
+    }
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&header_tag);
+    for value in [FORMAT_VERSION, sections.entry as u32, code_len as u32, flags, 0] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes
+}
+
+// Fill in the checksum of a finished file
+fn seal(bytes: &mut [u8]) {
+    let checksum = file_checksum(bytes);
+    bytes[CHECKSUM_AT..HEADER_LEN].copy_from_slice(&checksum.to_le_bytes());
+}
+
+// The CRC-32 of a file, leaving out the checksum itself
+fn file_checksum(bytes: &[u8]) -> u32 {
+    !crc32_update(crc32_update(!0, &bytes[..CHECKSUM_AT]), &bytes[HEADER_LEN..])
+}
+
+// CRC-32 with the reflected polynomial 0xedb88320, as in zlib
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, &byte| CRC_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8))
+}
+
+// A section's tag and body
+pub(crate) type RawSection<'a> = ([u8; 4], &'a [u8]);
+
+// A file's sections, its other sections and its code, as split_file gives them
+pub(crate) type SplitFile<'a> = (Sections, Vec<RawSection<'a>>, Cow<'a, [u8]>);
+
+// Split a file with the given header tag into its sections, the bodies of
+// any sections tagged with one of other_tags, and its code. Only program
+// files (HEADER_TAG) can leave the header out.
+pub(crate) fn split_file<'a>(
+    bytes: &'a [u8],
+    header_tag: [u8; 4],
+    other_tags: &[[u8; 4]],
+) -> Result<SplitFile<'a>, String> {
+    let (magic, mut rest) = match bytes.split_first_chunk::<4>() {
+        Some(split) => split,
+        None => return Err("Failed to read magic bytes: file too short".to_string()),
+    };
+    if *magic != MAGIC {
+        return Err(format!("Invalid magic bytes: {:?}", magic));
+    }
+    if header_tag == HEADER_TAG && rest.starts_with(&OBJECT_TAG) {
+        return Err("This is an object file; link it into a program with `vmma31 link` first".to_string());
+    }
+    if header_tag != HEADER_TAG && !rest.starts_with(&header_tag) {
+        return Err("Not an object file".to_string());
+    }
+    let mut sections = Sections::default();
+    let mut others = Vec::new();
+    let mut code_length = None;
+    if let Some(after) = rest.strip_prefix(&header_tag) {
+        let mut reader = Reader { bytes: after, what: "header" };
+        let version = reader.u32()?;
+        if version > FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported program format version {} (this build reads up to version {})",
+                version, FORMAT_VERSION
+            ));
+        }
+        sections.entry = reader.u32()? as usize;
+        code_length = Some(reader.u32()? as usize);
+        let flags = if version >= FLAGS_VERSION { reader.u32()? } else { 0 };
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(format!("Unsupported header flags 0x{:x}", flags & !KNOWN_FLAGS));
+        }
+        sections.return_stack = flags & RETURN_STACK != 0;
+        if flags & CHECKSUM != 0 {
+            let expected = reader.u32()?;
+            let actual = file_checksum(bytes);
+            if actual != expected {
+                return Err(format!(
+                    "File corrupted: checksum is {:08x} but the header says {:08x} (damaged or truncated?)",
+                    actual, expected
+                ));
+            }
+        }
+        rest = reader.bytes;
+        if flags & COMPRESSED != 0 {
+            if header_tag != HEADER_TAG {
+                return Err("Object files can't be compressed".to_string());
+            }
+            // The payload as a whole must fit in the largest memory, so a small
+            // file can't inflate to gigabytes before anything is checked
+            let inflated = zlib::decompress(rest, MAX_RAM_SIZE).map_err(|e| format!("Invalid compressed payload: {}", e))?;
+            let code = read_sections(&inflated, &mut sections, &[], &mut Vec::new())?;
+            check_code_length(code_length, code)?;
+            return Ok((sections, Vec::new(), Cow::Owned(code.to_vec())));
+        }
+    }
+    let code = read_sections(rest, &mut sections, other_tags, &mut others)?;
+    check_code_length(code_length, code)?;
+    Ok((sections, others, Cow::Borrowed(code)))
+}
+
+fn check_code_length(code_length: Option<usize>, code: &[u8]) -> Result<(), String> {
+    match code_length {
+        Some(length) if length != code.len() => {
+            Err(format!("Invalid header: code length {} but {} bytes of code follow", length, code.len()))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Read the sections at the start of rest into sections, and those tagged
+// with one of other_tags into others; what's left is the code
+fn read_sections<'a>(
+    mut rest: &'a [u8],
+    sections: &mut Sections,
+    other_tags: &[[u8; 4]],
+    others: &mut Vec<RawSection<'a>>,
+) -> Result<&'a [u8], String> {
+    while let Some((tag, after)) = rest.split_first_chunk::<4>() {
+        let what = match *tag {
+            SYMBOL_TAG => "symbol section",
+            DEBUG_TAG => "debug section",
+            DATA_TAG => "data section",
+            BSS_TAG => "bss section",
+            GLOBALS_TAG => "globals section",
+            tag if other_tags.contains(&tag) => "section",
+            _ => break,
+        };
+        let mut reader = Reader { bytes: after, what };
+        let length = reader.u32()? as usize;
+        let body = reader.take(length)?;
+        rest = reader.bytes;
+        let mut reader = Reader { bytes: body, what };
+        if other_tags.contains(tag) {
+            others.push((*tag, body));
+        } else if *tag == SYMBOL_TAG {
+            while !reader.bytes.is_empty() {
+                let addr = reader.u32()? as usize;
+                let name = reader.name()?;
+                sections.symbols.insert(name, addr);
+            }
+        } else if *tag == DATA_TAG {
+            while !reader.bytes.is_empty() {
+                let addr = reader.u32()? as usize;
+                let len = reader.u32()? as usize;
+                sections.data.push((addr, reader.take(len)?.to_vec()));
+            }
+        } else if *tag == BSS_TAG {
+            while !reader.bytes.is_empty() {
+                let addr = reader.u32()? as usize;
+                sections.bss.push((addr, reader.u32()? as usize));
+            }
+        } else if *tag == GLOBALS_TAG {
+            let addr = reader.u32()? as usize;
+            sections.globals = Some((addr, reader.u32()? as usize));
+        } else {
+            let files = (0..reader.u8()?).map(|_| reader.name()).collect::<Result<Vec<&str>, String>>()?;
+            while !reader.bytes.is_empty() {
+                let addr = reader.u32()? as usize;
+                let file = files.get(reader.u8()? as usize).ok_or("Invalid debug section: no such file")?;
+                let line = reader.u32()?;
+                sections.lines.insert(addr, file, line);
+            }
+        }
+    }
+    Ok(rest)
+}
+
+// Reads little-endian fields from the front of bytes, naming what they're
+// part of in errors
+pub(crate) struct Reader<'a> {
+    pub bytes: &'a [u8],
+    pub what: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.bytes.len() {
+            return Err(format!("Invalid {}: truncated", self.what));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    // A name with a one-byte length
+    pub fn name(&mut self) -> Result<&'a str, String> {
+        let len = self.u8()? as usize;
+        let bytes = self.take(len)?;
+        std::str::from_utf8(bytes).map_err(|_| format!("Invalid {}: name isn't UTF-8", self.what))
+    }
+}
+
+// Build a program file from code and its sections, leaving out the empty
+// ones. Names longer than 255 bytes are cut short, and only the first 255
+// source files are recorded.
+pub fn join_program(sections: &Sections, code: &[u8]) -> Vec<u8> {
+    join_file(HEADER_TAG, sections, &[], code)
+}
+
+// Like join_program, with another header tag and extra sections before the code
+pub(crate) fn join_file(header_tag: [u8; 4], sections: &Sections, others: &[([u8; 4], Vec<u8>)], code: &[u8]) -> Vec<u8> {
+    let mut bytes = header(header_tag, sections, code.len(), CHECKSUM);
+    if !sections.symbols.is_empty() {
+        let mut body = Vec::new();
+        for (addr, name) in sections.symbols.iter() {
+            body.extend_from_slice(&(addr as u32).to_le_bytes());
+            push_name(&mut body, name);
+        }
+        push_section(&mut bytes, SYMBOL_TAG, &body);
+    }
+    let lines = &sections.lines;
+    if !lines.is_empty() {
+        let files = lines.files.len().min(u8::MAX as usize);
+        let mut body = vec![files as u8];
+        for file in &lines.files[..files] {
+            push_name(&mut body, file);
+        }
+        for &(addr, file, line) in lines.entries.iter().filter(|(_, file, _)| *file < files) {
+            body.extend_from_slice(&(addr as u32).to_le_bytes());
+            body.push(file as u8);
+            body.extend_from_slice(&line.to_le_bytes());
+        }
+        push_section(&mut bytes, DEBUG_TAG, &body);
+    }
+    if !sections.data.is_empty() {
+        let mut body = Vec::new();
+        for (addr, data) in &sections.data {
+            body.extend_from_slice(&(*addr as u32).to_le_bytes());
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+        }
+        push_section(&mut bytes, DATA_TAG, &body);
+    }
+    if !sections.bss.is_empty() {
+        let mut body = Vec::new();
+        for &(addr, len) in &sections.bss {
+            body.extend_from_slice(&(addr as u32).to_le_bytes());
+            body.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+        push_section(&mut bytes, BSS_TAG, &body);
+    }
+    if let Some((addr, count)) = sections.globals {
+        let body: Vec<u8> = [addr as u32, count as u32].iter().flat_map(|value| value.to_le_bytes()).collect();
+        push_section(&mut bytes, GLOBALS_TAG, &body);
+    }
+    for (tag, body) in others {
+        push_section(&mut bytes, *tag, body);
+    }
+    bytes.extend_from_slice(code);
+    seal(&mut bytes);
+    bytes
+}
+
+fn push_section(bytes: &mut Vec<u8>, tag: [u8; 4], body: &[u8]) {
+    bytes.extend_from_slice(&tag);
+    bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(body);
+}
+
+pub(crate) fn push_name(bytes: &mut Vec<u8>, name: &str) {
+    let mut end = name.len().min(u8::MAX as usize);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    bytes.push(end as u8);
+    bytes.extend_from_slice(&name.as_bytes()[..end]);
+}
@@ -0,0 +1,25 @@
+// Shadow stack of return addresses, kept by the host where guest code can't
+// reach it. Every call records its return address; every return checks the
+// address it pops against the record and faults on a mismatch, which
+// catches unbalanced pushes and pops around calls. On by default;
+// --no-shadow-stack turns it off for programs that use return as a computed
+// jump.
+
+use crate::{VmError, VM};
+
+impl VM {
+    pub(crate) fn shadow_call(&mut self, return_addr: u32) {
+        self.shadow_stack.push(return_addr);
+    }
+
+    // Check the address `return` popped, raising a fault on a mismatch.
+    // Returns whether execution can continue.
+    pub(crate) fn shadow_return(&mut self, pc: usize, popped: u32) -> bool {
+        let expected = self.shadow_stack.pop();
+        if expected == Some(popped) {
+            return true;
+        }
+        self.raise(VmError::ShadowStackMismatch { pc, popped, expected });
+        false
+    }
+}
@@ -0,0 +1,158 @@
+// Snapshots of a run's state, so it can be stopped, saved and resumed later:
+// memory and its size, registers, exit status, the heap, the other guest
+// processes, call tracking and counters. Settings chosen by the host (strict
+// mode, regex access, the flush policy and so on) aren't state; set them
+// again after restoring. Two modes are: return stack mode, as the shadow
+// stack then holds the only return addresses, and the overflow trap, which
+// programs change with trapv.
+//
+// With the snapshot feature, snapshots serialize with serde, and save/load
+// write them as JSON for --snapshot-out and --resume.
+
+#[cfg(feature = "snapshot")]
+use std::fs;
+
+#[cfg(feature = "snapshot")]
+use serde::{Deserialize, Serialize};
+
+use crate::conventions::CallFrame;
+use crate::guest::Process;
+use crate::heap::Heap;
+use crate::{Telemetry, VmError, MAX_RAM_SIZE, VM};
+
+#[derive(Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct VmSnapshot {
+    memory: Vec<u8>,
+    pc: usize,
+    sp: usize,
+    #[cfg_attr(feature = "snapshot", serde(default))]
+    fp: usize, // 0 in snapshots from before enter and leave, which no program could use then
+    #[cfg_attr(feature = "snapshot", serde(default))]
+    flags: u32, // 0 in snapshots from before cmp
+    exited: bool,
+    exit_code: i32,
+    code_size: usize,
+    #[cfg_attr(feature = "snapshot", serde(default))]
+    globals: (usize, usize), // (0, 0) in snapshots from before the globals region
+    #[cfg_attr(feature = "snapshot", serde(default))]
+    heap: Heap, // Empty in snapshots from before alloc and free
+    fault: Option<VmError>,
+    call_frames: Vec<CallFrame>,
+    shadow_stack: Vec<u32>,
+    #[cfg_attr(feature = "snapshot", serde(default))]
+    return_stack: bool,
+    #[cfg_attr(feature = "snapshot", serde(default))]
+    trap_overflow: bool, // false in snapshots from before trapv
+    pid: u32,
+    parent_pid: u32,
+    next_pid: u32,
+    processes: Vec<Process>,
+    telemetry: Telemetry,
+    instructions_executed: u64,
+    branches_taken: u64,
+    input_bytes: u64,
+}
+
+impl VM {
+    pub fn snapshot(&self) -> VmSnapshot {
+        VmSnapshot {
+            memory: self.memory.clone(),
+            pc: self.pc,
+            sp: self.sp,
+            fp: self.fp,
+            flags: self.flags,
+            exited: self.exited,
+            exit_code: self.exit_code,
+            code_size: self.code_size,
+            globals: self.globals,
+            heap: self.heap.clone(),
+            fault: self.fault.clone(),
+            call_frames: self.call_frames.clone(),
+            shadow_stack: self.shadow_stack.clone(),
+            return_stack: self.return_stack,
+            trap_overflow: self.trap_overflow,
+            pid: self.pid,
+            parent_pid: self.parent_pid,
+            next_pid: self.next_pid,
+            processes: self.processes.clone(),
+            telemetry: self.telemetry,
+            instructions_executed: self.instructions_executed,
+            branches_taken: self.branches_taken,
+            input_bytes: self.input_bytes,
+        }
+    }
+
+    // Put the VM back in the state the snapshot was taken in. A snapshot
+    // whose registers point outside its memory is refused, leaving the VM as
+    // it was.
+    pub fn restore(&mut self, snapshot: &VmSnapshot) -> Result<(), String> {
+        snapshot.check()?;
+        self.memory = snapshot.memory.clone();
+        self.pc = snapshot.pc;
+        self.sp = snapshot.sp;
+        self.fp = snapshot.fp;
+        self.flags = snapshot.flags;
+        self.exited = snapshot.exited;
+        self.exit_code = snapshot.exit_code;
+        self.code_size = snapshot.code_size;
+        self.predecode();
+        self.globals = snapshot.globals;
+        self.heap = snapshot.heap.clone();
+        self.fault = snapshot.fault.clone();
+        self.call_frames = snapshot.call_frames.clone();
+        self.shadow_stack = snapshot.shadow_stack.clone();
+        self.return_stack = snapshot.return_stack;
+        self.trap_overflow = snapshot.trap_overflow;
+        self.pid = snapshot.pid;
+        self.parent_pid = snapshot.parent_pid;
+        self.next_pid = snapshot.next_pid;
+        self.processes = snapshot.processes.clone();
+        self.telemetry = snapshot.telemetry;
+        self.instructions_executed = snapshot.instructions_executed;
+        self.branches_taken = snapshot.branches_taken;
+        self.input_bytes = snapshot.input_bytes;
+        Ok(())
+    }
+}
+
+impl VmSnapshot {
+    // What CoreDump::from_bytes checks of a core, for a snapshot that may have
+    // been edited by hand or damaged
+    fn check(&self) -> Result<(), String> {
+        let len = self.memory.len();
+        if len == 0 || !len.is_multiple_of(4) || len > MAX_RAM_SIZE {
+            return Err(format!("Invalid snapshot: {} bytes of memory", len));
+        }
+        if self.code_size > len || self.sp > len || self.pc > len {
+            return Err("Invalid snapshot: registers outside memory".to_string());
+        }
+        let (addr, words) = self.globals;
+        if words.checked_mul(4).and_then(|bytes| bytes.checked_add(addr)).is_none_or(|end| end > len) {
+            return Err("Invalid snapshot: globals region outside memory".to_string());
+        }
+        if !self.processes.iter().all(|process| process.fits(len)) {
+            return Err("Invalid snapshot: a process doesn't match its memory".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "snapshot")]
+impl VmSnapshot {
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, text + "\n").map_err(|e| format!("Failed to write snapshot: {}", e))
+    }
+
+    pub fn load(path: &str) -> Result<VmSnapshot, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Failed to read snapshot: {}", e))?;
+        VmSnapshot::from_bytes(&bytes).map_err(|e| format!("Invalid snapshot {}: {}", path, e))
+    }
+
+    // A snapshot from the contents of a file save wrote, for callers that
+    // have already read it (stdin can only be read once)
+    pub fn from_bytes(bytes: &[u8]) -> Result<VmSnapshot, String> {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+}
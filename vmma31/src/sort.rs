@@ -0,0 +1,38 @@
+// Sort syscall: a trusted host sort over words in VM memory, so guest
+// sorting exercises have a baseline to compare against.
+
+use crate::VM;
+
+pub const SORT_WORDS: u32 = 0x40;
+
+const SORT_DESCENDING: u32 = 0x1;
+const SORT_UNSIGNED: u32 = 0x2;
+
+impl VM {
+    // Pops [flags] [count] [addr] (top first) and sorts the count words at
+    // addr in place. Flag bit 0 sorts descending, bit 1 compares unsigned.
+    // Pushes 0 on success or -1 if the region is out of bounds.
+    pub(crate) fn exec_sort(&mut self) {
+        let flags = self.pop();
+        let count = self.pop() as usize;
+        let addr = self.pop() as usize;
+
+        let end = match count.checked_mul(4).and_then(|len| addr.checked_add(len)) {
+            Some(end) if end <= self.memory.len() => end,
+            _ => return self.push(-1i32 as u32),
+        };
+        let mut words: Vec<u32> = (addr..end).step_by(4).map(|a| self.read_u32(a)).collect();
+        if flags & SORT_UNSIGNED != 0 {
+            words.sort_unstable();
+        } else {
+            words.sort_unstable_by_key(|&w| w as i32);
+        }
+        if flags & SORT_DESCENDING != 0 {
+            words.reverse();
+        }
+        for (i, &word) in words.iter().enumerate() {
+            self.write_u32(addr + i * 4, word);
+        }
+        self.push(0);
+    }
+}
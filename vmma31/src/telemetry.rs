@@ -0,0 +1,37 @@
+// High-water marks collected while a program runs, so users can see how
+// close it came to the memory limits. Reported by --stats.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub struct Telemetry {
+    pub stack_top: usize,  // Where the stack starts: the size of memory
+    pub lowest_sp: usize,  // Lowest stack pointer reached
+    pub output_bytes: u64, // Bytes written by print, stprint and dump
+    #[cfg_attr(feature = "snapshot", serde(default))]
+    pub heap_top: usize,   // Highest end of the heap reached; 0 if nothing was allocated
+}
+
+impl Telemetry {
+    pub(crate) fn new(stack_top: usize) -> Telemetry {
+        Telemetry { stack_top, lowest_sp: stack_top, output_bytes: 0, heap_top: 0 }
+    }
+
+    // Deepest the stack got, in words
+    pub fn max_stack_depth(&self) -> usize {
+        (self.stack_top - self.lowest_sp) / 4
+    }
+}
+
+impl fmt::Display for Telemetry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "max stack depth: {} words", self.max_stack_depth())?;
+        writeln!(f, "lowest sp:       0x{:x}", self.lowest_sp)?;
+        write!(f, "output:          {} bytes", self.output_bytes)?;
+        if self.heap_top != 0 {
+            write!(f, "\nheap top:        0x{:x}", self.heap_top)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+// Helpers for testing bytecode from Rust without spawning the CLI.
+// ScriptedIo feeds a VM fixed input lines and collects everything it prints;
+// run_scripted and the assert_ functions wrap a whole run:
+//
+//   let program = std::fs::read("add.v").unwrap();
+//   assert_program_exit(&program, &["5", "7"], 0);
+//   assert_program_output(&program, &["5", "7"], "Welcome to the number adder!\n\
+//       Enter left: Enter right: Result = 12\n");
+//
+// Runs stop after TEST_FUEL instructions, so a program stuck in a loop
+// fails its test instead of hanging it.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::{FuelStop, VmError, VmIo, VM};
+
+pub const TEST_FUEL: u64 = 100_000_000;
+
+// Input lines handed out one per read, and the output written so far.
+// Clones share both, so keep a clone to inspect after passing one to set_io.
+#[derive(Clone, Default)]
+pub struct ScriptedIo {
+    input: Rc<RefCell<VecDeque<String>>>,
+    output: Rc<RefCell<String>>,
+}
+
+impl ScriptedIo {
+    // Lines are given without their newline; reads past the last one get
+    // an empty line, as at the end of stdin
+    pub fn new<I, S>(lines: I) -> ScriptedIo
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let input = lines.into_iter().map(|line| line.into() + "\n").collect();
+        ScriptedIo { input: Rc::new(RefCell::new(input)), output: Rc::default() }
+    }
+
+    // Everything written so far, once the VM's output has been flushed
+    pub fn output(&self) -> String {
+        self.output.borrow().clone()
+    }
+
+    // Input lines not read yet
+    pub fn remaining_input(&self) -> usize {
+        self.input.borrow().len()
+    }
+}
+
+impl VmIo for ScriptedIo {
+    fn read_line(&mut self) -> String {
+        self.input.borrow_mut().pop_front().unwrap_or_default()
+    }
+
+    fn write(&mut self, text: &str) {
+        self.output.borrow_mut().push_str(text);
+    }
+}
+
+// What a scripted run printed and how it ended
+#[derive(Debug)]
+pub struct ProgramRun {
+    pub output: String,
+    pub result: Result<i32, VmError>, // Exit code, or the fault that stopped it
+}
+
+// Load a program file's bytes, run it on the given input lines and collect
+// its output. Panics if the program doesn't load or doesn't finish within
+// TEST_FUEL instructions.
+pub fn run_scripted(bytes: &[u8], inputs: &[&str]) -> ProgramRun {
+    let mut vm = VM::from_bytes(bytes).unwrap_or_else(|e| panic!("program didn't load: {}", e));
+    let io = ScriptedIo::new(inputs.iter().copied());
+    vm.set_io(Box::new(io.clone()));
+    let result = match vm.run_with_fuel(TEST_FUEL).stop {
+        FuelStop::Exited(code) => Ok(code),
+        FuelStop::Trapped(error) => Err(error),
+        FuelStop::OutOfFuel => panic!("program didn't finish within {} instructions", TEST_FUEL),
+    };
+    vm.flush_output();
+    ProgramRun { output: io.output(), result }
+}
+
+// Check that the program runs to completion without faulting and prints
+// exactly expected
+#[track_caller]
+pub fn assert_program_output(bytes: &[u8], inputs: &[&str], expected: &str) {
+    let run = run_scripted(bytes, inputs);
+    if let Err(error) = &run.result {
+        panic!("program faulted: {}\noutput: {:?}", error, run.output);
+    }
+    assert_eq!(run.output, expected, "program output differs");
+}
+
+// Check that the program exits with code, without faulting
+#[track_caller]
+pub fn assert_program_exit(bytes: &[u8], inputs: &[&str], code: i32) {
+    let run = run_scripted(bytes, inputs);
+    match run.result {
+        Ok(exit_code) => assert_eq!(exit_code, code, "exit code differs\noutput: {:?}", run.output),
+        Err(error) => panic!("program faulted: {}\noutput: {:?}", error, run.output),
+    }
+}
+
+// Check that the program stops with a fault of the given kind ("stack_underflow",
+// "divide_by_zero" and so on, as VmError::kind names them)
+#[track_caller]
+pub fn assert_program_fault(bytes: &[u8], inputs: &[&str], kind: &str) {
+    let run = run_scripted(bytes, inputs);
+    match run.result {
+        Ok(code) => panic!("program exited with {} instead of faulting\noutput: {:?}", code, run.output),
+        Err(error) => assert_eq!(error.kind(), kind, "fault differs: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble_program;
+
+    // Reads two numbers, prints each and exits with 3
+    const ECHO: &str = "input\nprint 0\ninput\nprint 0\nexit 3\n";
+
+    #[test]
+    fn output_is_captured() {
+        let program = assemble_program(ECHO).unwrap();
+        assert_program_output(&program, &["5", "-7"], "5\n-7\n");
+        assert_program_exit(&program, &["5", "-7"], 3);
+    }
+
+    #[test]
+    fn reads_past_the_input_get_empty_lines() {
+        let program = assemble_program(ECHO).unwrap();
+        assert_program_output(&program, &["5"], "5\n0\n");
+        assert_program_output(&program, &[], "0\n0\n");
+
+        let io = ScriptedIo::new(["1", "2", "3"]);
+        let mut vm = VM::from_bytes(&program).unwrap();
+        vm.set_io(Box::new(io.clone()));
+        assert_eq!(vm.run(), Ok(3));
+        vm.flush_output();
+        assert_eq!(io.output(), "1\n2\n");
+        assert_eq!(io.remaining_input(), 1);
+    }
+
+    #[test]
+    fn faults_are_reported_by_kind() {
+        let program = assemble_program("push 1\npush 0\ndiv\nexit 0\n").unwrap();
+        assert_program_fault(&program, &[], "divide_by_zero");
+        let run = run_scripted(&program, &[]);
+        assert!(matches!(run.result, Err(VmError::DivideByZero { pc: 8 })), "{:?}", run.result);
+    }
+
+    #[test]
+    #[should_panic(expected = "program output differs")]
+    fn different_output_fails() {
+        let program = assemble_program(ECHO).unwrap();
+        assert_program_output(&program, &["5", "7"], "5\n8\n");
+    }
+}
@@ -0,0 +1,138 @@
+// Instruction trace for --trace: one line per executed instruction. The text
+// format shows its address, encoding and disassembly, SP before and after,
+// and the word left on top of the stack ("-" when the stack is empty):
+//
+//   0008: 50000010  call +16             sp 0ff8 -> 0ff4  top 0000000c
+//
+// When the program file has a debug section, the line ends with the source
+// position the instruction was assembled from (`  fact.s:7`).
+//
+// The json format is one object per line for tools to analyze. Besides the
+// instruction and SP it lists the words pushed (negative when popped) and
+// every memory write as its address and the bytes written, in memory order:
+//
+//   {"pc":8,"word":"0x50000010","opcode":"call","operands":[16],"sp_before":4088,
+//    "sp_after":4084,"stack_delta":1,"writes":[{"addr":4084,"bytes":"0c000000"}]}
+//
+// Undefined words have a null opcode, and the step that faults also has a
+// "fault" field with the fault kind. Lines are buffered, and flushed whenever
+// the guest writes output or reads input so the two stay in order on a
+// terminal.
+
+use std::fmt::Write as _;
+use std::io::{BufWriter, Write};
+
+use crate::{isa, VM};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Json,
+}
+
+impl TraceFormat {
+    // Parse the --trace-format argument: text or json
+    pub fn parse(text: &str) -> Option<TraceFormat> {
+        match text {
+            "text" => Some(TraceFormat::Text),
+            "json" => Some(TraceFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) struct Trace {
+    writer: BufWriter<Box<dyn Write>>,
+    format: TraceFormat,
+    writes: Vec<(usize, Vec<u8>)>, // Memory written by the current instruction (json only)
+}
+
+impl VM {
+    // Trace every instruction executed from now on to out
+    pub fn set_trace(&mut self, out: Box<dyn Write>, format: TraceFormat) {
+        self.trace = Some(Trace { writer: BufWriter::new(out), format, writes: Vec::new() });
+    }
+
+    // Note a write to memory for the json trace
+    pub(crate) fn trace_write(&mut self, addr: usize, bytes: &[u8]) {
+        if let Some(trace) = &mut self.trace {
+            if trace.format == TraceFormat::Json {
+                trace.writes.push((addr, bytes.to_vec()));
+            }
+        }
+    }
+
+    pub(crate) fn trace_step(&mut self, pc: usize, word: u32, sp_before: usize) {
+        let line = match self.trace.as_ref().map(|trace| trace.format) {
+            Some(TraceFormat::Text) => self.text_line(pc, word, sp_before),
+            Some(TraceFormat::Json) => self.json_line(pc, word, sp_before),
+            None => return,
+        };
+        if let Some(trace) = &mut self.trace {
+            trace.writes.clear();
+            let _ = writeln!(trace.writer, "{}", line);
+        }
+    }
+
+    fn text_line(&self, pc: usize, word: u32, sp_before: usize) -> String {
+        let top = if self.sp + 4 <= self.memory.len() {
+            format!("{:08x}", self.read_u32(self.sp))
+        } else {
+            "-".to_string()
+        };
+        let mut line = format!(
+            "{:04x}: {:08x}  {:<20} sp {:04x} -> {:04x}  top {}",
+            pc,
+            word,
+            isa::disassemble(word),
+            sp_before,
+            self.sp,
+            top
+        );
+        if let Some((file, number)) = self.sections.lines.at(pc) {
+            let _ = write!(line, "  {}:{}", file, number);
+        }
+        line
+    }
+
+    // Every string in the line is a mnemonic, fault kind or hex digits, so
+    // nothing needs escaping
+    fn json_line(&self, pc: usize, word: u32, sp_before: usize) -> String {
+        let (opcode, operands) = match isa::lookup(word) {
+            Some(spec) => {
+                let values = spec.operands(word);
+                let operands: Vec<String> = values[..spec.operands.len()].iter().map(i32::to_string).collect();
+                (format!("\"{}\"", spec.mnemonic), operands.join(","))
+            }
+            None => ("null".to_string(), String::new()),
+        };
+        let mut line = format!(
+            "{{\"pc\":{},\"word\":\"0x{:08x}\",\"opcode\":{},\"operands\":[{}],\"sp_before\":{},\"sp_after\":{},\"stack_delta\":{},\"writes\":[",
+            pc,
+            word,
+            opcode,
+            operands,
+            sp_before,
+            self.sp,
+            (sp_before as i64 - self.sp as i64) / 4
+        );
+        let writes = self.trace.as_ref().map(|trace| trace.writes.as_slice()).unwrap_or_default();
+        for (index, (addr, bytes)) in writes.iter().enumerate() {
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            let separator = if index > 0 { "," } else { "" };
+            let _ = write!(line, "{}{{\"addr\":{},\"bytes\":\"{}\"}}", separator, addr, hex);
+        }
+        line.push(']');
+        if let Some(fault) = &self.fault {
+            let _ = write!(line, ",\"fault\":\"{}\"", fault.kind());
+        }
+        line.push('}');
+        line
+    }
+
+    pub(crate) fn flush_trace(&mut self) {
+        if let Some(trace) = &mut self.trace {
+            let _ = trace.writer.flush();
+        }
+    }
+}
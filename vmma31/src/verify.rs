@@ -0,0 +1,91 @@
+// Static checks on a program file before it runs, for `vmma31 verify`. A
+// corrupted or mis-assembled file otherwise just runs off into nothing: the
+// VM stops at the first undefined word, and branches outside the code end
+// the program. verify reports every such problem with its address instead.
+
+use std::fmt;
+
+use crate::{isa, split_program, MAGIC};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    BadMagic { found: Vec<u8> },                // The file doesn't start with MAGIC
+    BadSection { reason: String },              // The header or a section can't be read
+    BadEntry { entry: usize },                  // The entry point isn't an instruction in the code
+    BadBlock { reason: String },                // A data or bss block overlaps the code or memory end
+    SymbolOutsideCode { name: String, addr: usize }, // Symbol past the end of the code and outside every block
+    UnalignedLength { len: usize },             // Code isn't a whole number of words
+    UndefinedInstruction { addr: usize, word: u32 }, // Word matches no instruction
+    UnalignedTarget { addr: usize, target: i64 },    // Branch to an address that isn't a multiple of 4
+    TargetOutsideCode { addr: usize, target: i64 },  // Branch before the start or past the end of the code
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verdict::BadMagic { found } => write!(f, "invalid magic bytes {:02x?}", found),
+            Verdict::BadSection { reason } => f.write_str(reason),
+            Verdict::BadBlock { reason } => f.write_str(reason),
+            Verdict::BadEntry { entry } => write!(f, "entry point {:#06x} is not an instruction in the code", entry),
+            Verdict::SymbolOutsideCode { name, addr } => {
+                write!(f, "symbol {} at {:#06x} is past the end of the code", name, addr)
+            }
+            Verdict::UnalignedLength { len } => write!(f, "code is {} bytes, not a multiple of 4", len),
+            Verdict::UndefinedInstruction { addr, word } => {
+                write!(f, "{:04x}: undefined instruction 0x{:08x}", addr, word)
+            }
+            Verdict::UnalignedTarget { addr, target } => {
+                write!(f, "{:04x}: branch to unaligned address {:#x}", addr, target)
+            }
+            Verdict::TargetOutsideCode { addr, target } if *target < 0 => {
+                write!(f, "{:04x}: branch to {} before the start of the code", addr, target)
+            }
+            Verdict::TargetOutsideCode { addr, target } => {
+                write!(f, "{:04x}: branch to {:#06x} past the end of the code", addr, target)
+            }
+        }
+    }
+}
+
+// Every problem found in a program file, in address order; empty if it's
+// sound. Branches may target the end of the code, which ends the program.
+pub fn verify(bytes: &[u8]) -> Vec<Verdict> {
+    if !bytes.starts_with(&MAGIC) {
+        return vec![Verdict::BadMagic { found: bytes.iter().take(MAGIC.len()).copied().collect() }];
+    }
+    let (sections, code) = match split_program(bytes) {
+        Ok(split) => split,
+        Err(reason) => return vec![Verdict::BadSection { reason }],
+    };
+    let mut verdicts = Vec::new();
+    if sections.check_entry(code.len()).is_err() {
+        verdicts.push(Verdict::BadEntry { entry: sections.entry });
+    }
+    if let Err(reason) = sections.check_blocks(code.len(), u32::MAX as usize) {
+        verdicts.push(Verdict::BadBlock { reason });
+    }
+    let in_block = |addr: usize| sections.blocks().any(|(_, start, len)| (start..=start + len).contains(&addr));
+    for (addr, name) in sections.symbols.iter().filter(|(addr, _)| *addr > code.len() && !in_block(*addr)) {
+        verdicts.push(Verdict::SymbolOutsideCode { name: name.to_string(), addr });
+    }
+    if !code.len().is_multiple_of(4) {
+        verdicts.push(Verdict::UnalignedLength { len: code.len() });
+    }
+    for (index, chunk) in code.chunks_exact(4).enumerate() {
+        let addr = index * 4;
+        let word = u32::from_le_bytes(chunk.try_into().unwrap());
+        let Some(spec) = isa::lookup(word) else {
+            verdicts.push(Verdict::UndefinedInstruction { addr, word });
+            continue;
+        };
+        for field in spec.operands.iter().filter(|field| field.branch) {
+            let target = addr as i64 + field.extract(word) as i64;
+            if target % 4 != 0 {
+                verdicts.push(Verdict::UnalignedTarget { addr, target });
+            } else if !(0..=code.len() as i64).contains(&target) {
+                verdicts.push(Verdict::TargetOutsideCode { addr, target });
+            }
+        }
+    }
+    verdicts
+}
@@ -0,0 +1,61 @@
+// Where guest input comes from and guest output goes. The VM reads a line
+// for each input or stinput and hands printed text over through a VmIo, so
+// a host can run programs against any source and sink (another program, a
+// test, a browser page) by passing its own to set_io. The default, StreamIo,
+// reads stdin and writes stdout unless given other streams.
+
+use std::io::{self, BufRead, Write};
+
+pub trait VmIo {
+    // The next line of input, with its newline; empty once input runs out
+    fn read_line(&mut self) -> String;
+
+    // Guest output, already batched by the VM's flush policy
+    fn write(&mut self, text: &str);
+
+    // Push out anything the sink itself buffers
+    fn flush(&mut self) {}
+}
+
+// Reads lines from a BufRead and writes to a Write, using stdin or stdout
+// for whichever isn't given
+#[derive(Default)]
+pub struct StreamIo {
+    input: Option<Box<dyn BufRead>>,
+    output: Option<Box<dyn Write>>,
+}
+
+impl StreamIo {
+    pub fn new(input: Option<Box<dyn BufRead>>, output: Option<Box<dyn Write>>) -> StreamIo {
+        StreamIo { input, output }
+    }
+}
+
+impl VmIo for StreamIo {
+    fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        match &mut self.input {
+            Some(input) => {
+                let _ = input.read_line(&mut line);
+            }
+            None => {
+                io::stdin().read_line(&mut line).expect("Failed to read input");
+            }
+        }
+        line
+    }
+
+    fn write(&mut self, text: &str) {
+        let _ = match &mut self.output {
+            Some(output) => output.write_all(text.as_bytes()),
+            None => io::stdout().write_all(text.as_bytes()),
+        };
+    }
+
+    fn flush(&mut self) {
+        let _ = match &mut self.output {
+            Some(output) => output.flush(),
+            None => io::stdout().flush(),
+        };
+    }
+}
@@ -0,0 +1,1040 @@
+// WebAssembly translation behind `vmma31 to-wasm`. The module exports its
+// memory and `run() -> i32`, which runs the program and returns the exit
+// status (134 after a fault, as the CLI). Input and output go through
+// imports from "env", which wasm/runtime.mjs provides for browsers and Node:
+//
+//   input() -> i32                   next line, parsed as input does
+//   read_line(ptr, max) -> len       next line without surrounding
+//                                    whitespace, at most max bytes of it
+//                                    written at ptr; returns its full length
+//   print_word(value, format)        print as printf's format bits say:
+//                                    0 decimal, 1 hex, 2 binary, 3 octal,
+//                                    4 character, then flags and width
+//   print_bytes(ptr, len)            stprint text, one byte per character
+//                                    (Latin-1)
+//   print_stack_word(offset, value)  one dump line
+//   fault(kind, pc, a, b)            report a fault; run then returns 134
+//
+// Fault kinds are the FAULT_* constants below; a is the word for an invalid
+// instruction, the popped and expected addresses for a shadow stack
+// mismatch, and the size asked for when alloc runs out of memory. Memory
+// 0..4096 is the guest's; a scratch buffer, the heap's block table and the
+// shadow stack follow it.
+//
+// Each instruction's code sits after the end of its own block, so the
+// program falls through from one instruction to the next, and a branch sets
+// the PC and goes back to a br_table that picks the block. As with compile,
+// the program is translated once (self-modifying code keeps its original
+// meaning), and host services (syscall, fork, wait, cpuid, counter and the
+// codec group) are rejected.
+
+use crate::flags;
+use crate::isa::{self, Op};
+use crate::program::Sections;
+use crate::{split_program, DEFAULT_RAM_SIZE, FAULT_EXIT_CODE};
+
+pub const FAULT_INVALID_INSTRUCTION: i32 = 0;
+pub const FAULT_STACK_UNDERFLOW: i32 = 1;
+pub const FAULT_STACK_OVERFLOW: i32 = 2;
+pub const FAULT_DIVIDE_BY_ZERO: i32 = 3;
+pub const FAULT_SHADOW_MISMATCH: i32 = 4;
+pub const FAULT_SHADOW_EMPTY: i32 = 5; // Return with no matching call
+pub const FAULT_OUT_OF_MEMORY: i32 = 6;
+pub const FAULT_OVERFLOW: i32 = 7; // add, sub, mul, qmul or qdiv overflowed after trapv 1
+
+const RAM: i32 = DEFAULT_RAM_SIZE as i32;
+const SCRATCH: i32 = RAM; // RAM bytes for input lines, stprint text and vector lanes
+const HEAP_TABLE: i32 = 2 * RAM; // Size of the heap block at each word, | 1 while in use
+const SHADOW_BASE: i32 = 3 * RAM; // Grows up, adding pages as needed
+const PAGE_SIZE: i32 = 65536;
+
+// Value types
+const I32: u8 = 0x7F;
+const I64: u8 = 0x7E;
+
+// Globals
+const SP: u32 = 0;
+const PC: u32 = 1; // Address of the current instruction, for fault reports
+const FAULTED: u32 = 2;
+const SHADOW_LEN: u32 = 3;
+const FP: u32 = 4;
+const HEAP_TOP: u32 = 5; // End of the last heap block; 0 while there are none
+const FLAGS: u32 = 6; // N, Z, C and V as cmp last set them, in bits 3 to 0
+const TRAP_OVERFLOW: u32 = 7; // Set by trapv
+
+// Functions: the imports, then the helpers, then run
+const INPUT: u32 = 0;
+const READ_LINE: u32 = 1;
+const PRINT_WORD: u32 = 2;
+const PRINT_BYTES: u32 = 3;
+const PRINT_STACK_WORD: u32 = 4;
+const FAULT: u32 = 5;
+const RAISE: u32 = 6;
+const READ: u32 = 7;
+const WRITE: u32 = 8;
+const PUSH: u32 = 9;
+const POP: u32 = 10;
+const POP_BYTES: u32 = 11;
+const SWAP: u32 = 12;
+const DIV: u32 = 13;
+const REM: u32 = 14;
+const FIXED_DIVIDE: u32 = 15;
+const MAP_CHARS: u32 = 16;
+const ALL_CHARS: u32 = 17;
+const VECTOR: u32 = 18;
+const STPRINT: u32 = 19;
+const DUMP: u32 = 20;
+const STINPUT: u32 = 21;
+const SHADOW_CALL: u32 = 22;
+const RETURN: u32 = 23;
+const ALLOC: u32 = 24;
+const FREE: u32 = 25;
+const MEMCPY: u32 = 26;
+const MEMSET: u32 = 27;
+const MEMCMP: u32 = 28;
+const WIDE: u32 = 29;
+const COMPARE: u32 = 30;
+const CONDITION: u32 = 31;
+const CHECKED: u32 = 32;
+const RUN: u32 = 33;
+
+// Opcodes
+const UNREACHABLE: u8 = 0x00;
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0B;
+const BR: u8 = 0x0C;
+const BR_IF: u8 = 0x0D;
+const BR_TABLE: u8 = 0x0E;
+const RETURN_OP: u8 = 0x0F;
+const CALL: u8 = 0x10;
+const DROP: u8 = 0x1A;
+const SELECT: u8 = 0x1B;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const GLOBAL_GET: u8 = 0x23;
+const GLOBAL_SET: u8 = 0x24;
+const I32_LOAD: u8 = 0x28;
+const I32_LOAD8_U: u8 = 0x2D;
+const I32_STORE: u8 = 0x36;
+const I32_STORE8: u8 = 0x3A;
+const MEMORY_SIZE: u8 = 0x3F;
+const MEMORY_GROW: u8 = 0x40;
+const I32_CONST: u8 = 0x41;
+const I64_CONST: u8 = 0x42;
+const I32_EQZ: u8 = 0x45;
+const I32_EQ: u8 = 0x46;
+const I32_NE: u8 = 0x47;
+const I32_LT_S: u8 = 0x48;
+const I32_LT_U: u8 = 0x49;
+const I32_GT_S: u8 = 0x4A;
+const I32_GT_U: u8 = 0x4B;
+const I32_LE_S: u8 = 0x4C;
+const I32_LE_U: u8 = 0x4D;
+const I32_GE_S: u8 = 0x4E;
+const I32_GE_U: u8 = 0x4F;
+const I64_EQZ: u8 = 0x50;
+const I64_NE: u8 = 0x52;
+const I64_LT_S: u8 = 0x53;
+const I64_GT_S: u8 = 0x55;
+const I64_GE_S: u8 = 0x59;
+const I32_CLZ: u8 = 0x67;
+const I32_CTZ: u8 = 0x68;
+const I32_POPCNT: u8 = 0x69;
+const I32_ADD: u8 = 0x6A;
+const I32_SUB: u8 = 0x6B;
+const I32_MUL: u8 = 0x6C;
+const I32_DIV_S: u8 = 0x6D;
+const I32_DIV_U: u8 = 0x6E;
+const I32_REM_S: u8 = 0x6F;
+const I32_AND: u8 = 0x71;
+const I32_OR: u8 = 0x72;
+const I32_XOR: u8 = 0x73;
+const I32_SHL: u8 = 0x74;
+const I32_SHR_S: u8 = 0x75;
+const I32_SHR_U: u8 = 0x76;
+const I64_ADD: u8 = 0x7C;
+const I64_SUB: u8 = 0x7D;
+const I64_MUL: u8 = 0x7E;
+const I64_DIV_S: u8 = 0x7F;
+const I64_REM_S: u8 = 0x81;
+const I64_OR: u8 = 0x84;
+const I64_SHL: u8 = 0x86;
+const I64_SHR_S: u8 = 0x87;
+const I64_SHR_U: u8 = 0x88;
+const I32_WRAP_I64: u8 = 0xA7;
+const I64_EXTEND_I32_S: u8 = 0xAC;
+const I64_EXTEND_I32_U: u8 = 0xAD;
+
+const VOID: u8 = 0x40; // Block type with no result
+
+// Function body under construction
+#[derive(Default)]
+struct Code(Vec<u8>);
+
+impl Code {
+    fn op(&mut self, opcode: u8) -> &mut Code {
+        self.0.push(opcode);
+        self
+    }
+
+    fn index(&mut self, value: u32) -> &mut Code {
+        unsigned(&mut self.0, value);
+        self
+    }
+
+    fn i32(&mut self, value: i32) -> &mut Code {
+        self.op(I32_CONST);
+        signed(&mut self.0, value as i64);
+        self
+    }
+
+    fn i64(&mut self, value: i64) -> &mut Code {
+        self.op(I64_CONST);
+        signed(&mut self.0, value);
+        self
+    }
+
+    fn get(&mut self, local: u32) -> &mut Code {
+        self.op(LOCAL_GET).index(local)
+    }
+
+    fn set(&mut self, local: u32) -> &mut Code {
+        self.op(LOCAL_SET).index(local)
+    }
+
+    fn tee(&mut self, local: u32) -> &mut Code {
+        self.op(LOCAL_TEE).index(local)
+    }
+
+    fn global_get(&mut self, global: u32) -> &mut Code {
+        self.op(GLOBAL_GET).index(global)
+    }
+
+    fn global_set(&mut self, global: u32) -> &mut Code {
+        self.op(GLOBAL_SET).index(global)
+    }
+
+    fn call(&mut self, function: u32) -> &mut Code {
+        self.op(CALL).index(function)
+    }
+
+    fn br(&mut self, depth: u32) -> &mut Code {
+        self.op(BR).index(depth)
+    }
+
+    fn br_if(&mut self, depth: u32) -> &mut Code {
+        self.op(BR_IF).index(depth)
+    }
+
+    fn block(&mut self, kind: u8, result: u8) -> &mut Code {
+        self.op(kind).op(result)
+    }
+
+    // Loads and stores with no offset; the alignment is only a hint
+    fn memory(&mut self, opcode: u8) -> &mut Code {
+        self.op(opcode).index(0).index(0)
+    }
+
+    // Add the address of shadow stack entry `shadow_len - back`
+    fn shadow_entry(&mut self, back: i32) -> &mut Code {
+        self.i32(SHADOW_BASE).global_get(SHADOW_LEN).i32(back).op(I32_SUB).i32(2).op(I32_SHL).op(I32_ADD)
+    }
+
+    // Add the heap table entry for the block at the address in `local`
+    fn heap_entry(&mut self, local: u32) -> &mut Code {
+        self.i32(HEAP_TABLE).get(local).op(I32_ADD)
+    }
+
+    // Push how many of the bytes at local `addr`, local `len` long, are inside memory
+    fn bytes_inside(&mut self, addr: u32, len: u32) -> &mut Code {
+        self.get(addr).i32(RAM).op(I32_LT_U).block(IF, I32);
+        self.get(len).i32(RAM).get(addr).op(I32_SUB).i32(RAM).get(addr).op(I32_SUB).get(len).op(I32_GT_U).op(SELECT);
+        self.op(ELSE).i32(0).op(END)
+    }
+
+    // Push byte local `i` of the range at local `addr`, 0 unless it is one of
+    // the local `inside` bytes inside memory
+    fn range_byte(&mut self, addr: u32, i: u32, inside: u32) -> &mut Code {
+        self.get(i).get(inside).op(I32_LT_U).block(IF, I32);
+        self.get(addr).get(i).op(I32_ADD).memory(I32_LOAD8_U).op(ELSE).i32(0).op(END)
+    }
+
+    // Return the fault status if anything faulted
+    fn check_fault(&mut self) -> &mut Code {
+        self.global_get(FAULTED).block(IF, VOID).i32(FAULT_EXIT_CODE).op(RETURN_OP).op(END)
+    }
+
+    // Pop the right operand into local 1 and the left into local 0, and push them
+    fn operands(&mut self) -> &mut Code {
+        self.call(POP).set(1).call(POP).set(0).get(0).get(1)
+    }
+
+    // `value` shifted right by 8 * local `i`, as a byte
+    fn byte_of(&mut self, value: u32, i: u32) -> &mut Code {
+        self.get(value).get(i).i32(3).op(I32_SHL).op(I32_SHR_U).i32(0xFF).op(I32_AND)
+    }
+}
+
+struct Function {
+    params: &'static [u8],
+    results: &'static [u8],
+    locals: &'static [u8],
+    code: Code,
+}
+
+fn function(params: &'static [u8], results: &'static [u8], locals: &'static [u8], code: &mut Code) -> Function {
+    Function { params, results, locals, code: std::mem::take(code) }
+}
+
+// Translate magic-prefixed bytecode into a WebAssembly module
+pub fn compile_wasm(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let (sections, code) = split_program(bytes)?;
+    sections.check_entry(code.len())?;
+    sections.check_blocks(code.len(), RAM as usize)?;
+    if code.len() > RAM as usize {
+        return Err("Program too large for memory".to_string());
+    }
+    let mut functions = helpers();
+    functions.push(run(&code, &sections)?);
+
+    let imports: [(&str, &'static [u8], &'static [u8]); 6] = [
+        ("input", &[], &[I32]),
+        ("read_line", &[I32, I32], &[I32]),
+        ("print_word", &[I32, I32], &[]),
+        ("print_bytes", &[I32, I32], &[]),
+        ("print_stack_word", &[I32, I32], &[]),
+        ("fault", &[I32, I32, I32, I32], &[]),
+    ];
+    let mut types: Vec<(&[u8], &[u8])> = Vec::new();
+    let mut type_of = |params: &'static [u8], results: &'static [u8]| {
+        let index = types.iter().position(|&t| t == (params, results)).unwrap_or_else(|| {
+            types.push((params, results));
+            types.len() - 1
+        });
+        index as u32
+    };
+    let import_types: Vec<u32> = imports.iter().map(|&(_, params, results)| type_of(params, results)).collect();
+    let function_types: Vec<u32> = functions.iter().map(|f| type_of(f.params, f.results)).collect();
+
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+    section(&mut module, 1, types.len(), |out| {
+        for (params, results) in &types {
+            out.push(0x60);
+            vector(out, params);
+            vector(out, results);
+        }
+    });
+    section(&mut module, 2, imports.len(), |out| {
+        for ((name, _, _), &ty) in imports.iter().zip(&import_types) {
+            vector(out, b"env");
+            vector(out, name.as_bytes());
+            out.push(0x00);
+            unsigned(out, ty);
+        }
+    });
+    section(&mut module, 3, functions.len(), |out| {
+        for &ty in &function_types {
+            unsigned(out, ty);
+        }
+    });
+    let pages = (SHADOW_BASE + PAGE_SIZE - 1) / PAGE_SIZE + 1;
+    section(&mut module, 5, 1, |out| {
+        out.push(0x00);
+        unsigned(out, pages as u32);
+    });
+    section(&mut module, 6, 8, |out| {
+        // The PC starts at the entry point, where the dispatch loop goes first
+        for initial in [RAM, sections.entry as i32, 0, 0, RAM, 0, 0, 0] {
+            out.extend_from_slice(&[I32, 0x01, I32_CONST]);
+            signed(out, initial as i64);
+            out.push(END);
+        }
+    });
+    section(&mut module, 7, 2, |out| {
+        vector(out, b"memory");
+        out.extend_from_slice(&[0x02, 0x00]);
+        vector(out, b"run");
+        out.push(0x00);
+        unsigned(out, RUN);
+    });
+    section(&mut module, 10, functions.len(), |out| {
+        for f in &functions {
+            let mut body = Vec::new();
+            unsigned(&mut body, f.locals.len() as u32);
+            for &local in f.locals {
+                body.extend_from_slice(&[0x01, local]);
+            }
+            body.extend_from_slice(&f.code.0);
+            body.push(END);
+            vector(out, &body);
+        }
+    });
+    // The code at 0, then each data block at its address
+    let segments = std::iter::once((0, &code[..])).chain(sections.data.iter().map(|(addr, data)| (*addr, data.as_slice())));
+    section(&mut module, 11, 1 + sections.data.len(), |out| {
+        for (addr, bytes) in segments {
+            out.extend_from_slice(&[0x00, I32_CONST]);
+            signed(out, addr as i64);
+            out.push(END);
+            vector(out, bytes);
+        }
+    });
+    Ok(module)
+}
+
+// The function that runs the program
+fn run(code: &[u8], sections: &Sections) -> Result<Function, String> {
+    let words: Vec<u32> = code
+        .chunks(4)
+        .map(|chunk| {
+            let mut bytes = [0u8; 4]; // The VM reads past a short last word as zeros
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            u32::from_le_bytes(bytes)
+        })
+        .collect();
+    let count = words.len() as u32;
+    let mut c = Code::default();
+    c.block(BLOCK, VOID).block(LOOP, VOID);
+    for _ in 0..count {
+        c.block(BLOCK, VOID);
+    }
+    c.global_get(PC).i32(2).op(I32_SHR_U).op(BR_TABLE).index(count);
+    for depth in 0..count {
+        c.index(depth);
+    }
+    c.index(count + 1);
+    for (index, &word) in words.iter().enumerate() {
+        c.op(END);
+        let pc = index * 4;
+        // Enclosed by the blocks of later instructions, then the loop and the done block
+        let depth = count - 1 - index as u32;
+        c.i32(pc as i32).global_set(PC);
+        match isa::lookup(word) {
+            Some(spec) => translate(&mut c, spec.op, pc, word, code.len(), sections, depth)
+                .ok_or_else(|| format!("{} at 0x{:04x} can't be compiled", spec.mnemonic, pc))?,
+            None => {
+                c.i32(FAULT_INVALID_INSTRUCTION).i32(word as i32).i32(0).call(RAISE);
+                c.i32(FAULT_EXIT_CODE).op(RETURN_OP);
+            }
+        }
+    }
+    c.op(END).op(END).i32(0); // Running off the end of the code, or a branch outside it
+    Ok(function(&[], &[I32], &[I32, I32], &mut c))
+}
+
+// Append the code for one instruction, whose branches go `depth` levels out
+// to reach the dispatch loop; None if it can't be compiled. sections are
+// the program's, for its globals region and return stack mode.
+fn translate(c: &mut Code, op: Op, pc: usize, word: u32, code_size: usize, sections: &Sections, depth: u32) -> Option<()> {
+    let [a, b] = op.spec().operands(word);
+    let globals = sections.globals.unwrap_or_default();
+    let heap_start = sections.heap_start(code_size) as i32;
+    let target = pc as i64 + a as i64; // Only meaningful for branches
+    let peek = |c: &mut Code, offset: i32| {
+        c.global_get(SP).i32(offset).op(I32_ADD).call(READ);
+    };
+    match op {
+        Op::Exit => {
+            c.i32(a).op(RETURN_OP);
+            return Some(());
+        }
+        Op::Nop | Op::Debug => return Some(()),
+        Op::Goto => {
+            jump(c, target, pc, code_size, depth);
+            return Some(());
+        }
+        Op::Ifeq | Op::Ifne | Op::Iflt | Op::Ifgt | Op::Ifle | Op::Ifge
+        | Op::Ifltu | Op::Ifgtu | Op::Ifleu | Op::Ifgeu => {
+            let condition = match op {
+                Op::Ifeq => I32_EQ,
+                Op::Ifne => I32_NE,
+                Op::Iflt => I32_LT_S,
+                Op::Ifgt => I32_GT_S,
+                Op::Ifle => I32_LE_S,
+                Op::Ifge => I32_GE_S,
+                Op::Ifltu => I32_LT_U,
+                Op::Ifgtu => I32_GT_U,
+                Op::Ifleu => I32_LE_U,
+                _ => I32_GE_U,
+            };
+            if target != pc as i64 {
+                peek(c, 4);
+                peek(c, 0);
+                c.op(condition).block(IF, VOID);
+                jump(c, target, pc, code_size, depth + 1);
+                c.op(END);
+            }
+            return Some(());
+        }
+        Op::Ifez | Op::Ifnz | Op::Ifmi | Op::Ifpl => {
+            if target != pc as i64 {
+                peek(c, 0);
+                match op {
+                    Op::Ifez => c.op(I32_EQZ),
+                    Op::Ifnz => c.i32(0).op(I32_NE),
+                    Op::Ifmi => c.i32(0).op(I32_LT_S),
+                    _ => c.i32(0).op(I32_GE_S),
+                };
+                c.block(IF, VOID);
+                jump(c, target, pc, code_size, depth + 1);
+                c.op(END);
+            }
+            return Some(());
+        }
+        Op::Beq | Op::Bne | Op::Bhs | Op::Blo | Op::Bmi | Op::Bpl | Op::Bvs | Op::Bvc
+        | Op::Bhi | Op::Bls | Op::Bge | Op::Blt | Op::Bgt | Op::Ble => {
+            if target != pc as i64 {
+                c.i32(flags::condition_code(op) as i32).call(CONDITION).block(IF, VOID);
+                jump(c, target, pc, code_size, depth + 1);
+                c.op(END);
+            }
+            return Some(());
+        }
+        Op::Swap => c.i32(a).i32(b).call(SWAP),
+        Op::Input => c.call(INPUT).call(PUSH),
+        // Lines longer than the scratch buffer fill the stack before they run out
+        Op::Stinput if a == 0xFF_FFFF => c.i32(SCRATCH).call(STINPUT),
+        Op::Stinput => c.i32(a.min(SCRATCH)).call(STINPUT),
+        Op::Trapv => c.i32((a != 0) as i32).global_set(TRAP_OVERFLOW),
+        Op::Pop => c.i32(a).call(POP_BYTES),
+        Op::Add | Op::Sub | Op::Mul => {
+            let wide = match op {
+                Op::Add => I64_ADD,
+                Op::Sub => I64_SUB,
+                _ => I64_MUL,
+            };
+            c.call(POP).set(1).call(POP).op(I64_EXTEND_I32_S).get(1).op(I64_EXTEND_I32_S).op(wide);
+            c.call(CHECKED).call(PUSH)
+        }
+        Op::And => c.operands().op(I32_AND).call(PUSH),
+        Op::Or => c.operands().op(I32_OR).call(PUSH),
+        Op::Xor => c.operands().op(I32_XOR).call(PUSH),
+        Op::Lsl => c.operands().op(I32_SHL).call(PUSH),
+        Op::Lsr => c.operands().op(I32_SHR_U).call(PUSH),
+        Op::Asr => c.operands().op(I32_SHR_S).call(PUSH),
+        Op::Min => c.operands().get(0).get(1).op(I32_LT_S).op(SELECT).call(PUSH),
+        Op::Max => c.operands().get(0).get(1).op(I32_GT_S).op(SELECT).call(PUSH),
+        Op::Mulh => {
+            c.call(POP).set(1).call(POP).op(I64_EXTEND_I32_S).get(1).op(I64_EXTEND_I32_S).op(I64_MUL);
+            c.i64(32).op(I64_SHR_S).op(I32_WRAP_I64).call(PUSH)
+        }
+        Op::Mulhu => {
+            c.call(POP).set(1).call(POP).op(I64_EXTEND_I32_U).get(1).op(I64_EXTEND_I32_U).op(I64_MUL);
+            c.i64(32).op(I64_SHR_U).op(I32_WRAP_I64).call(PUSH)
+        }
+        Op::Div => c.operands().call(DIV).call(PUSH),
+        Op::Rem => c.operands().call(REM).call(PUSH),
+        Op::Qmul => {
+            c.call(POP).set(1).call(POP).set(0);
+            c.get(0).op(I64_EXTEND_I32_S).get(1).op(I64_EXTEND_I32_S).op(I64_MUL);
+            c.i64(1 << 16).i32(a).call(FIXED_DIVIDE).call(CHECKED).call(PUSH)
+        }
+        Op::Qdiv => {
+            c.call(POP).set(1).call(POP).set(0);
+            c.get(1).op(I32_EQZ).block(IF, I32).i32(FAULT_DIVIDE_BY_ZERO).i32(0).i32(0).call(RAISE).i32(0).op(ELSE);
+            c.get(0).op(I64_EXTEND_I32_S).i64(1 << 16).op(I64_MUL).get(1).op(I64_EXTEND_I32_S);
+            c.i32(a).call(FIXED_DIVIDE).call(CHECKED).op(END).call(PUSH)
+        }
+        Op::Neg => c.call(POP).set(0).i32(0).get(0).op(I32_SUB).call(PUSH),
+        Op::Not => c.call(POP).i32(-1).op(I32_XOR).call(PUSH),
+        Op::Popcount => c.call(POP).op(I32_POPCNT).call(PUSH),
+        Op::Clz => c.call(POP).op(I32_CLZ).call(PUSH),
+        Op::Ctz => c.call(POP).op(I32_CTZ).call(PUSH),
+        Op::Abs => c.call(POP).set(0).i32(0).get(0).op(I32_SUB).get(0).get(0).i32(0).op(I32_LT_S).op(SELECT).call(PUSH),
+        Op::Toupper => c.call(POP).i32(b'a' as i32).i32(b'z' as i32).i32(-32).call(MAP_CHARS).call(PUSH),
+        Op::Tolower => c.call(POP).i32(b'A' as i32).i32(b'Z' as i32).i32(32).call(MAP_CHARS).call(PUSH),
+        Op::Isdigit => c.call(POP).i32(0).call(ALL_CHARS).call(PUSH),
+        Op::Isalpha => c.call(POP).i32(1).call(ALL_CHARS).call(PUSH),
+        Op::Stprint => c.i32(a).call(STPRINT),
+        Op::Call => {
+            if sections.return_stack {
+                c.global_get(SHADOW_LEN).i32(RAM / 4).op(I32_GE_U).block(IF, VOID);
+                c.i32(FAULT_STACK_OVERFLOW).i32(0).i32(0).call(RAISE).op(END).check_fault();
+                c.i32(pc as i32 + 4).call(SHADOW_CALL);
+            } else {
+                c.i32(pc as i32 + 4).call(PUSH).i32(pc as i32 + 4).call(SHADOW_CALL).check_fault();
+            }
+            jump(c, target, pc, code_size, depth);
+            return Some(());
+        }
+        Op::Return => {
+            c.i32(a).i32(sections.return_stack as i32).call(RETURN).check_fault().block(IF, VOID).br(depth + 1).op(END);
+            return Some(());
+        }
+        Op::Vadd => c.i32(0).call(VECTOR),
+        Op::Vsub => c.i32(1).call(VECTOR),
+        Op::Vmul => c.i32(2).call(VECTOR),
+        Op::Add64 => c.i32(0).call(WIDE),
+        Op::Sub64 => c.i32(1).call(WIDE),
+        Op::Mul64 => c.i32(2).call(WIDE),
+        Op::Cmp64 => c.i32(3).call(WIDE),
+        Op::Cmp => c.operands().call(COMPARE),
+        Op::Load | Op::Loadi | Op::Loadb | Op::Loadbi => {
+            match op {
+                Op::Load | Op::Loadb => c.call(POP),
+                _ => c.i32(a),
+            };
+            c.set(0).get(0);
+            match op {
+                Op::Load | Op::Loadi => c.call(READ),
+                _ => c.i32(RAM).op(I32_LT_U).block(IF, I32).get(0).memory(I32_LOAD8_U).op(ELSE).i32(0).op(END),
+            };
+            c.call(PUSH)
+        }
+        // The value is popped before anything is written, and nothing is if that faults
+        Op::Store | Op::Storei | Op::Storeb | Op::Storebi => {
+            match op {
+                Op::Store | Op::Storeb => c.call(POP),
+                _ => c.i32(a),
+            };
+            c.set(0).call(POP).set(1).global_get(FAULTED).op(I32_EQZ).block(IF, VOID);
+            match op {
+                Op::Store | Op::Storei => c.get(0).get(1).call(WRITE),
+                _ => c.get(0).i32(RAM).op(I32_LT_U).block(IF, VOID).get(0).get(1).memory(I32_STORE8).op(END),
+            };
+            c.op(END)
+        }
+        // Globals past the region read 0 and aren't written
+        Op::Gload if (a as usize) < globals.1 => c.i32((globals.0 + a as usize * 4) as i32).call(READ).call(PUSH),
+        Op::Gload => c.i32(0).call(PUSH),
+        Op::Gstore if (a as usize) < globals.1 => {
+            c.call(POP).set(1).global_get(FAULTED).op(I32_EQZ).block(IF, VOID);
+            c.i32((globals.0 + a as usize * 4) as i32).get(1).call(WRITE).op(END)
+        }
+        Op::Gstore => c.call(POP).op(DROP),
+        Op::Enter => {
+            c.global_get(FP).call(PUSH).global_get(SP).global_set(FP);
+            c.global_get(SP).global_get(HEAP_TOP).i32(a * 4).op(I32_ADD).op(I32_LT_S).block(IF, VOID);
+            c.i32(FAULT_STACK_OVERFLOW).i32(0).i32(0).call(RAISE).op(ELSE);
+            c.global_get(SP).i32(a * 4).op(I32_SUB).global_set(SP).op(END)
+        }
+        Op::Leave => {
+            c.global_get(FP).i32(RAM).global_get(FP).i32(RAM).op(I32_LT_U).op(SELECT).global_set(SP);
+            c.call(POP).global_set(FP)
+        }
+        Op::Lload => c.global_get(FP).i32(a * 4).op(I32_ADD).call(READ).call(PUSH),
+        Op::Lstore => {
+            c.call(POP).set(1).global_get(FAULTED).op(I32_EQZ).block(IF, VOID);
+            c.global_get(FP).i32(a * 4).op(I32_ADD).get(1).call(WRITE).op(END)
+        }
+        Op::Alloc => {
+            c.call(POP).i32(heap_start).call(ALLOC).set(0);
+            c.global_get(FAULTED).op(I32_EQZ).block(IF, VOID).get(0).call(PUSH).op(END)
+        }
+        Op::Free => c.call(POP).i32(heap_start).call(FREE),
+        Op::Memcpy | Op::Memset | Op::Memcmp => {
+            c.call(POP).set(1).call(POP).set(0).call(POP).get(0).get(1);
+            match op {
+                Op::Memcpy => c.call(MEMCPY),
+                Op::Memset => c.call(MEMSET),
+                _ => c.call(MEMCMP).call(PUSH),
+            }
+        }
+        Op::Dup => {
+            peek(c, a);
+            c.call(PUSH)
+        }
+        Op::Print | Op::Printh | Op::Printb | Op::Printo => {
+            peek(c, a);
+            c.i32(op as i32 - Op::Print as i32).call(PRINT_WORD)
+        }
+        Op::Printf => {
+            peek(c, a);
+            c.i32(b).call(PRINT_WORD)
+        }
+        Op::Dump => c.call(DUMP),
+        Op::Push => c.i32(a).call(PUSH),
+        Op::Syscall | Op::Fork | Op::Wait | Op::Cpuid | Op::Counter => return None,
+        Op::B64enc | Op::B64dec | Op::Hexenc | Op::Hexdec => return None,
+    };
+    c.check_fault();
+    Some(())
+}
+
+// Branch to a fixed target. A branch to itself leaves the PC unchanged, so
+// the VM moves on to the next instruction; one outside the code ends the run.
+fn jump(c: &mut Code, target: i64, pc: usize, code_size: usize, depth: u32) {
+    if target == pc as i64 {
+        return;
+    }
+    if target < 0 || target >= code_size as i64 {
+        c.br(depth + 1);
+    } else {
+        c.i32(target as i32).global_set(PC).br(depth);
+    }
+}
+
+// The runtime shared by every program, in function index order from RAISE
+fn helpers() -> Vec<Function> {
+    let mut c = Code::default();
+    let mut functions = Vec::new();
+
+    // raise(kind, a, b): report the first fault only
+    c.global_get(FAULTED).op(I32_EQZ).block(IF, VOID);
+    c.i32(1).global_set(FAULTED).get(0).global_get(PC).get(1).get(2).call(FAULT);
+    c.op(END);
+    functions.push(function(&[I32, I32, I32], &[], &[], &mut c));
+
+    // read(addr) -> word, 0 outside memory
+    c.get(0).i32(RAM - 4).op(I32_LE_U).block(IF, I32);
+    c.get(0).memory(I32_LOAD).op(ELSE).i32(0).op(END);
+    functions.push(function(&[I32], &[I32], &[], &mut c));
+
+    // write(addr, value), ignored outside memory
+    c.get(0).i32(RAM - 4).op(I32_LE_U).block(IF, VOID);
+    c.get(0).get(1).memory(I32_STORE).op(END);
+    functions.push(function(&[I32, I32], &[], &[], &mut c));
+
+    // push(value), faulting at the top of the heap
+    c.global_get(SP).global_get(HEAP_TOP).i32(4).op(I32_ADD).op(I32_LT_S).block(IF, VOID);
+    c.i32(FAULT_STACK_OVERFLOW).i32(0).i32(0).call(RAISE).op(RETURN_OP).op(END);
+    c.global_get(SP).i32(4).op(I32_SUB).global_set(SP).global_get(SP).get(0).call(WRITE);
+    functions.push(function(&[I32], &[], &[], &mut c));
+
+    // pop() -> value
+    c.global_get(SP).i32(RAM - 4).op(I32_GT_S).block(IF, VOID);
+    c.i32(FAULT_STACK_UNDERFLOW).i32(0).i32(0).call(RAISE).i32(0).op(RETURN_OP).op(END);
+    c.global_get(SP).call(READ).global_get(SP).i32(4).op(I32_ADD).global_set(SP);
+    functions.push(function(&[], &[I32], &[], &mut c));
+
+    // pop_bytes(n): drop n bytes, stopping at an empty stack
+    c.global_get(SP).get(0).op(I32_ADD).tee(1).i32(RAM).op(I32_LE_U).block(IF, I32);
+    c.get(1).op(ELSE).i32(RAM).op(END).global_set(SP);
+    functions.push(function(&[I32], &[], &[I32], &mut c));
+
+    // swap(from, to), when both words are in memory
+    c.global_get(SP).get(0).op(I32_ADD).tee(2).i32(RAM - 4).op(I32_LE_U);
+    c.global_get(SP).get(1).op(I32_ADD).tee(3).i32(RAM - 4).op(I32_LE_U);
+    c.op(I32_AND).block(IF, VOID);
+    c.get(2).call(READ).set(4).get(2).get(3).call(READ).call(WRITE).get(3).get(4).call(WRITE);
+    c.op(END);
+    functions.push(function(&[I32, I32], &[], &[I32, I32, I32], &mut c));
+
+    // div(l, r); division by -1 of the smallest word wraps instead of trapping
+    c.get(1).op(I32_EQZ).block(IF, VOID);
+    c.i32(FAULT_DIVIDE_BY_ZERO).i32(0).i32(0).call(RAISE).i32(0).op(RETURN_OP).op(END);
+    c.get(0).i32(i32::MIN).op(I32_EQ).get(1).i32(-1).op(I32_EQ).op(I32_AND).block(IF, VOID);
+    c.get(0).op(RETURN_OP).op(END);
+    c.get(0).get(1).op(I32_DIV_S);
+    functions.push(function(&[I32, I32], &[I32], &[], &mut c));
+
+    // rem(l, r)
+    c.get(1).op(I32_EQZ).block(IF, VOID);
+    c.i32(FAULT_DIVIDE_BY_ZERO).i32(0).i32(0).call(RAISE).i32(0).op(RETURN_OP).op(END);
+    c.get(0).get(1).op(I32_REM_S);
+    functions.push(function(&[I32, I32], &[I32], &[], &mut c));
+
+    // fixed_divide(n, d, rounding) -> Q16.16 value, as fixed::divide, before narrowing
+    // Locals: 3 quotient, 4 remainder, 5 result is negative
+    c.get(0).get(1).op(I64_DIV_S).set(3);
+    c.get(0).get(1).op(I64_REM_S).tee(4).op(I64_EQZ).block(IF, VOID);
+    c.get(3).op(RETURN_OP).op(END);
+    c.get(0).i64(0).op(I64_LT_S).get(1).i64(0).op(I64_LT_S).op(I32_NE).set(5);
+    c.get(2).i32(1).op(I32_EQ); // Nearest, and 2 * |remainder| >= |d|
+    c.i64(0).get(4).op(I64_SUB).get(4).get(4).i64(0).op(I64_LT_S).op(SELECT).i64(2).op(I64_MUL);
+    c.i64(0).get(1).op(I64_SUB).get(1).get(1).i64(0).op(I64_LT_S).op(SELECT).op(I64_GE_S).op(I32_AND);
+    c.get(2).i32(2).op(I32_EQ).get(5).op(I32_AND).op(I32_OR); // Floor, negative
+    c.get(2).i32(3).op(I32_EQ).get(5).op(I32_EQZ).op(I32_AND).op(I32_OR); // Ceil, positive
+    c.block(IF, VOID);
+    c.get(3).i64(-1).i64(1).get(5).op(SELECT).op(I64_ADD).set(3);
+    c.op(END).get(3);
+    functions.push(function(&[I64, I64, I32], &[I64], &[I64, I64, I32], &mut c));
+
+    // map_chars(word, low, high, delta): shift bytes 0-2 in low..=high by delta
+    // Locals: 4 byte index, 5 byte
+    c.block(LOOP, VOID);
+    c.byte_of(0, 4).tee(5).get(1).op(I32_GE_U).get(5).get(2).op(I32_LE_U).op(I32_AND).block(IF, VOID);
+    c.get(0).i32(0xFF).get(4).i32(3).op(I32_SHL).op(I32_SHL).i32(-1).op(I32_XOR).op(I32_AND);
+    c.get(5).get(3).op(I32_ADD).get(4).i32(3).op(I32_SHL).op(I32_SHL).op(I32_OR).set(0);
+    c.op(END);
+    c.get(4).i32(1).op(I32_ADD).tee(4).i32(3).op(I32_LT_U).br_if(0);
+    c.op(END).get(0);
+    functions.push(function(&[I32, I32, I32, I32], &[I32], &[I32, I32], &mut c));
+
+    // all_chars(word, alpha) -> 1 if the characters before the first NUL are
+    // all digits (or letters), and there is at least one
+    // Locals: 2 byte index, 3 byte
+    c.block(BLOCK, VOID).block(LOOP, VOID);
+    c.get(2).i32(3).op(I32_EQ).br_if(1);
+    c.byte_of(0, 2).tee(3).op(I32_EQZ).br_if(1);
+    c.get(1).block(IF, I32);
+    c.get(3).i32(0x20).op(I32_OR).i32(b'a' as i32).op(I32_SUB).i32(26).op(I32_LT_U);
+    c.op(ELSE).get(3).i32(b'0' as i32).op(I32_SUB).i32(10).op(I32_LT_U).op(END);
+    c.op(I32_EQZ).block(IF, VOID).i32(0).op(RETURN_OP).op(END);
+    c.get(2).i32(1).op(I32_ADD).set(2).br(0);
+    c.op(END).op(END).get(2).i32(0).op(I32_GT_U);
+    functions.push(function(&[I32, I32], &[I32], &[I32, I32], &mut c));
+
+    // vector(op): 0 add, 1 sub, 2 mul on two groups of four words. The lanes
+    // go through scratch memory, right then left, lane 0 first.
+    // Locals: 1 lane, 2 left, 3 right
+    c.block(LOOP, VOID);
+    c.i32(SCRATCH).get(1).i32(2).op(I32_SHL).op(I32_ADD).call(POP).memory(I32_STORE);
+    c.get(1).i32(1).op(I32_ADD).tee(1).i32(8).op(I32_LT_U).br_if(0).op(END);
+    c.i32(3).set(1).block(LOOP, VOID);
+    c.i32(SCRATCH + 16).get(1).i32(2).op(I32_SHL).op(I32_ADD).memory(I32_LOAD).set(2);
+    c.i32(SCRATCH).get(1).i32(2).op(I32_SHL).op(I32_ADD).memory(I32_LOAD).set(3);
+    c.get(0).op(I32_EQZ).block(IF, I32).get(2).get(3).op(I32_ADD).op(ELSE);
+    c.get(0).i32(1).op(I32_EQ).block(IF, I32).get(2).get(3).op(I32_SUB).op(ELSE);
+    c.get(2).get(3).op(I32_MUL).op(END).op(END).call(PUSH);
+    c.get(1).i32(1).op(I32_SUB).tee(1).i32(0).op(I32_GE_S).br_if(0).op(END);
+    functions.push(function(&[I32], &[], &[I32, I32, I32], &mut c));
+
+    // stprint(offset): copy the string at sp + offset to scratch, skipping
+    // continuation bytes, and print it
+    // Locals: 1 address, 2 length, 3 byte
+    c.global_get(SP).get(0).op(I32_ADD).set(1);
+    c.block(BLOCK, VOID).block(LOOP, VOID);
+    c.get(1).i32(RAM).op(I32_GE_U).br_if(1);
+    c.get(1).memory(I32_LOAD8_U).tee(3).op(I32_EQZ).br_if(1);
+    c.get(3).i32(1).op(I32_NE).block(IF, VOID);
+    c.i32(SCRATCH).get(2).op(I32_ADD).get(3).memory(I32_STORE8).get(2).i32(1).op(I32_ADD).set(2);
+    c.op(END).get(1).i32(1).op(I32_ADD).set(1).br(0);
+    c.op(END).op(END).i32(SCRATCH).get(2).call(PRINT_BYTES);
+    functions.push(function(&[I32], &[], &[I32, I32, I32], &mut c));
+
+    // dump(): one line per stack word, top first
+    c.global_get(SP).set(0).block(BLOCK, VOID).block(LOOP, VOID);
+    c.get(0).i32(RAM).op(I32_GE_U).br_if(1);
+    c.get(0).global_get(SP).op(I32_SUB).get(0).call(READ).call(PRINT_STACK_WORD);
+    c.get(0).i32(4).op(I32_ADD).set(0).br(0);
+    c.op(END).op(END);
+    functions.push(function(&[], &[], &[I32], &mut c));
+
+    // stinput(limit): push the line three characters to a word, with byte 3
+    // flagging that more follow, so the first chunk ends up on top
+    // Locals: 1 line length, 2 characters kept, 3 chunk, 4 word, 5 byte index, 6 character index
+    c.i32(SCRATCH).i32(SCRATCH).call(READ_LINE).tee(1).op(I32_EQZ).block(IF, VOID);
+    c.i32(0).call(PUSH).op(RETURN_OP).op(END);
+    c.get(1).get(0).get(1).get(0).op(I32_LT_U).op(SELECT).set(2);
+    c.get(2).i32(2).op(I32_ADD).i32(3).op(I32_DIV_U).set(3);
+    c.block(BLOCK, VOID).block(LOOP, VOID);
+    c.get(3).op(I32_EQZ).br_if(1);
+    c.get(3).i32(1).op(I32_SUB).set(3).i32(0).set(4).i32(0).set(5);
+    c.block(BLOCK, VOID).block(LOOP, VOID);
+    c.get(5).i32(3).op(I32_EQ).br_if(1);
+    c.get(3).i32(3).op(I32_MUL).get(5).op(I32_ADD).tee(6).get(2).op(I32_GE_U).br_if(1);
+    c.get(4).i32(SCRATCH).get(6).op(I32_ADD).memory(I32_LOAD8_U);
+    c.get(5).i32(3).op(I32_SHL).op(I32_SHL).op(I32_OR).set(4);
+    c.get(5).i32(1).op(I32_ADD).set(5).br(0);
+    c.op(END).op(END);
+    c.get(3).i32(3).op(I32_MUL).i32(3).op(I32_ADD).get(2).op(I32_LT_U).block(IF, VOID);
+    c.get(4).i32(0x0100_0000).op(I32_OR).set(4).op(END);
+    c.get(4).call(PUSH).br(0);
+    c.op(END).op(END);
+    functions.push(function(&[I32], &[], &[I32, I32, I32, I32, I32, I32], &mut c));
+
+    // shadow_call(return_addr), adding a page when the shadow stack needs one
+    // Locals: 1 entry address
+    c.shadow_entry(0).tee(1).i32(4).op(I32_ADD).op(MEMORY_SIZE).op(0x00).i32(16).op(I32_SHL).op(I32_GT_U);
+    c.block(IF, VOID).i32(1).op(MEMORY_GROW).op(0x00).i32(-1).op(I32_EQ).block(IF, VOID);
+    c.op(UNREACHABLE).op(END).op(END);
+    c.get(1).get(0).memory(I32_STORE).global_get(SHADOW_LEN).i32(1).op(I32_ADD).global_set(SHADOW_LEN);
+    functions.push(function(&[I32], &[], &[I32], &mut c));
+
+    // return(offset, separate) -> 1 if the PC moved: drop offset bytes, pop
+    // the return address and check it against the shadow stack; or with
+    // separate set, take it off the shadow stack, the only return stack
+    // Locals: 2 PC of the return, 3 new SP, 4 popped address, 5 expected address
+    c.global_get(PC).set(2);
+    c.get(0).block(IF, VOID);
+    c.global_get(SP).get(0).op(I32_ADD).tee(3).i32(0).op(I32_GE_S).get(3).i32(RAM).op(I32_LE_S).op(I32_AND);
+    c.block(IF, VOID).get(3).global_set(SP).op(END);
+    c.op(END);
+    c.get(1).block(IF, VOID);
+    c.global_get(SHADOW_LEN).op(I32_EQZ).block(IF, VOID).i32(0).op(RETURN_OP).op(END);
+    c.global_get(SHADOW_LEN).i32(1).op(I32_SUB).global_set(SHADOW_LEN);
+    c.shadow_entry(0).memory(I32_LOAD).tee(4).global_set(PC).get(4).get(2).op(I32_NE).op(RETURN_OP);
+    c.op(END);
+    c.global_get(SP).i32(RAM).op(I32_GE_S).block(IF, VOID).i32(0).op(RETURN_OP).op(END);
+    c.call(POP).set(4);
+    c.global_get(SHADOW_LEN).op(I32_EQZ).block(IF, VOID);
+    c.i32(FAULT_SHADOW_EMPTY).get(4).i32(0).call(RAISE).i32(0).op(RETURN_OP).op(END);
+    c.shadow_entry(1).memory(I32_LOAD).tee(5).get(4).op(I32_NE).block(IF, VOID);
+    c.i32(FAULT_SHADOW_MISMATCH).get(4).get(5).call(RAISE).i32(0).op(RETURN_OP).op(END);
+    c.global_get(SHADOW_LEN).i32(1).op(I32_SUB).global_set(SHADOW_LEN);
+    c.get(4).global_set(PC).get(4).get(2).op(I32_NE);
+    functions.push(function(&[I32, I32], &[I32], &[I32, I32, I32, I32], &mut c));
+
+    // alloc(size, start) -> address: first fit among freed heap blocks,
+    // splitting a bigger one, or else a new block on top below the stack
+    // Locals: 2 block address, 3 end of the heap, 4 table entry, 5 size in whole words
+    c.get(0).i32(RAM).op(I32_GT_U).block(IF, VOID);
+    c.i32(FAULT_OUT_OF_MEMORY).get(0).i32(0).call(RAISE).i32(0).op(RETURN_OP).op(END);
+    c.get(0).i32(3).op(I32_ADD).i32(-4).op(I32_AND).tee(5).op(I32_EQZ).block(IF, VOID).i32(4).set(5).op(END);
+    c.global_get(HEAP_TOP).tee(3).op(I32_EQZ).block(IF, VOID).get(1).set(3).op(END);
+    c.get(1).set(2);
+    c.block(BLOCK, VOID).block(LOOP, VOID);
+    c.get(2).get(3).op(I32_GE_U).br_if(1);
+    c.heap_entry(2).memory(I32_LOAD).tee(4).i32(1).op(I32_AND).op(I32_EQZ).get(4).get(5).op(I32_GE_U).op(I32_AND);
+    c.block(IF, VOID);
+    c.get(4).get(5).op(I32_GT_U).block(IF, VOID);
+    c.heap_entry(2).get(5).op(I32_ADD).get(4).get(5).op(I32_SUB).memory(I32_STORE).op(END);
+    c.heap_entry(2).get(5).i32(1).op(I32_OR).memory(I32_STORE).get(2).op(RETURN_OP);
+    c.op(END);
+    c.get(2).get(4).i32(-2).op(I32_AND).op(I32_ADD).set(2).br(0);
+    c.op(END).op(END);
+    c.get(2).get(5).op(I32_ADD).global_get(SP).op(I32_GT_U).block(IF, VOID);
+    c.i32(FAULT_OUT_OF_MEMORY).get(0).i32(0).call(RAISE).i32(0).op(RETURN_OP).op(END);
+    c.heap_entry(2).get(5).i32(1).op(I32_OR).memory(I32_STORE);
+    c.get(2).get(5).op(I32_ADD).global_set(HEAP_TOP).get(2);
+    functions.push(function(&[I32, I32], &[I32], &[I32, I32, I32, I32], &mut c));
+
+    // free(addr, start): merge the heap block at addr with free neighbours,
+    // giving a free block on top back to the stack; other addresses are ignored
+    // Locals: 2 block address, 3 the block before it or -1, 4 size, 5 table entry
+    c.get(0).op(I32_EQZ).block(IF, VOID).op(RETURN_OP).op(END);
+    c.get(1).set(2).i32(-1).set(3);
+    c.block(BLOCK, VOID).block(LOOP, VOID);
+    c.get(2).global_get(HEAP_TOP).op(I32_GE_U).get(2).get(0).op(I32_EQ).op(I32_OR).br_if(1);
+    c.get(2).set(3).get(2).heap_entry(2).memory(I32_LOAD).i32(-2).op(I32_AND).op(I32_ADD).set(2).br(0);
+    c.op(END).op(END);
+    c.get(2).global_get(HEAP_TOP).op(I32_GE_U).block(IF, VOID).op(RETURN_OP).op(END);
+    c.heap_entry(2).memory(I32_LOAD).tee(4).i32(1).op(I32_AND).op(I32_EQZ).block(IF, VOID).op(RETURN_OP).op(END);
+    c.get(4).i32(-2).op(I32_AND).set(4);
+    c.get(2).get(4).op(I32_ADD).tee(5).global_get(HEAP_TOP).op(I32_LT_U).block(IF, VOID);
+    c.heap_entry(5).memory(I32_LOAD).tee(5).i32(1).op(I32_AND).op(I32_EQZ).block(IF, VOID);
+    c.get(4).get(5).op(I32_ADD).set(4).op(END);
+    c.op(END);
+    c.get(3).i32(-1).op(I32_NE).block(IF, VOID);
+    c.heap_entry(3).memory(I32_LOAD).tee(5).i32(1).op(I32_AND).op(I32_EQZ).block(IF, VOID);
+    c.get(3).set(2).get(4).get(5).op(I32_ADD).set(4).op(END);
+    c.op(END);
+    c.get(2).get(4).op(I32_ADD).global_get(HEAP_TOP).op(I32_EQ).block(IF, VOID);
+    c.get(2).i32(0).get(2).get(1).op(I32_NE).op(SELECT).global_set(HEAP_TOP).op(RETURN_OP).op(END);
+    c.heap_entry(2).get(4).memory(I32_STORE);
+    functions.push(function(&[I32, I32], &[], &[I32, I32, I32, I32], &mut c));
+
+    // memcpy(dst, src, len), as if through a buffer: forwards when dst is
+    // below src, else backwards. Source bytes outside memory read as 0.
+    // Locals: 3 bytes of dst inside memory, 4 bytes of src inside, 5 index
+    c.global_get(FAULTED).block(IF, VOID).op(RETURN_OP).op(END);
+    c.bytes_inside(0, 2).set(3).bytes_inside(1, 2).set(4);
+    c.get(0).get(1).op(I32_LT_U).block(IF, VOID);
+    c.block(BLOCK, VOID).block(LOOP, VOID);
+    c.get(5).get(3).op(I32_GE_U).br_if(1);
+    c.get(0).get(5).op(I32_ADD).range_byte(1, 5, 4).memory(I32_STORE8);
+    c.get(5).i32(1).op(I32_ADD).set(5).br(0);
+    c.op(END).op(END);
+    c.op(ELSE).get(3).set(5);
+    c.block(BLOCK, VOID).block(LOOP, VOID);
+    c.get(5).op(I32_EQZ).br_if(1);
+    c.get(5).i32(1).op(I32_SUB).set(5);
+    c.get(0).get(5).op(I32_ADD).range_byte(1, 5, 4).memory(I32_STORE8).br(0);
+    c.op(END).op(END);
+    c.op(END);
+    functions.push(function(&[I32, I32, I32], &[], &[I32, I32, I32], &mut c));
+
+    // memset(dst, value, len)
+    // Locals: 3 bytes of dst inside memory, 4 index
+    c.global_get(FAULTED).block(IF, VOID).op(RETURN_OP).op(END);
+    c.bytes_inside(0, 2).set(3);
+    c.block(BLOCK, VOID).block(LOOP, VOID);
+    c.get(4).get(3).op(I32_GE_U).br_if(1);
+    c.get(0).get(4).op(I32_ADD).get(1).memory(I32_STORE8);
+    c.get(4).i32(1).op(I32_ADD).set(4).br(0);
+    c.op(END).op(END);
+    functions.push(function(&[I32, I32, I32], &[], &[I32, I32], &mut c));
+
+    // memcmp(a, b, len) -> -1, 0 or 1 at the first unsigned byte difference.
+    // Past both ranges' ends in memory the bytes are all 0.
+    // Locals: 3 bytes of a inside memory, 4 of b, 5 index, 6 byte of a, 7 byte of b
+    c.bytes_inside(0, 2).set(3).bytes_inside(1, 2).set(4);
+    c.block(BLOCK, VOID).block(LOOP, VOID);
+    c.get(5).get(3).get(4).get(3).get(4).op(I32_GT_U).op(SELECT).op(I32_GE_U).br_if(1);
+    c.range_byte(0, 5, 3).tee(6).range_byte(1, 5, 4).tee(7).op(I32_NE).block(IF, VOID);
+    c.i32(-1).i32(1).get(6).get(7).op(I32_LT_U).op(SELECT).op(RETURN_OP).op(END);
+    c.get(5).i32(1).op(I32_ADD).set(5).br(0);
+    c.op(END).op(END);
+    c.i32(0);
+    functions.push(function(&[I32, I32, I32], &[I32], &[I32, I32, I32, I32, I32], &mut c));
+
+    // wide(op): 0 add, 1 sub, 2 mul, 3 signed compare to -1, 0 or 1 on two
+    // 64-bit values, each two words with the low one on top
+    // Locals: 1 right, 2 left
+    c.call(POP).op(I64_EXTEND_I32_U).call(POP).op(I64_EXTEND_I32_U).i64(32).op(I64_SHL).op(I64_OR).set(1);
+    c.call(POP).op(I64_EXTEND_I32_U).call(POP).op(I64_EXTEND_I32_U).i64(32).op(I64_SHL).op(I64_OR).set(2);
+    c.get(0).i32(3).op(I32_EQ).block(IF, VOID);
+    c.get(2).get(1).op(I64_GT_S).get(2).get(1).op(I64_LT_S).op(I32_SUB).call(PUSH).op(RETURN_OP).op(END);
+    c.get(0).op(I32_EQZ).block(IF, I64).get(2).get(1).op(I64_ADD).op(ELSE);
+    c.get(0).i32(1).op(I32_EQ).block(IF, I64).get(2).get(1).op(I64_SUB).op(ELSE);
+    c.get(2).get(1).op(I64_MUL).op(END).op(END).tee(1);
+    c.i64(32).op(I64_SHR_U).op(I32_WRAP_I64).call(PUSH).get(1).op(I32_WRAP_I64).call(PUSH);
+    functions.push(function(&[I32], &[], &[I64, I64], &mut c));
+
+    // compare(l, r): set the flags from l - r
+    // Locals: 2 difference
+    c.get(0).get(1).op(I32_SUB).tee(2).i32(31).op(I32_SHR_U).i32(3).op(I32_SHL);
+    c.get(2).op(I32_EQZ).i32(2).op(I32_SHL).op(I32_OR);
+    c.get(0).get(1).op(I32_GE_U).i32(1).op(I32_SHL).op(I32_OR);
+    c.get(0).get(1).op(I32_XOR).get(0).get(2).op(I32_XOR).op(I32_AND).i32(31).op(I32_SHR_U).op(I32_OR);
+    c.global_set(FLAGS);
+    functions.push(function(&[I32, I32], &[], &[I32], &mut c));
+
+    // condition(code) -> whether a flag branch with that condition code goes.
+    // The even codes' tests, Z C N V (C && !Z) (N == V) (!Z && N == V), are
+    // bits 0 to 6 of a mask; the odd codes are their opposites.
+    // Locals: 1 N, 2 Z, 3 C, 4 V, 5 N == V
+    for (local, shift) in [(1, 3), (2, 2), (3, 1), (4, 0)] {
+        c.global_get(FLAGS).i32(shift).op(I32_SHR_U).i32(1).op(I32_AND).set(local);
+    }
+    c.get(1).get(4).op(I32_XOR).i32(1).op(I32_XOR).set(5);
+    c.get(2).get(3).i32(1).op(I32_SHL).op(I32_OR).get(1).i32(2).op(I32_SHL).op(I32_OR).get(4).i32(3).op(I32_SHL).op(I32_OR);
+    c.get(3).get(2).i32(1).op(I32_XOR).op(I32_AND).i32(4).op(I32_SHL).op(I32_OR);
+    c.get(5).i32(5).op(I32_SHL).op(I32_OR);
+    c.get(2).i32(1).op(I32_XOR).get(5).op(I32_AND).i32(6).op(I32_SHL).op(I32_OR);
+    c.get(0).i32(1).op(I32_SHR_U).op(I32_SHR_U).i32(1).op(I32_AND).get(0).i32(1).op(I32_AND).op(I32_XOR);
+    functions.push(function(&[I32], &[I32], &[I32, I32, I32, I32, I32], &mut c));
+
+    // checked(result) -> a result computed in 64 bits as a word, raising an
+    // overflow fault if it doesn't fit while trapv is on
+    c.global_get(TRAP_OVERFLOW).get(0).get(0).op(I32_WRAP_I64).op(I64_EXTEND_I32_S).op(I64_NE).op(I32_AND);
+    c.block(IF, VOID).i32(FAULT_OVERFLOW).i32(0).i32(0).call(RAISE).op(END);
+    c.get(0).op(I32_WRAP_I64);
+    functions.push(function(&[I64], &[I32], &[], &mut c));
+
+    functions
+}
+
+fn unsigned(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn signed(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// Length-prefixed bytes
+fn vector(out: &mut Vec<u8>, bytes: &[u8]) {
+    unsigned(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+// A section of `count` entries written by `entries`
+fn section(module: &mut Vec<u8>, id: u8, count: usize, entries: impl FnOnce(&mut Vec<u8>)) {
+    let mut content = Vec::new();
+    unsigned(&mut content, count as u32);
+    entries(&mut content);
+    module.push(id);
+    vector(module, &content);
+}
@@ -0,0 +1,359 @@
+// zlib streams (RFC 1950) around DEFLATE data (RFC 1951), for compressed
+// program files. Decompression handles all three block types, so streams
+// from zlib itself or any other tool load. Compression is simple: greedy
+// LZ77 matches over the 32K window, written as one block of the fixed
+// Huffman codes. Programs are small and repetitive enough that this gets
+// most of what a full encoder would.
+
+// Base value and number of extra bits for length codes 257..=285
+const LENGTH_BASE: [u16; 29] =
+    [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+// The same for distance codes 0..=29
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+// Order the code length code lengths are stored in a dynamic block
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const WINDOW: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 128; // Earlier positions tried per match before settling
+const HASH_BITS: u32 = 15;
+
+// The bytes a zlib stream holds, refusing to produce more than limit
+pub(crate) fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let [cmf, flg, ..] = *data else {
+        return Err("truncated".to_string());
+    };
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 || (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+        return Err("not a zlib stream".to_string());
+    }
+    if flg & 0x20 != 0 {
+        return Err("preset dictionaries aren't supported".to_string());
+    }
+    let mut bits = Bits { bytes: &data[2..], next: 0, buffer: 0, count: 0 };
+    let out = inflate(&mut bits, limit)?;
+    let checksum = bits.bytes.get(bits.next..bits.next + 4).ok_or("truncated")?;
+    if u32::from_be_bytes(checksum.try_into().unwrap()) != adler32(&out) {
+        return Err("checksum mismatch".to_string());
+    }
+    Ok(out)
+}
+
+// data as a zlib stream
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter { out: vec![0x78, 0x9c], buffer: 0, count: 0 };
+    writer.put(1, 1); // Last block
+    writer.put(1, 2); // Fixed Huffman codes
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+    let mut pos = 0;
+    while pos < data.len() {
+        let (len, distance) = longest_match(data, pos, &head, &prev);
+        let end = if len >= MIN_MATCH { pos + len } else { pos + 1 };
+        if len >= MIN_MATCH {
+            let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= len).unwrap();
+            writer.literal_length(257 + code as u16);
+            writer.put((len - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code].into());
+            let code = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+            writer.put_code(code as u32, 5);
+            writer.put((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code].into());
+        } else {
+            writer.literal_length(data[pos].into());
+        }
+        // Every position passed over goes into the chains, for later matches
+        while pos < end {
+            if pos + MIN_MATCH <= data.len() {
+                let hash = hash(&data[pos..]);
+                prev[pos] = head[hash];
+                head[hash] = pos;
+            }
+            pos += 1;
+        }
+    }
+    writer.literal_length(256);
+    let mut out = writer.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+    (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+// The longest earlier run of bytes matching those at pos, as its length
+// and distance back; a length under MIN_MATCH means none
+fn longest_match(data: &[u8], pos: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    let mut best = (0, 0);
+    if pos + MIN_MATCH > data.len() {
+        return best;
+    }
+    let max = (data.len() - pos).min(MAX_MATCH);
+    let mut candidate = head[hash(&data[pos..])];
+    for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || pos - candidate > WINDOW {
+            break;
+        }
+        let len = data[candidate..].iter().zip(&data[pos..pos + max]).take_while(|(a, b)| a == b).count();
+        if len > best.0 {
+            best = (len, pos - candidate);
+            if len == max {
+                break;
+            }
+        }
+        candidate = prev[candidate];
+    }
+    best
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+// Reads bits from the front of bytes, least significant first
+struct Bits<'a> {
+    bytes: &'a [u8],
+    next: usize, // Index of the next byte to read
+    buffer: u32,
+    count: u32, // Bits in buffer, always fewer than 8 between reads
+}
+
+impl Bits<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        while self.count < n {
+            let byte = *self.bytes.get(self.next).ok_or("truncated")?;
+            self.next += 1;
+            self.buffer |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    // Skip to the next byte boundary
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+// A canonical Huffman code, as the number of codes of each length and the
+// symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    // The code with lengths[symbol] bits for each symbol (0 for unused)
+    fn new(lengths: &[u8]) -> Result<Huffman, String> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err("invalid Huffman code".to_string());
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate().filter(|(_, &len)| len != 0) {
+            symbols[offsets[len as usize] as usize] = symbol as u16;
+            offsets[len as usize] += 1;
+        }
+        counts[0] = 0;
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = i32::from(count);
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+fn inflate(bits: &mut Bits, limit: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = bits.bytes.get(bits.next..bits.next + 4).ok_or("truncated")?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err("stored block length mismatch".to_string());
+                }
+                let start = bits.next + 4;
+                let stored = bits.bytes.get(start..start + len as usize).ok_or("truncated")?;
+                if out.len() + stored.len() > limit {
+                    return Err(too_large(limit));
+                }
+                out.extend_from_slice(stored);
+                bits.next = start + len as usize;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let literal = Huffman::new(&lengths)?;
+                let distance = Huffman::new(&[5; 30])?;
+                codes(bits, &literal, &distance, &mut out, limit)?;
+            }
+            2 => {
+                let (literal, distance) = dynamic_codes(bits)?;
+                codes(bits, &literal, &distance, &mut out, limit)?;
+            }
+            _ => return Err("invalid block type".to_string()),
+        }
+        if last {
+            break;
+        }
+    }
+    bits.align();
+    Ok(out)
+}
+
+// The literal/length and distance codes at the start of a dynamic block
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+    if literals > 286 || distances > 30 {
+        return Err("too many codes".to_string());
+    }
+    let mut lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[symbol] = bits.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&lengths)?;
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (value, repeat) = match code_length_code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or("repeat with no previous length")?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        if lengths.len() + repeat as usize > literals + distances {
+            return Err("too many code lengths".to_string());
+        }
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths[256] == 0 {
+        return Err("no end-of-block code".to_string());
+    }
+    Ok((Huffman::new(&lengths[..literals])?, Huffman::new(&lengths[literals..])?))
+}
+
+// Decode a block's literals and matches into out, up to its end code
+fn codes(bits: &mut Bits, literal: &Huffman, distance: &Huffman, out: &mut Vec<u8>, limit: usize) -> Result<(), String> {
+    loop {
+        let symbol = literal.decode(bits)? as usize;
+        if out.len() >= limit && symbol != 256 {
+            return Err(too_large(limit));
+        }
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let code = symbol - 257;
+                let base = *LENGTH_BASE.get(code).ok_or("invalid length code")?;
+                let len = base as usize + bits.bits(LENGTH_EXTRA[code].into())? as usize;
+                let code = distance.decode(bits)? as usize;
+                let base = *DISTANCE_BASE.get(code).ok_or("invalid distance code")?;
+                let back = base as usize + bits.bits(DISTANCE_EXTRA[code].into())? as usize;
+                if back > out.len() {
+                    return Err("distance reaches before the start".to_string());
+                }
+                if out.len() + len > limit {
+                    return Err(too_large(limit));
+                }
+                for _ in 0..len {
+                    out.push(out[out.len() - back]);
+                }
+            }
+        }
+    }
+}
+
+fn too_large(limit: usize) -> String {
+    format!("expands to more than {} bytes", limit)
+}
+
+// Writes bits least significant first
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, n: u32) {
+        self.buffer |= u64::from(value) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes go most significant bit first
+    fn put_code(&mut self, code: u32, len: u32) {
+        self.put(code.reverse_bits() >> (32 - len), len);
+    }
+
+    // A literal/length symbol in the fixed code
+    fn literal_length(&mut self, symbol: u16) {
+        let symbol = u32::from(symbol);
+        match symbol {
+            0..=143 => self.put_code(0x30 + symbol, 8),
+            144..=255 => self.put_code(0x190 + symbol - 144, 9),
+            256..=279 => self.put_code(symbol - 256, 7),
+            _ => self.put_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
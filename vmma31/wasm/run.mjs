@@ -0,0 +1,17 @@
+// Run a translated program under Node with the terminal's stdin and stdout:
+//
+//   node wasm/run.mjs program.wasm < input.txt
+
+import { readFileSync } from "node:fs";
+import { runProgram } from "./runtime.mjs";
+
+const [path] = process.argv.slice(2);
+if (!path) {
+  console.error("Usage: node run.mjs <wasm_file>");
+  process.exit(1);
+}
+const input = readFileSync(0, "utf8").split("\n");
+const { exitCode } = await runProgram(readFileSync(path), input, {
+  write: (text) => process.stdout.write(text),
+});
+process.exitCode = exitCode & 0xff;
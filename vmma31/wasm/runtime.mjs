@@ -0,0 +1,134 @@
+// Host side of modules made by `vmma31 to-wasm`, for browsers and Node.
+// Input is given up front as lines, since a module can't wait for the user
+// in the middle of run():
+//
+//   const bytes = await (await fetch("program.wasm")).arrayBuffer();
+//   const { exitCode, output } = await runProgram(bytes, ["5", "7"]);
+//
+// Output goes to `write` when given, and is collected in `output`
+// otherwise. Fault messages go to `error` (default console.error) in the
+// form the vmma31 CLI prints them.
+
+const FAULT_EXIT_CODE = 134;
+
+function hex(value) {
+  return (value >>> 0).toString(16);
+}
+
+// An optional sign and digits in the radix, as Rust's from_str_radix
+// accepts them; anything else, or a value that doesn't fit a word, is 0
+function parseWord(text, radix) {
+  const match = /^([+-]?)([0-9a-zA-Z]+)$/.exec(text);
+  if (!match) {
+    return 0;
+  }
+  let value = 0;
+  for (const digit of match[2]) {
+    const d = parseInt(digit, 36);
+    if (d >= radix) {
+      return 0;
+    }
+    value = value * radix + d;
+    if (value > 2 ** 31) {
+      return 0;
+    }
+  }
+  value = match[1] === "-" ? -value : value;
+  return value === 2 ** 31 ? 0 : value;
+}
+
+// The value the input instruction pushes for a line
+function parseInput(line) {
+  const text = line.trim();
+  if (/^0[xX]/.test(text)) {
+    return parseWord(text.slice(2), 16);
+  }
+  if (/^0[bB]/.test(text)) {
+    return parseWord(text.slice(2), 2);
+  }
+  return parseWord(text, 10);
+}
+
+function faultMessage(kind, pc, a, b) {
+  const at = ` at pc 0x${hex(pc)}`;
+  switch (kind) {
+    case 0:
+      return `invalid instruction 0x${hex(a).padStart(8, "0")}${at}`;
+    case 1:
+      return `stack underflow${at}`;
+    case 2:
+      return `stack overflow${at}`;
+    case 3:
+      return `divide by zero${at}`;
+    case 4:
+      return `shadow stack mismatch (popped 0x${hex(a)}, expected 0x${hex(b)})${at}`;
+    case 6:
+      return `out of memory allocating ${a >>> 0} bytes${at}`;
+    case 7:
+      return `arithmetic overflow${at}`;
+    default:
+      return `shadow stack mismatch (no matching call)${at}`;
+  }
+}
+
+function formatWord(value, format) {
+  let prefix = "";
+  let digits;
+  switch (format & 7) {
+    case 1:
+      [prefix, digits] = ["0x", hex(value)];
+      break;
+    case 2:
+      [prefix, digits] = ["0b", (value >>> 0).toString(2)];
+      break;
+    case 3:
+      [prefix, digits] = ["0o", (value >>> 0).toString(8)];
+      break;
+    case 4:
+      digits = String.fromCharCode(value & 0xff);
+      break;
+    default:
+      [prefix, digits] = [value < 0 ? "-" : "", String(Math.abs(value))];
+  }
+  const width = (format >> 5) - prefix.length - digits.length;
+  if (format & 0x10) {
+    return prefix + "0".repeat(Math.max(width, 0)) + digits;
+  }
+  return " ".repeat(Math.max(width, 0)) + prefix + digits;
+}
+
+export async function runProgram(bytes, input = [], { write, error = console.error } = {}) {
+  const lines = [...input];
+  let output = "";
+  const emit = write ?? ((text) => (output += text));
+  let memory;
+  const encoder = new TextEncoder();
+
+  const env = {
+    input: () => parseInput(lines.shift() ?? ""),
+    read_line(ptr, max) {
+      const text = encoder.encode((lines.shift() ?? "").trim());
+      new Uint8Array(memory.buffer, ptr, max).set(text.subarray(0, max));
+      return text.length;
+    },
+    // Format bits as printf: 2:0 the kind, 3 no newline, 4 zero padding, 11:5 the width
+    print_word(value, format) {
+      const text = formatWord(value, format);
+      emit(format & 8 ? text : text + "\n");
+    },
+    print_bytes(ptr, len) {
+      emit(String.fromCharCode(...new Uint8Array(memory.buffer, ptr, len)));
+    },
+    print_stack_word(offset, value) {
+      emit(`${hex(offset).padStart(4, "0")}: ${hex(value).padStart(8, "0")}\n`);
+    },
+    fault(kind, pc, a, b) {
+      error(`Error: ${faultMessage(kind, pc, a, b)}`);
+    },
+  };
+
+  const { instance } = await WebAssembly.instantiate(bytes, { env });
+  memory = instance.exports.memory;
+  const exitCode = instance.exports.run();
+  return { exitCode, output, faulted: exitCode === FAULT_EXIT_CODE };
+}